@@ -4,7 +4,11 @@ use lazy_static::*;
 use pest::prec_climber::{Assoc, Operator, PrecClimber};
 use pest_consume::{match_nodes, Error, Parser};
 type ParseResult<T> = std::result::Result<T, Error<Rule>>;
-type Node<'i> = pest_consume::Node<'i, Rule, ()>;
+type Node<'i> = pest_consume::Node<'i, Rule, Scope>;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
 
 use super::super::structures;
 
@@ -22,6 +26,245 @@ lazy_static::lazy_static! {
             // tighest binding
         ]
     );
+    static ref EXPRESSION_CLIMBER: PrecClimber<Rule> = PrecClimber::new(
+        vec![
+            // loosest binding
+            Operator::new(Rule::ast_expr_xor, Assoc::Left),
+            Operator::new(Rule::ast_expr_and, Assoc::Left),
+            Operator::new(Rule::ast_expr_lshift, Assoc::Left) | Operator::new(Rule::ast_expr_rshift, Assoc::Left),
+            Operator::new(Rule::ast_expr_add, Assoc::Left) | Operator::new(Rule::ast_expr_sub, Assoc::Left),
+            Operator::new(Rule::ast_expr_mul, Assoc::Left) | Operator::new(Rule::ast_expr_div, Assoc::Left) | Operator::new(Rule::ast_expr_mod, Assoc::Left),
+            // tighest binding
+        ]
+    );
+}
+
+/// An address expression lowered while parsing. Expressions using `INPUT`
+/// become the sequence of steps applied to it, everything else is folded
+/// into a constant
+enum Expression {
+    Input(Vec<structures::TerminalRoutingProgram>),
+    Constant(u64),
+}
+
+impl Expression {
+    fn into_steps(self) -> Vec<structures::TerminalRoutingProgram> {
+        match self {
+            Expression::Input(steps) if steps.is_empty() => {
+                vec![structures::TerminalRoutingProgram::Noop]
+            }
+            Expression::Input(steps) => steps,
+            Expression::Constant(c) => vec![structures::TerminalRoutingProgram::Constant(c)],
+        }
+    }
+}
+
+/// What is in scope while parsing, shared by every node of a parse
+#[derive(Default)]
+struct Names {
+    /// the values of the parameters and constants
+    constants: HashMap<String, u64>,
+    /// whether `?` may leave parts of the memory to the solver, as it may in
+    /// sketches
+    holes: bool,
+    /// whether layouts past the memory are left for `check` to report
+    /// along with its other errors
+    checking: bool,
+}
+
+type Scope = Rc<RefCell<Names>>;
+
+/// the number standing in for a hole until the solver fills it in. It is
+/// non-zero so that `INPUT / ?num` parses
+const HOLE: u64 = 1;
+
+/// refuses holes outside of sketches
+fn allow_hole(node: &Node) -> ParseResult<()> {
+    if node.user_data().borrow().holes {
+        Ok(())
+    } else {
+        Err(node.error("`?` may only leave holes in sketches"))
+    }
+}
+
+/// the first hole within the node
+fn find_hole<'i>(node: &Node<'i>) -> Option<pest::iterators::Pair<'i, Rule>> {
+    node.as_pair()
+        .clone()
+        .into_inner()
+        .flatten()
+        .find(|x| matches!(x.as_rule(), Rule::hole | Rule::num_hole))
+}
+
+fn has_hole(node: &Node) -> bool {
+    find_hole(node).is_some()
+}
+
+/// brings a named value into scope, refusing to shadow an existing one
+fn define(node: &Node, name: String, value: u64) -> ParseResult<()> {
+    let constants = &mut node.user_data().borrow_mut().constants;
+    if constants.contains_key(&name) {
+        return Err(node.error(format!("`{}` is already defined", name)));
+    }
+    constants.insert(name, value);
+    Ok(())
+}
+
+/// the banks of a `for` block, each paired with the value of the loop
+/// variable it is built with
+fn expand_bank_group(node: Node) -> ParseResult<(String, Vec<(u64, Node)>)> {
+    let mut children = node.children();
+    let variable = AstParser::ident(children.next().unwrap())?;
+    let start = AstParser::ast_value(children.next().unwrap())?;
+    let finish = AstParser::ast_value(children.next().unwrap())?;
+    let banks = children.collect::<Vec<_>>();
+    let expanded = (start..finish)
+        .flat_map(|value| banks.iter().map(move |bank| (value, bank.clone())))
+        .collect();
+    Ok((variable, expanded))
+}
+
+/// a loop variable and its value
+type Binding = (String, u64);
+
+/// the bank nodes of a description in order, with the loop variable each is
+/// built with
+fn bank_nodes<'i>(component: &Node<'i>) -> ParseResult<Vec<(Option<Binding>, Node<'i>)>> {
+    let mut banks = vec![];
+    for node in component.children() {
+        match node.as_rule() {
+            Rule::bank => banks.push((None, node)),
+            Rule::bank_group => {
+                let (variable, expanded) = expand_bank_group(node)?;
+                for (value, bank) in expanded {
+                    banks.push((Some((variable.clone(), value)), bank));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(banks)
+}
+
+fn semantic_error(span: pest::Span, message: String) -> Error<Rule> {
+    Error::new_from_span(pest::error::ErrorVariant::CustomError { message }, span)
+}
+
+/// reports layouts holding addresses past the end of the memory
+fn check_layout(node: Node, size: usize, errors: &mut Vec<Error<Rule>>) -> ParseResult<()> {
+    let span = node.as_span();
+    let layout = AstParser::layout_ast(node)?;
+    let exceeds = format!(
+        "layout {} exceeds the memory size {}",
+        layout.pretty_print(),
+        size
+    );
+    let error = match &layout {
+        structures::MemoryLayout::Range { finish, .. } => (*finish > size).then_some(exceeds),
+        structures::MemoryLayout::Set(addresses) => {
+            addresses.iter().any(|x| *x >= size).then_some(exceeds)
+        }
+        // cyclic layouts end with the memory, so only their first address
+        // can lie past it
+        structures::MemoryLayout::Cyclic { offset, .. } => (*offset >= size).then_some(exceeds),
+        structures::MemoryLayout::BlockCyclic { block, offset, .. } => {
+            if size % block != 0 {
+                Some(format!(
+                    "the block size {} does not divide the memory size {}",
+                    block, size
+                ))
+            } else {
+                (offset * block >= size).then_some(exceeds)
+            }
+        }
+    };
+    if let Some(message) = error {
+        errors.push(semantic_error(span, message));
+    }
+    Ok(())
+}
+
+/// reports switch cases which are unreachable or overlap earlier cases for
+/// some address of the bank. The default case only takes the addresses left
+/// over, so it is only unreachable when the cases take all of them
+fn check_switch(node: Node, addresses: &[usize], errors: &mut Vec<Error<Rule>>) -> ParseResult<()> {
+    let mut covered = vec![false; addresses.len()];
+    for case in node.children() {
+        let span = case.as_span();
+        let matches = if case.as_rule() == Rule::ast_translation_switch_case {
+            let (condition, _) = AstParser::ast_translation_switch_case(case)?;
+            (0..addresses.len())
+                .filter(|x| condition.eval(addresses[*x] as u64))
+                .collect::<Vec<_>>()
+        } else {
+            (0..addresses.len()).filter(|x| !covered[*x]).collect()
+        };
+
+        if matches.iter().all(|x| covered[*x]) {
+            errors.push(semantic_error(span, "this case is unreachable".to_string()));
+        } else if let Some(x) = matches.iter().find(|x| covered[**x]) {
+            errors.push(semantic_error(
+                span,
+                format!(
+                    "this case overlaps an earlier case, e.g. for address {}",
+                    addresses[*x]
+                ),
+            ));
+        }
+        for x in matches {
+            covered[x] = true;
+        }
+    }
+    Ok(())
+}
+
+/// reports translations of the bank's addresses which underflow or land past
+/// the end of the bank
+fn check_translation(
+    node: Node,
+    routing: &structures::TopLevelRoutingProgram,
+    bank: &structures::MemoryBank,
+    errors: &mut Vec<Error<Rule>>,
+) -> ParseResult<()> {
+    let span = node.as_span();
+    let addresses = bank.layout().addresses();
+    for child in node.children() {
+        if child.as_rule() == Rule::ast_translation_switch {
+            check_switch(child, &addresses, errors)?;
+        }
+    }
+
+    for address in addresses {
+        match routing.checked_eval(address as u64) {
+            None => {
+                errors.push(semantic_error(
+                    span,
+                    format!("translation of address {} underflows or overflows", address),
+                ));
+                break;
+            }
+            Some(index) if index >= bank.size() as u64 => {
+                errors.push(semantic_error(
+                    span,
+                    format!(
+                        "translation maps address {} to index {}, past the end of the bank of size {}",
+                        address,
+                        index,
+                        bank.size()
+                    ),
+                ));
+                break;
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// The optional trailing entries of a bank block
+enum BankAttribute {
+    Latency(u64),
+    Storage(usize),
 }
 
 #[derive(Parser)]
@@ -65,11 +308,28 @@ impl AstParser {
             .map_err(|_| input.error("Expected non-negative number"))
     }
 
+    fn ident(input: Node) -> ParseResult<String> {
+        Ok(input.as_str().to_string())
+    }
+
+    fn ast_value(input: Node) -> ParseResult<u64> {
+        let scope = input.user_data().clone();
+        match_nodes!(input.children();
+            [num(n)] => Ok(n),
+            [num_hole(_)] => allow_hole(&input).map(|_| HOLE),
+            [ident(name)] => match scope.borrow().constants.get(&name) {
+                Some(value) => Ok(*value),
+                None => Err(input.error(format!("unknown constant `{}`", name))),
+            }
+        )
+    }
+
     fn hex_num(input: Node) -> ParseResult<u64> {
         let string = input.as_str();
-        // drop the hex literal prefix
+        let radix = if string.starts_with("#b") { 2 } else { 16 };
+        // drop the literal prefix
         let string = string.chars().skip(2).collect::<String>();
-        Ok(u64::from_str_radix(&string, 16).expect("Expected non-negative number"))
+        u64::from_str_radix(&string, radix).map_err(|_| input.error("Expected non-negative number"))
     }
 
     fn comparison_operator(input: Node) -> ParseResult<structures::ComparisonOperator> {
@@ -85,13 +345,13 @@ impl AstParser {
 
     fn ast_p_v_comp(input: Node) -> ParseResult<structures::Condition> {
         Ok(match_nodes!(input.into_children();
-                [comparison_operator(c), num(n)] => structures::Condition::ComparisonPortVal(n, c)
+                [comparison_operator(c), ast_value(n)] => structures::Condition::ComparisonPortVal(n, c)
         ))
     }
 
     fn ast_v_p_comp(input: Node) -> ParseResult<structures::Condition> {
         Ok(match_nodes!(input.into_children();
-            [num(n), comparison_operator(c)] => structures::Condition::ComparisonValPort(n, c)
+            [ast_value(n), comparison_operator(c)] => structures::Condition::ComparisonValPort(n, c)
         ))
     }
 
@@ -138,60 +398,68 @@ impl AstParser {
     fn ast_noop(_input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
         Ok(structures::TerminalRoutingProgram::Noop)
     }
+    fn hole(input: Node) -> ParseResult<()> {
+        allow_hole(&input)
+    }
+    fn num_hole(_input: Node) -> ParseResult<()> {
+        Ok(())
+    }
 
     fn z3_rshift(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
         Ok(match_nodes!(input.into_children();
             [hex_num(n)] => structures::TerminalRoutingProgram::RShift(n as usize)
         ))
     }
-    fn ast_rshift(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
+    fn z3_add(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
         Ok(match_nodes!(input.into_children();
-            [num(n)] => structures::TerminalRoutingProgram::RShift(n as usize)
+            [hex_num(n)] => structures::TerminalRoutingProgram::Add(n)
         ))
     }
-
-    fn z3_add(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
+    fn z3_subpv(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
         Ok(match_nodes!(input.into_children();
-            [hex_num(n)] => structures::TerminalRoutingProgram::Add(n)
+            [hex_num(n)] => structures::TerminalRoutingProgram::SubPortVal(n)
+        ))
+    }
+    fn z3_subvp(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
+        Ok(match_nodes!(input.into_children();
+            [hex_num(n)] => structures::TerminalRoutingProgram::SubValPort(n)
         ))
     }
-    fn ast_add(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
+    fn z3_constant(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
         Ok(match_nodes!(input.into_children();
-            [num(n)] => structures::TerminalRoutingProgram::Add(n)
+            [hex_num(n)] => structures::TerminalRoutingProgram::Constant(n)
         ))
     }
-    fn z3_subpv(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
+    fn z3_lshift(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
         Ok(match_nodes!(input.into_children();
-            [hex_num(n)] => structures::TerminalRoutingProgram::SubPortVal(n)
+            [hex_num(n)] => structures::TerminalRoutingProgram::LShift(n as usize)
         ))
     }
-    fn ast_subpv(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
+    fn z3_bitand(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
         Ok(match_nodes!(input.into_children();
-            [num(n)] => structures::TerminalRoutingProgram::SubPortVal(n)
+            [hex_num(n)] => structures::TerminalRoutingProgram::And(n)
         ))
     }
-
-    fn z3_subvp(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
+    fn z3_xor(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
         Ok(match_nodes!(input.into_children();
-            [hex_num(n)] => structures::TerminalRoutingProgram::SubValPort(n)
+            [hex_num(n)] => structures::TerminalRoutingProgram::Xor(n)
         ))
     }
-    fn ast_subvp(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
+    fn z3_mod(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
         Ok(match_nodes!(input.into_children();
-            [num(n)] => structures::TerminalRoutingProgram::SubValPort(n)
+            [hex_num(n)] => structures::TerminalRoutingProgram::Mod(n)
         ))
     }
-    fn z3_constant(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
+    fn z3_div(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
         Ok(match_nodes!(input.into_children();
-            [hex_num(n)] => structures::TerminalRoutingProgram::Constant(n)
+            [hex_num(n)] => structures::TerminalRoutingProgram::Div(n)
         ))
     }
-    fn ast_constant(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
+    fn z3_mul(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
         Ok(match_nodes!(input.into_children();
-            [num(n)] => structures::TerminalRoutingProgram::Constant(n)
+            [hex_num(n)] => structures::TerminalRoutingProgram::Mul(n)
         ))
     }
-
     fn z3_address_translation(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
         Ok(match_nodes!(input.into_children();
             [z3_constant(z)] => z,
@@ -199,46 +467,233 @@ impl AstParser {
             [z3_subpv(z)] => z,
             [z3_add(z)] => z,
             [z3_rshift(z)] => z,
+            [z3_lshift(z)] => z,
+            [z3_bitand(z)] => z,
+            [z3_xor(z)] => z,
+            [z3_mod(z)] => z,
+            [z3_div(z)] => z,
+            [z3_mul(z)] => z,
             [z3_noop(z)] => z
         ))
     }
-    fn ast_translation_terminal(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
+    fn ast_input(_input: Node) -> ParseResult<Expression> {
+        Ok(Expression::Input(vec![]))
+    }
+    fn ast_expression_atom(input: Node) -> ParseResult<Expression> {
         Ok(match_nodes!(input.into_children();
-            [ast_constant(a)] => a,
-            [ast_subvp(a)] => a,
-            [ast_subpv(a)] => a,
-            [ast_add(a)] => a,
-            [ast_rshift(a)] => a,
-            [ast_noop(a)] => a
+            [ast_input(e)] => e,
+            [ast_value(n)] => Expression::Constant(n),
+            [ast_expression(e)] => e
         ))
     }
-    fn range_z3(input: Node) -> ParseResult<structures::MemoryLayout> {
+
+    #[prec_climb(ast_expression_atom, EXPRESSION_CLIMBER)]
+    fn ast_expression(left: Expression, op: Node, right: Expression) -> ParseResult<Expression> {
+        use structures::TerminalRoutingProgram as T;
+        let error = |message: &str| op.error(message);
+
+        if let (Expression::Constant(l), Expression::Constant(r)) = (&left, &right) {
+            let (l, r) = (*l, *r);
+            let folded = match op.as_rule() {
+                Rule::ast_expr_add => l.checked_add(r),
+                Rule::ast_expr_sub => l.checked_sub(r),
+                Rule::ast_expr_mul => l.checked_mul(r),
+                Rule::ast_expr_div => l.checked_div(r),
+                Rule::ast_expr_mod => l.checked_rem(r),
+                Rule::ast_expr_lshift => u32::try_from(r).ok().and_then(|r| l.checked_shl(r)),
+                Rule::ast_expr_rshift => u32::try_from(r).ok().and_then(|r| l.checked_shr(r)),
+                Rule::ast_expr_and => Some(l & r),
+                Rule::ast_expr_xor => Some(l ^ r),
+                _ => unreachable!(),
+            };
+            return folded
+                .map(Expression::Constant)
+                .ok_or_else(|| error("constant expression is out of range"));
+        }
+
+        let (mut steps, value, input_on_left) = match (left, right) {
+            (Expression::Input(steps), Expression::Constant(value)) => (steps, value, true),
+            (Expression::Constant(value), Expression::Input(steps)) => (steps, value, false),
+            _ => return Err(error("INPUT may only appear once in a translation")),
+        };
+        let step = match (op.as_rule(), input_on_left) {
+            (Rule::ast_expr_add, _) => T::Add(value),
+            (Rule::ast_expr_mul, _) => T::Mul(value),
+            (Rule::ast_expr_and, _) => T::And(value),
+            (Rule::ast_expr_xor, _) => T::Xor(value),
+            (Rule::ast_expr_sub, true) => T::SubPortVal(value),
+            (Rule::ast_expr_sub, false) => T::SubValPort(value),
+            (Rule::ast_expr_div | Rule::ast_expr_mod, true) if value == 0 => {
+                return Err(error("translation divides by zero"))
+            }
+            (Rule::ast_expr_div, true) => T::Div(value),
+            (Rule::ast_expr_mod, true) => T::Mod(value),
+            (Rule::ast_expr_lshift, true) => T::LShift(value as usize),
+            (Rule::ast_expr_rshift, true) => T::RShift(value as usize),
+            _ => {
+                return Err(error(
+                    "INPUT must be the left operand of division, modulo and shifts",
+                ))
+            }
+        };
+        steps.push(step);
+        Ok(Expression::Input(steps))
+    }
+
+    fn ast_lookup_entry(input: Node) -> ParseResult<(u64, u64)> {
         Ok(match_nodes!(input.into_children();
-                [num(n1), num(n2), num(n3)] => structures::MemoryLayout::new(n1 as usize, n2 as usize, Some(n3 as usize)),
+            [ast_value(address), ast_value(index)] => (address, index)
         ))
     }
-    fn range_ast(input: Node) -> ParseResult<structures::MemoryLayout> {
+    fn ast_lookup(input: Node) -> ParseResult<structures::TerminalRoutingProgram> {
+        let span = input.as_span();
+        let table = match_nodes!(input.into_children();
+            [ast_lookup_entry(e)..] => e.collect::<Vec<_>>()
+        );
+        for (idx, (address, _)) in table.iter().enumerate() {
+            if table[..idx].iter().any(|(x, _)| x == address) {
+                return Err(Error::new_from_span(
+                    pest::error::ErrorVariant::CustomError {
+                        message: format!("address {} has two entries in the lookup table", address),
+                    },
+                    span,
+                ));
+            }
+        }
+        Ok(structures::TerminalRoutingProgram::Lookup(table))
+    }
+
+    fn ast_translation_terminal(
+        input: Node,
+    ) -> ParseResult<Vec<structures::TerminalRoutingProgram>> {
         Ok(match_nodes!(input.into_children();
+            [ast_noop(a)] => vec![a],
+            [ast_lookup(a)] => vec![a],
+            [hole(_)] => vec![structures::TerminalRoutingProgram::Noop],
+            [ast_expression(e)] => e.into_steps()
+        ))
+    }
+
+    fn range_z3(input: Node) -> ParseResult<structures::MemoryLayout> {
+        let span = input.as_span();
+        let layout = match_nodes!(input.into_children();
                 [num(n1), num(n2), num(n3)] => structures::MemoryLayout::new(n1 as usize, n2 as usize, Some(n3 as usize)),
-                [num(n1), num(n2)] => structures::MemoryLayout::new(n1 as usize, n2 as usize, None),
+        );
+        layout.map_err(|e| semantic_error(span, e.to_string()))
+    }
+    fn range_ast(input: Node) -> ParseResult<structures::MemoryLayout> {
+        let span = input.as_span();
+        let holes = has_hole(&input);
+        let (start, finish, stride) = match_nodes!(input.into_children();
+                [ast_value(n1), ast_value(n2), ast_value(n3)] => (n1 as usize, n2 as usize, Some(n3 as usize)),
+                [ast_value(n1), ast_value(n2)] => (n1 as usize, n2 as usize, None),
+        );
+        // the numbers of a sketch's layout are checked once they are filled in
+        if holes {
+            return Ok(structures::MemoryLayout::Range {
+                start,
+                finish,
+                stride: stride.unwrap_or(1),
+            });
+        }
+        structures::MemoryLayout::new(start, finish, stride)
+            .map_err(|e| semantic_error(span, e.to_string()))
+    }
+    fn cyclic_z3(input: Node) -> ParseResult<structures::MemoryLayout> {
+        let span = input.as_span();
+        let layout = match_nodes!(input.into_children();
+                [num(factor), num(offset)] => structures::MemoryLayout::cyclic(factor as usize, offset as usize),
+        );
+        layout.map_err(|e| semantic_error(span, e.to_string()))
+    }
+    fn block_cyclic_z3(input: Node) -> ParseResult<structures::MemoryLayout> {
+        let span = input.as_span();
+        let layout = match_nodes!(input.into_children();
+                [num(block), num(factor), num(offset)] => structures::MemoryLayout::block_cyclic(block as usize, factor as usize, offset as usize),
+        );
+        layout.map_err(|e| semantic_error(span, e.to_string()))
+    }
+    fn cyclic_ast(input: Node) -> ParseResult<structures::MemoryLayout> {
+        let span = input.as_span();
+        let holes = has_hole(&input);
+        let (factor, offset) = match_nodes!(input.into_children();
+                [ast_value(factor), ast_value(offset)] => (factor as usize, offset as usize),
+        );
+        if holes {
+            return Ok(structures::MemoryLayout::Cyclic {
+                factor,
+                offset,
+                finish: usize::MAX,
+            });
+        }
+        structures::MemoryLayout::cyclic(factor, offset)
+            .map_err(|e| semantic_error(span, e.to_string()))
+    }
+    fn block_cyclic_ast(input: Node) -> ParseResult<structures::MemoryLayout> {
+        let span = input.as_span();
+        let holes = has_hole(&input);
+        let (block, factor, offset) = match_nodes!(input.into_children();
+                [ast_value(block), ast_value(factor), ast_value(offset)] => (block as usize, factor as usize, offset as usize),
+        );
+        if holes {
+            return Ok(structures::MemoryLayout::BlockCyclic {
+                block,
+                factor,
+                offset,
+                finish: usize::MAX,
+            });
+        }
+        structures::MemoryLayout::block_cyclic(block, factor, offset)
+            .map_err(|e| semantic_error(span, e.to_string()))
+    }
+    fn set_ast(input: Node) -> ParseResult<structures::MemoryLayout> {
+        let span = input.as_span();
+        let addresses = match_nodes!(input.into_children();
+            [ast_value(n)..] => n.map(|x| x as usize).collect::<Vec<_>>()
+        );
+        for (idx, address) in addresses.iter().enumerate() {
+            if addresses[..idx].contains(address) {
+                return Err(Error::new_from_span(
+                    pest::error::ErrorVariant::CustomError {
+                        message: format!("address {} is listed twice", address),
+                    },
+                    span,
+                ));
+            }
+        }
+        Ok(structures::MemoryLayout::Set(addresses))
+    }
+    fn layout_ast(input: Node) -> ParseResult<structures::MemoryLayout> {
+        Ok(match_nodes!(input.into_children();
+            [set_ast(a)] => a,
+            [range_ast(a)] => a,
+            [cyclic_ast(a)] => a,
+            [block_cyclic_ast(a)] => a,
+            [hole(_)] => structures::MemoryLayout::Set(vec![])
         ))
     }
     fn partition(input: Node) -> ParseResult<structures::MemoryLayout> {
         Ok(match_nodes!(input.into_children();
             [range_z3(z)] => z,
+            [cyclic_z3(z)] => z,
+            [block_cyclic_z3(z)] => z,
             [range_ast(a)] => a
         ))
     }
 
     fn ast_translation_sequence(input: Node) -> ParseResult<structures::SequenceRoutingProg> {
         Ok(match_nodes!(input.into_children();
-            [ast_translation_terminal(n)..] => structures::SequenceRoutingProg::Sequence(n.collect())
+            [ast_translation_terminal(n)..] => structures::SequenceRoutingProg::Sequence(n.flatten().collect())
         ))
     }
     fn ast_translation_mid_level(input: Node) -> ParseResult<structures::SequenceRoutingProg> {
         Ok(match_nodes!(input.into_children();
                 [ast_translation_sequence(s)] => s,
-                [ast_translation_terminal(n)] => structures::SequenceRoutingProg::Prog(n)
+                [ast_translation_terminal(mut n)] => if n.len() == 1 {
+                    structures::SequenceRoutingProg::Prog(n.remove(0))
+                } else {
+                    structures::SequenceRoutingProg::Sequence(n)
+                }
         ))
     }
 
@@ -268,28 +723,397 @@ impl AstParser {
     }
     fn ast_partition(input: Node) -> ParseResult<structures::TopLevelMemoryLayout> {
         Ok(match_nodes!(input.into_children();
-            [range_ast(a)..] => structures::TopLevelMemoryLayout::new(a.collect())))
+            [layout_ast(a)..] => structures::TopLevelMemoryLayout::new(a.collect())))
     }
 
-    fn bank(input: Node) -> ParseResult<structures::MemoryBank> {
+    fn bank_latency(input: Node) -> ParseResult<BankAttribute> {
         Ok(match_nodes!(input.into_children();
-        [ast_partition(p), ast_translation_top_level(tr)] => structures::MemoryBank::new(tr, p)
+            [num(n)] => BankAttribute::Latency(n)
         ))
     }
-    fn component(input: Node) -> ParseResult<structures::Component> {
+    fn bank_storage(input: Node) -> ParseResult<BankAttribute> {
         Ok(match_nodes!(input.into_children();
-                [num(bitwidth), num(size), bank(b)..] => {
-                    structures::Component::from_parse(size,bitwidth, b.collect())
-                }
+            [num(n)] => BankAttribute::Storage(n as usize)
         ))
     }
+    fn bank_attribute(input: Node) -> ParseResult<BankAttribute> {
+        Ok(match_nodes!(input.into_children();
+            [bank_latency(a)] => a,
+            [bank_storage(a)] => a
+        ))
+    }
+
+    fn bank_translation(input: Node) -> ParseResult<structures::TopLevelRoutingProgram> {
+        Ok(match_nodes!(input.into_children();
+            [ast_translation_top_level(tr)] => tr
+        ))
+    }
+    fn bank_phase_translation(
+        input: Node,
+    ) -> ParseResult<(u64, structures::TopLevelRoutingProgram)> {
+        Ok(match_nodes!(input.into_children();
+            [num(phase), ast_translation_top_level(tr)] => (phase, tr)
+        ))
+    }
+    fn bank_translations(input: Node) -> ParseResult<Vec<structures::TopLevelRoutingProgram>> {
+        let span = input.as_span();
+        let phases = match_nodes!(input.into_children();
+            [bank_translation(tr)] => return Ok(vec![tr]),
+            [bank_phase_translation(tr)..] => tr.collect::<Vec<_>>()
+        );
+
+        let mut translations = vec![];
+        for (idx, (phase, tr)) in phases.into_iter().enumerate() {
+            if phase != idx as u64 {
+                return Err(Error::new_from_span(
+                    pest::error::ErrorVariant::CustomError {
+                        message: format!("expected the translation for phase {}", idx),
+                    },
+                    span,
+                ));
+            }
+            translations.push(tr);
+        }
+        Ok(translations)
+    }
+
+    fn bank(input: Node) -> ParseResult<structures::MemoryBank> {
+        let span = input.as_span();
+        let (name, layout, translations, attributes) = match_nodes!(input.into_children();
+        [ast_partition(p), bank_translations(tr), bank_attribute(a)..] => (None, p, tr, a),
+        [ident(name), ast_partition(p), bank_translations(tr), bank_attribute(a)..] => (Some(name), p, tr, a)
+        );
+        let bank = structures::MemoryBank::new(translations, layout)
+            .map_err(|e| semantic_error(span, e.to_string()))?
+            .with_name(name);
+        Ok(attributes.fold(bank, |bank, attribute| match attribute {
+            BankAttribute::Latency(l) => bank.with_latency(l),
+            BankAttribute::Storage(s) => bank.with_storage(Some(s)),
+        }))
+    }
+    fn parameter(input: Node) -> ParseResult<()> {
+        let node = input.clone();
+        let (name, default) = match_nodes!(input.into_children();
+            [ident(name)] => (name, None),
+            [ident(name), num(value)] => (name, Some(value))
+        );
+        // values given when reading the description take precedence
+        if node.user_data().borrow().constants.contains_key(&name) {
+            return Ok(());
+        }
+        match default {
+            Some(value) => define(&node, name, value),
+            None => Err(node.error(format!(
+                "parameter `{}` has no default and needs a value, e.g. `--param {}=4`",
+                name, name
+            ))),
+        }
+    }
+    fn parameters(input: Node) -> ParseResult<()> {
+        for parameter in input.into_children() {
+            AstParser::parameter(parameter)?;
+        }
+        Ok(())
+    }
+    fn constant_definition(input: Node) -> ParseResult<()> {
+        let node = input.clone();
+        let (name, value) = match_nodes!(input.into_children();
+            [ident(name), ast_expression(value)] => (name, value)
+        );
+        match value {
+            Expression::Constant(value) => define(&node, name, value),
+            Expression::Input(_) => Err(node.error("constants cannot depend on INPUT")),
+        }
+    }
+
+    fn component(input: Node) -> ParseResult<structures::Component> {
+        let span = input.as_span();
+        let error = |message: String| {
+            Error::new_from_span(pest::error::ErrorVariant::CustomError { message }, span)
+        };
+        // the header and banks may use any parameter or constant, so these
+        // are defined first
+        for node in input.children() {
+            match node.as_rule() {
+                Rule::parameters => AstParser::parameters(node)?,
+                Rule::constant_definition => AstParser::constant_definition(node)?,
+                _ => {}
+            }
+        }
+        let name = input
+            .children()
+            .find(|x| x.as_rule() == Rule::ident)
+            .map(|x| x.as_str().to_string());
+        let mut header = input.children().filter(|x| x.as_rule() == Rule::ast_value);
+        let bitwidth = AstParser::ast_value(header.next().unwrap())?;
+        let size = AstParser::ast_value(header.next().unwrap())?;
+
+        let mut banks = vec![];
+        let mut spans = vec![];
+        for (variable, node) in bank_nodes(&input)? {
+            spans.push(node.as_span());
+            let bank = match variable {
+                Some((name, value)) => {
+                    define(&node, name.clone(), value)?;
+                    let bank = AstParser::bank(node.clone());
+                    node.user_data().borrow_mut().constants.remove(&name);
+                    bank?
+                }
+                None => AstParser::bank(node)?,
+            };
+            banks.push(bank);
+        }
+        let banks = banks
+            .into_iter()
+            .map(|x| x.with_memory_size(size as usize))
+            .collect::<Vec<_>>();
+
+        let component = structures::Component::from_parse(size, bitwidth, banks).with_name(name);
+        // the solver makes the layouts of a sketch agree
+        let Names {
+            holes, checking, ..
+        } = *input.user_data().borrow();
+        component
+            .check_banks(holes)
+            .map_err(|e| error(e.to_string()))?;
+        if !holes && !checking {
+            component.check_layouts().map_err(|e| match e {
+                structures::DescriptionError::LayoutPastMemory { bank, .. } => {
+                    semantic_error(spans[bank], e.to_string())
+                }
+                e => error(e.to_string()),
+            })?;
+        }
+        Ok(component)
+    }
+}
+
+/// A parsed description file
+pub struct Description {
+    /// the paths of the imported description files, as written
+    pub imports: Vec<String>,
+    pub memories: Vec<structures::Component>,
+    /// the names of the parameters declared by any of the memories
+    pub parameters: Vec<String>,
+}
+
+impl Description {
+    /// prints the imports and memories in their canonical form
+    pub fn pretty_print(&self) -> String {
+        let mut sections = vec![];
+        if !self.imports.is_empty() {
+            let imports = self
+                .imports
+                .iter()
+                .map(|x| format!("import \"{}\"\n", x))
+                .collect::<String>();
+            sections.push(imports);
+        }
+        sections.extend(self.memories.iter().map(|x| x.pretty_print()));
+        sections.join("\n")
+    }
+}
+
+/// A memory whose `?` holes are left for the solver to fill in
+#[derive(Debug, Clone)]
+pub struct Sketch {
+    /// the memory, with a placeholder wherever there is a hole
+    pub memory: structures::Component,
+    pub holes: Vec<Hole>,
+}
+
+/// A part of a sketch's bank left to the solver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hole {
+    /// the whole layout of a bank
+    Layout { bank: usize },
+    /// a number of a bank's range or cyclic layout, counting from the left
+    LayoutField { bank: usize, field: usize },
+    /// the whole translation of a bank in a phase
+    Translation { bank: usize, phase: usize },
+    /// the number of a bank's single step translation in a phase
+    TranslationValue { bank: usize, phase: usize },
+}
+
+impl Sketch {
+    pub fn has(&self, hole: Hole) -> bool {
+        self.holes.contains(&hole)
+    }
+}
+
+/// refuses holes anywhere within the node, which the solver cannot fill in
+fn refuse_holes(node: &Node) -> ParseResult<()> {
+    match find_hole(node) {
+        Some(hole) => Err(semantic_error(
+            hole.as_span(),
+            "the solver can only fill in a whole layout or translation, or a number of a \
+             range, a cyclic layout or a single step such as `INPUT >> ?num`"
+                .to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// whether a value is a hole
+fn is_hole(value: &Node) -> bool {
+    value.as_rule() == Rule::ast_value
+        && value
+            .children()
+            .next()
+            .is_some_and(|x| x.as_rule() == Rule::num_hole)
+}
+
+/// the hole in a bank's translation for a phase, if any
+fn translation_hole(bank: usize, phase: usize, node: Node) -> ParseResult<Option<Hole>> {
+    let mut node = node;
+    while matches!(
+        node.as_rule(),
+        Rule::ast_translation_top_level
+            | Rule::ast_translation_mid_level
+            | Rule::ast_translation_terminal
+    ) {
+        node = node.children().single()?;
+    }
+    if node.as_rule() == Rule::hole {
+        return Ok(Some(Hole::Translation { bank, phase }));
+    }
+    if node.as_rule() != Rule::ast_expression || !has_hole(&node) {
+        refuse_holes(&node)?;
+        return Ok(None);
+    }
+
+    // the hole must be the operand of a single step applied to INPUT
+    let atom_is = |atom: &Node, hole: bool| {
+        atom.children().single().is_ok_and(|x| match hole {
+            true => is_hole(&x),
+            false => x.as_rule() == Rule::ast_input,
+        })
+    };
+    let single_step = match node.children().collect::<Vec<_>>().as_slice() {
+        [value] => atom_is(value, true),
+        [left, _, right] => {
+            (atom_is(left, false) && atom_is(right, true))
+                || (atom_is(left, true) && atom_is(right, false))
+        }
+        _ => false,
+    };
+    if !single_step {
+        refuse_holes(&node)?;
+    }
+    Ok(Some(Hole::TranslationValue { bank, phase }))
+}
+
+/// the holes of a sketch's bank
+fn bank_holes(bank: usize, node: &Node, holes: &mut Vec<Hole>) -> ParseResult<()> {
+    for child in node.children() {
+        match child.as_rule() {
+            Rule::ast_partition if child.children().count() > 1 => refuse_holes(&child)?,
+            Rule::ast_partition => {
+                let layout = child.children().single()?.children().single()?;
+                match layout.as_rule() {
+                    Rule::hole => holes.push(Hole::Layout { bank }),
+                    Rule::set_ast => refuse_holes(&layout)?,
+                    _ => holes.extend(
+                        layout
+                            .children()
+                            .enumerate()
+                            .filter(|(_, value)| is_hole(value))
+                            .map(|(field, _)| Hole::LayoutField { bank, field }),
+                    ),
+                }
+            }
+            Rule::bank_translations => {
+                for (phase, translation) in child.children().enumerate() {
+                    let translation = translation.children().last().unwrap();
+                    holes.extend(translation_hole(bank, phase, translation)?);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// the names of the parameters a memory declares
+fn declared_parameters(component: &Node) -> Vec<String> {
+    component
+        .children()
+        .filter(|x| x.as_rule() == Rule::parameters)
+        .flat_map(|x| x.children())
+        .map(|x| x.children().next().unwrap().as_str().to_string())
+        .collect()
+}
+
+/// the value of a memory's size from its header
+fn memory_size(component: &Node) -> ParseResult<u64> {
+    let size = component
+        .children()
+        .filter(|x| x.as_rule() == Rule::ast_value)
+        .nth(1)
+        .unwrap();
+    AstParser::ast_value(size)
 }
 
 impl AstParser {
+    /// parses a description and reports the semantic errors of its
+    /// memories, each pointing at the offending part of the source
+    pub fn check_description<S: AsRef<str>>(
+        input: S,
+        parameters: &[(String, u64)],
+    ) -> ParseResult<Vec<Error<Rule>>> {
+        let scope = Scope::default();
+        scope.borrow_mut().checking = true;
+        let inputs = AstParser::parse_with_userdata(Rule::description, input.as_ref(), scope)?;
+        let mut errors = vec![];
+        for input in inputs.single()?.children() {
+            if input.as_rule() == Rule::component {
+                AstParser::check_component(input, parameters, &mut errors)?;
+            }
+        }
+        Ok(errors)
+    }
+
+    fn check_component(
+        input: Node,
+        parameters: &[(String, u64)],
+        errors: &mut Vec<Error<Rule>>,
+    ) -> ParseResult<()> {
+        let component = AstParser::component_with_parameters(input.clone(), parameters)?;
+        let size = memory_size(&input)? as usize;
+
+        for ((variable, bank_node), bank) in bank_nodes(&input)?.into_iter().zip(component.banks())
+        {
+            if let Some((name, value)) = &variable {
+                define(&bank_node, name.clone(), *value)?;
+            }
+            for node in bank_node.children() {
+                match node.as_rule() {
+                    Rule::ast_partition => {
+                        for layout in node.children() {
+                            check_layout(layout, size, errors)?;
+                        }
+                    }
+                    Rule::bank_translations => {
+                        for (phase, translation) in node.children().enumerate() {
+                            let translation = translation.children().last().unwrap();
+                            check_translation(translation, bank.routing(phase), bank, errors)?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if let Some((name, _)) = variable {
+                bank_node.user_data().borrow_mut().constants.remove(&name);
+            }
+        }
+        Ok(())
+    }
+
     pub fn parse_partition<S: AsRef<str>>(
         input: S,
     ) -> ParseResult<structures::TopLevelMemoryLayout> {
-        let inputs = AstParser::parse(Rule::partition, input.as_ref())?;
+        let inputs =
+            AstParser::parse_with_userdata(Rule::partition, input.as_ref(), Scope::default())?;
         let input = inputs.single()?;
         Ok(AstParser::partition(input)?.into())
     }
@@ -297,13 +1121,783 @@ impl AstParser {
     pub fn parse_z3_address_translation<S: AsRef<str>>(
         input: S,
     ) -> ParseResult<structures::TopLevelRoutingProgram> {
-        let inputs = AstParser::parse(Rule::z3_address_translation, input.as_ref())?;
+        let inputs = AstParser::parse_with_userdata(
+            Rule::z3_address_translation,
+            input.as_ref(),
+            Scope::default(),
+        )?;
         let input = inputs.single()?;
         Ok(AstParser::z3_address_translation(input)?.into())
     }
-    pub fn parse_component<S: AsRef<str>>(input: S) -> ParseResult<structures::Component> {
-        let inputs = AstParser::parse(Rule::component, input.as_ref())?;
+    /// parses a sketch, a description of a single memory where `?` leaves
+    /// parts of its banks for the solver to fill in
+    pub fn parse_sketch<S: AsRef<str>>(input: S) -> ParseResult<Sketch> {
+        let scope = Scope::default();
+        scope.borrow_mut().holes = true;
+        let inputs = AstParser::parse_with_userdata(Rule::description, input.as_ref(), scope)?;
         let input = inputs.single()?;
+        let span = input.as_span();
+        let node = match input.children().collect::<Vec<_>>().as_slice() {
+            [node] if node.as_rule() == Rule::component => node.clone(),
+            _ => {
+                return Err(semantic_error(
+                    span,
+                    "a sketch describes a single memory, without imports".to_string(),
+                ))
+            }
+        };
+
+        // holes may only be left in the banks
+        for child in node.children() {
+            match child.as_rule() {
+                Rule::bank => {}
+                Rule::bank_group => {
+                    for bound in child.children().filter(|x| x.as_rule() == Rule::ast_value) {
+                        refuse_holes(&bound)?;
+                    }
+                }
+                _ => refuse_holes(&child)?,
+            }
+        }
+        let memory = AstParser::component_with_parameters(node.clone(), &[])?;
+        let mut holes = vec![];
+        for (bank, (_, bank_node)) in bank_nodes(&node)?.iter().enumerate() {
+            bank_holes(bank, bank_node, &mut holes)?;
+        }
+        Ok(Sketch { memory, holes })
+    }
+    /// parses a description file, giving the parameters of its memories the
+    /// values provided rather than their defaults
+    pub fn parse_description<S: AsRef<str>>(
+        input: S,
+        parameters: &[(String, u64)],
+    ) -> ParseResult<Description> {
+        let inputs =
+            AstParser::parse_with_userdata(Rule::description, input.as_ref(), Scope::default())?;
+        let mut description = Description {
+            imports: vec![],
+            memories: vec![],
+            parameters: vec![],
+        };
+        for node in inputs.single()?.children() {
+            match node.as_rule() {
+                Rule::import => description
+                    .imports
+                    .push(node.children().next().unwrap().as_str().to_string()),
+                Rule::component => {
+                    let span = node.as_span();
+                    description.parameters.extend(declared_parameters(&node));
+                    let memory = AstParser::component_with_parameters(node, parameters)?;
+                    if let Some(name) = memory.name() {
+                        if description.memories.iter().any(|x| x.name() == Some(name)) {
+                            return Err(semantic_error(
+                                span,
+                                format!("memory `{}` is described twice", name),
+                            ));
+                        }
+                    }
+                    description.memories.push(memory);
+                }
+                _ => {}
+            }
+        }
+        Ok(description)
+    }
+    /// whether any memory of the description uses parameters, constants or
+    /// `for` blocks, which are lost when it is printed back out
+    pub fn is_parameterized<S: AsRef<str>>(input: S) -> ParseResult<bool> {
+        let inputs = AstParser::parse(Rule::description, input.as_ref())?;
+        let input = inputs.single()?;
+        // names can only be used once one of these defines them
+        Ok(input.children().flat_map(|x| x.children()).any(|x| {
+            matches!(
+                x.as_rule(),
+                Rule::parameters | Rule::constant_definition | Rule::bank_group
+            )
+        }))
+    }
+    /// whether the description has comments, which are lost when it is
+    /// printed back out. Import paths are the only text which may contain
+    /// `//` or `/*` without starting a comment
+    pub fn has_comments<S: AsRef<str>>(input: S) -> ParseResult<bool> {
+        let source = input.as_ref();
+        let inputs = AstParser::parse(Rule::description, source)?;
+        let mut text = source.to_string();
+        for path in inputs.single()?.into_pair().into_inner().flatten() {
+            if path.as_rule() == Rule::path {
+                let span = path.as_span();
+                text.replace_range(span.start()..span.end(), &" ".repeat(span.as_str().len()));
+            }
+        }
+        Ok(text.contains("//") || text.contains("/*"))
+    }
+    /// parses a memory, giving the parameters it declares the values
+    /// provided. Each memory only sees its own parameters and constants
+    fn component_with_parameters(
+        input: Node,
+        parameters: &[(String, u64)],
+    ) -> ParseResult<structures::Component> {
+        let declared = declared_parameters(&input);
+        {
+            let constants = &mut input.user_data().borrow_mut().constants;
+            constants.clear();
+            for (name, value) in parameters {
+                if declared.contains(name) {
+                    constants.insert(name.clone(), *value);
+                }
+            }
+        }
         AstParser::component(input)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::structures::{
+        ComparisonOperator, Component, Condition, DescriptionError, MemoryBank, MemoryLayout,
+        SequenceRoutingProg, TerminalRoutingProgram, TopLevelMemoryLayout, TopLevelRoutingProgram,
+    };
+    use super::{AstParser, Hole};
+    use pest::error::LineColLocation;
+    use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+
+    /// a memory which the description language can express
+    #[derive(Debug, Clone)]
+    struct Memory(Component);
+
+    /// a number, mostly small but sometimes as large as they come
+    fn number(g: &mut Gen) -> u64 {
+        match *g.choose(&[0, 1, 2, 3]).unwrap() {
+            0 => u64::arbitrary(g),
+            _ => u64::arbitrary(g) % 32,
+        }
+    }
+
+    fn small(g: &mut Gen, bound: u64) -> u64 {
+        u64::arbitrary(g) % bound
+    }
+
+    /// a layout holding only addresses of a memory of the given size
+    fn layout(g: &mut Gen, size: usize) -> MemoryLayout {
+        let n = |g: &mut Gen, bound: usize| small(g, bound as u64) as usize;
+        match small(g, 4) {
+            0 => {
+                let start = n(g, size);
+                let finish = start + n(g, size - start) + 1;
+                MemoryLayout::new(start, finish, Some(n(g, 16) + 1)).unwrap()
+            }
+            1 => {
+                let factor = n(g, 16) + 1;
+                MemoryLayout::cyclic(factor, n(g, factor.min(size))).unwrap()
+            }
+            2 => {
+                let blocks = (1..=size).filter(|x| size % x == 0).collect::<Vec<_>>();
+                let block = *g.choose(&blocks).unwrap();
+                let factor = n(g, 16) + 1;
+                MemoryLayout::block_cyclic(block, factor, n(g, factor.min(size / block))).unwrap()
+            }
+            _ => {
+                let mut addresses = (0..small(g, 4) + 1).map(|_| n(g, size)).collect::<Vec<_>>();
+                addresses.sort_unstable();
+                addresses.dedup();
+                MemoryLayout::Set(addresses)
+            }
+        }
+        .with_memory_size(size)
+    }
+
+    fn step(g: &mut Gen) -> TerminalRoutingProgram {
+        use TerminalRoutingProgram as T;
+        let value = number(g);
+        match small(g, 13) {
+            0 => T::RShift(small(g, 64) as usize),
+            1 => T::LShift(small(g, 64) as usize),
+            2 => T::Add(value),
+            3 => T::SubPortVal(value),
+            4 => T::SubValPort(value),
+            5 => T::Constant(value),
+            6 => T::And(value),
+            7 => T::Xor(value),
+            8 => T::Mod(value.max(1)),
+            9 => T::Div(value.max(1)),
+            10 => T::Mul(value),
+            11 => {
+                let mut table = (0..small(g, 4) + 1)
+                    .map(|_| (small(g, 16), number(g)))
+                    .collect::<Vec<_>>();
+                table.sort_unstable_by_key(|x| x.0);
+                table.dedup_by_key(|x| x.0);
+                T::Lookup(table)
+            }
+            _ => T::Noop,
+        }
+    }
+
+    fn sequence(g: &mut Gen) -> SequenceRoutingProg {
+        match bool::arbitrary(g) {
+            true => SequenceRoutingProg::Prog(step(g)),
+            false => SequenceRoutingProg::Sequence((0..small(g, 3) + 1).map(|_| step(g)).collect()),
+        }
+    }
+
+    fn condition(g: &mut Gen, depth: usize) -> Condition {
+        let operators = [
+            ComparisonOperator::LessThan,
+            ComparisonOperator::Equal,
+            ComparisonOperator::GreaterThan,
+            ComparisonOperator::NotEqual,
+            ComparisonOperator::LessThanOrEqual,
+            ComparisonOperator::GreaterThanOrEqual,
+        ];
+        let operator = g.choose(&operators).unwrap().clone();
+        let nested = |g: &mut Gen| Box::new(condition(g, depth - 1));
+        match small(g, if depth == 0 { 2 } else { 5 }) {
+            0 => Condition::ComparisonPortVal(number(g), operator),
+            1 => Condition::ComparisonValPort(number(g), operator),
+            2 => Condition::And(nested(g), nested(g)),
+            3 => Condition::Or(nested(g), nested(g)),
+            _ => Condition::Not(nested(g)),
+        }
+    }
+
+    fn translation(g: &mut Gen) -> TopLevelRoutingProgram {
+        match bool::arbitrary(g) {
+            true => TopLevelRoutingProgram::Prog(sequence(g)),
+            false => TopLevelRoutingProgram::Switch(
+                (0..small(g, 3) + 1)
+                    .map(|_| (condition(g, 2), sequence(g)))
+                    .collect(),
+                Box::new(sequence(g)),
+            ),
+        }
+    }
+
+    impl Arbitrary for Memory {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let size = small(g, 64) as usize + 1;
+            let phases = small(g, 3) as usize + 1;
+            let count = small(g, 4) as usize + 1;
+            let mut banks: Vec<MemoryBank> = vec![];
+            for idx in 0..count {
+                let routing = match bool::arbitrary(g) {
+                    true => vec![translation(g)],
+                    false => (0..phases).map(|_| translation(g)).collect(),
+                };
+                // a bank may share the storage of an earlier one which owns
+                // its storage, taking on its layout
+                let owner = (0..idx)
+                    .filter(|x| banks[*x].storage().is_none())
+                    .collect::<Vec<_>>();
+                let storage = match bool::arbitrary(g) {
+                    true => g.choose(&owner).copied(),
+                    false => None,
+                };
+                let layout = match storage {
+                    Some(owner) => banks[owner].layout().clone(),
+                    None => TopLevelMemoryLayout::new(
+                        (0..small(g, 3) + 1).map(|_| layout(g, size)).collect(),
+                    ),
+                };
+                let name = bool::arbitrary(g).then(|| format!("port{}", idx));
+                banks.push(
+                    MemoryBank::new(routing, layout)
+                        .unwrap()
+                        .with_name(name)
+                        .with_latency(small(g, 3))
+                        .with_storage(storage),
+                );
+            }
+            let name = bool::arbitrary(g).then(|| "mem".to_string());
+            Memory(Component::from_parse(size as u64, small(g, 64) + 1, banks).with_name(name))
+        }
+    }
+
+    #[test]
+    fn pretty_print_round_trips() {
+        fn round_trips(memory: Memory) -> TestResult {
+            let printed = memory.0.pretty_print();
+            match AstParser::parse_description(&printed, &[]) {
+                Ok(description) if description.memories == vec![memory.0] => TestResult::passed(),
+                Ok(description) => TestResult::error(format!(
+                    "{}\nwas parsed as {:?}",
+                    printed, description.memories
+                )),
+                Err(e) => TestResult::error(format!("{}\n{}", printed, e)),
+            }
+        }
+        QuickCheck::new()
+            .tests(1000)
+            .quickcheck(round_trips as fn(Memory) -> TestResult);
+    }
+
+    #[test]
+    fn sketches_record_their_holes() {
+        let sketch = AstParser::parse_sketch(
+            "memory<32, 16> {
+                bank { layout: [0:16:?num] translation: ? }
+                bank { layout: {1, 3, 5, 7} translation: INPUT >> ?num }
+                bank { layout: ? translation: INPUT }
+            }",
+        )
+        .unwrap();
+        assert_eq!(
+            sketch.holes,
+            vec![
+                Hole::LayoutField { bank: 0, field: 2 },
+                Hole::Translation { bank: 0, phase: 0 },
+                Hole::TranslationValue { bank: 1, phase: 0 },
+                Hole::Layout { bank: 2 },
+            ]
+        );
+        assert_eq!(sketch.memory.banks().len(), 3);
+    }
+
+    #[test]
+    fn sketches_without_holes_are_kept_as_written() {
+        let source = "memory<32, 16> { bank { layout: [0:16] translation: INPUT } }";
+        let sketch = AstParser::parse_sketch(source).unwrap();
+        assert!(sketch.holes.is_empty());
+        assert_eq!(
+            vec![sketch.memory],
+            AstParser::parse_description(source, &[]).unwrap().memories
+        );
+    }
+
+    #[test]
+    fn sketches_refuse_holes_the_solver_cannot_fill() {
+        for source in [
+            // outside the banks
+            "memory<32, ?num> { bank { layout: [0:16] translation: INPUT } }",
+            // within a larger expression
+            "memory<32, 16> { bank { layout: [0:16] translation: (INPUT + ?num) * 2 } }",
+            // within a set
+            "memory<32, 16> { bank { layout: {1, ?num} translation: INPUT } }",
+        ] {
+            assert!(AstParser::parse_sketch(source).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn sketches_describe_a_single_memory() {
+        let bank = "bank { layout: ? translation: INPUT }";
+        let source = format!(
+            "memory<32, 16> {{ {} }} memory<32, 16> {{ {} }}",
+            bank, bank
+        );
+        assert!(AstParser::parse_sketch(source).is_err());
+    }
+
+    #[test]
+    fn descriptions_refuse_holes() {
+        // a hole would otherwise be emitted as its placeholder
+        for (layout, translation) in [
+            ("?", "INPUT"),
+            ("[0:?num]", "INPUT"),
+            ("cyclic(?, 0)", "INPUT"),
+            ("[0:16]", "?"),
+            ("[0:16]", "INPUT >> ?num"),
+            ("[0:16]", "[INPUT - ?; NOOP]"),
+            ("[0:16]", "switch { INPUT < ?num -> INPUT, -> NOOP }"),
+        ] {
+            let source = format!(
+                "memory<32, 16> {{ bank {{ layout: {} translation: {} }} }}",
+                layout, translation
+            );
+            for parsed in [
+                AstParser::parse_description(&source, &[]).map(|_| ()),
+                AstParser::check_description(&source, &[]).map(|_| ()),
+            ] {
+                let error = parsed.expect_err(&source);
+                assert!(
+                    error
+                        .variant
+                        .message()
+                        .contains("may only leave holes in sketches"),
+                    "{}: {}",
+                    source,
+                    error
+                );
+            }
+        }
+        for source in [
+            "memory<32, ?num> { bank { layout: [0:16] translation: INPUT } }",
+            "memory<32, 16> with P = ? { bank { layout: [0:16] translation: INPUT } }",
+            "memory<32, 16> { let P = ?; bank { layout: [0:16] translation: INPUT } }",
+        ] {
+            assert!(
+                AstParser::parse_description(source, &[]).is_err(),
+                "{}",
+                source
+            );
+        }
+    }
+
+    /// the messages `check` reports for a description
+    fn diagnostics(source: &str) -> Vec<String> {
+        AstParser::check_description(source, &[])
+            .unwrap()
+            .into_iter()
+            .map(|e| e.variant.message().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn check_accepts_layouts_within_the_memory() {
+        let source = "memory<32, 16> {
+            bank { layout: [0:16:2] translation: INPUT / 2 }
+            bank { layout: cyclic(2, 1) translation: INPUT / 2 }
+            bank { layout: block_cyclic(8, 2, 1) translation: INPUT - 8 }
+            bank { layout: {1, 15} translation: lookup {1 -> 0, 15 -> 1} }
+        }";
+        assert_eq!(diagnostics(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn check_reports_layouts_past_the_memory() {
+        for (layout, translation) in [
+            ("[0:17]", "INPUT"),
+            ("{3, 16}", "lookup {3 -> 0, 16 -> 1}"),
+            ("cyclic(32, 20)", "INPUT / 32"),
+            ("block_cyclic(4, 8, 5)", "INPUT % 4"),
+        ] {
+            let source = format!(
+                "memory<32, 16> {{ bank {{ layout: {} translation: {} }} }}",
+                layout, translation
+            );
+            assert!(
+                diagnostics(&source)
+                    .iter()
+                    .any(|x| x.contains("exceeds the memory size 16")),
+                "{}",
+                layout
+            );
+        }
+    }
+
+    #[test]
+    fn parsing_refuses_layouts_past_the_memory() {
+        for layout in [
+            "[0:17]",
+            "{3, 16}",
+            "cyclic(32, 20)",
+            "block_cyclic(4, 8, 5)",
+            "block_cyclic(3, 2, 0)",
+        ] {
+            let source = format!(
+                "memory<32, 16> {{\n  bank {{ layout: [0:16] translation: INPUT }}\n  \
+                 bank {{ layout: {} translation: INPUT }}\n}}",
+                layout
+            );
+            let error = AstParser::parse_description(&source, &[])
+                .err()
+                .unwrap_or_else(|| panic!("{} parsed", layout));
+            // the error a JSON description gets, pointing at the bank
+            let expected = DescriptionError::LayoutPastMemory { bank: 1, size: 16 };
+            assert_eq!(error.variant.message(), expected.to_string());
+            assert!(
+                matches!(error.line_col, LineColLocation::Span((3, 3), _)),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn check_reports_blocks_not_dividing_the_memory() {
+        let source = "memory<32, 16> {
+            bank { layout: block_cyclic(3, 2, 0) translation: INPUT % 3 }
+        }";
+        assert_eq!(
+            diagnostics(source),
+            vec!["the block size 3 does not divide the memory size 16".to_string()]
+        );
+    }
+
+    #[test]
+    fn check_reports_translations_past_the_bank() {
+        let source = "memory<32, 16> { bank { layout: [0:16] translation: INPUT * 2 } }";
+        assert!(!diagnostics(source).is_empty());
+    }
+
+    /// parses a switch case's condition
+    fn parse_condition(source: &str) -> Result<Condition, String> {
+        use pest_consume::Parser;
+        let scope = super::Scope::default();
+        AstParser::parse_with_userdata(super::Rule::ast_bool, source, scope)
+            .and_then(|x| x.single())
+            .and_then(AstParser::ast_bool)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn not_applies_to_the_rest_of_the_condition() {
+        let below = || {
+            Box::new(Condition::ComparisonPortVal(
+                2,
+                ComparisonOperator::LessThan,
+            ))
+        };
+        let above = || {
+            Box::new(Condition::ComparisonPortVal(
+                12,
+                ComparisonOperator::GreaterThan,
+            ))
+        };
+        let neither = || Ok(Condition::Not(Box::new(Condition::And(below(), above()))));
+        assert_eq!(parse_condition("!INPUT < 2"), Ok(Condition::Not(below())));
+        assert_eq!(parse_condition("!INPUT < 2 && INPUT > 12"), neither());
+        assert_eq!(parse_condition("(!INPUT < 2 && INPUT > 12)"), neither());
+        assert_eq!(parse_condition("!(INPUT < 2 && INPUT > 12)"), neither());
+        assert_eq!(
+            parse_condition("((!INPUT < 2) && INPUT > 12)"),
+            Ok(Condition::And(Box::new(Condition::Not(below())), above()))
+        );
+        assert_eq!(
+            parse_condition("!!INPUT < 2"),
+            Ok(Condition::Not(Box::new(Condition::Not(below()))))
+        );
+        // a case's condition may be an unparenthesised negation
+        let switch = "memory<32, 16> { bank { layout: [0:16] translation: switch {
+            !INPUT < 2 && INPUT > 12 -> INPUT, -> NOOP
+        } } }";
+        let memory = AstParser::parse_description(switch, &[]).unwrap();
+        match memory.memories[0].banks()[0].routing(0) {
+            TopLevelRoutingProgram::Switch(cases, _) => {
+                assert_eq!(Ok(cases[0].0.clone()), neither())
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn negated_operands_print_with_parentheses() {
+        let source = "((!INPUT < 2) || INPUT > 12)";
+        let printed = parse_condition(source).unwrap().pretty_print();
+        assert_eq!(printed, "((!(INPUT < 2)) || INPUT > 12)");
+        assert_eq!(parse_condition(&printed), parse_condition(source));
+    }
+
+    #[test]
+    fn comments_are_found_outside_import_paths() {
+        let memory = "memory<32, 16> { bank { layout: [0:16] translation: INPUT } }";
+        let has_comments = |source: String| AstParser::has_comments(source).unwrap();
+        assert!(!has_comments(format!(
+            "import \"lib//a/*b.futil\"\n{}",
+            memory
+        )));
+        assert!(has_comments(format!("// the memory\n{}", memory)));
+        assert!(has_comments(format!(
+            "import \"a.futil\" /* and */ import \"b.futil\" {}",
+            memory
+        )));
+    }
+
+    #[test]
+    fn parameters_constants_and_for_blocks_are_found() {
+        let bank = "bank { layout: [0:16] translation: INPUT }";
+        let parameterized = |source: String| AstParser::is_parameterized(source).unwrap();
+        assert!(!parameterized(format!("memory<32, 16> {{ {} }}", bank)));
+        for source in [
+            format!("memory<32, N> with N = 16 {{ {} }}", bank),
+            format!("memory<32, 16> {{ let HALF = 8; {} }}", bank),
+            format!("memory<32, 16> {{ for i in 0..2 {{ {} }} }}", bank),
+            format!(
+                "memory a<32, 16> {{ {} }} memory b<32, 16> with P = 1 {{ {} }}",
+                bank, bank
+            ),
+        ] {
+            assert!(parameterized(source.clone()), "{}", source);
+        }
+    }
+
+    /// the memories of a description, given the parameters
+    fn memories(source: &str, parameters: &[(&str, u64)]) -> Result<Vec<Component>, String> {
+        let parameters = parameters
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect::<Vec<_>>();
+        AstParser::parse_description(source, &parameters)
+            .map(|x| x.memories)
+            .map_err(|e| e.variant.message().to_string())
+    }
+
+    const CYCLIC: &str = "
+        // P banks, each holding every P-th address
+        memory<32, N> with P = 4, N = 16 {
+            /* the bank an address is in is dropped */
+            let SHIFT = P / 2;
+            for i in 0..P {
+                bank {
+                    layout: cyclic(P, i)
+                    translation: INPUT >> SHIFT // P is a power of two
+                }
+            }
+        }";
+
+    #[test]
+    fn for_blocks_repeat_their_banks() {
+        let expanded = |factor: u64, shift: u64| {
+            let banks = (0..factor)
+                .map(|i| {
+                    format!(
+                        "bank {{ layout: cyclic({}, {}) translation: INPUT >> {} }}",
+                        factor, i, shift
+                    )
+                })
+                .collect::<Vec<_>>();
+            memories(&format!("memory<32, 16> {{ {} }}", banks.join(" ")), &[]).unwrap()
+        };
+        assert_eq!(memories(CYCLIC, &[]), Ok(expanded(4, 2)));
+        // overriding a parameter also changes the constants built from it
+        assert_eq!(memories(CYCLIC, &[("P", 2)]), Ok(expanded(2, 1)));
+        let wider = memories(CYCLIC, &[("N", 64)]).unwrap();
+        assert_eq!(wider[0].size(), 64);
+        assert_eq!(wider[0].banks()[3].layout().addresses().len(), 16);
+    }
+
+    #[test]
+    fn names_must_be_defined_once_before_their_use() {
+        let bank = "bank { layout: [0:16] translation: INPUT }";
+        for (source, message) in [
+            (
+                format!("memory<32, N> {{ {} }}", bank),
+                "unknown constant `N`",
+            ),
+            (
+                format!("memory<32, 16> with P = 4 {{ let P = 2; {} }}", bank),
+                "`P` is already defined",
+            ),
+            (
+                format!("memory<32, 16> {{ let A = INPUT; {} }}", bank),
+                "constants cannot depend on INPUT",
+            ),
+            (
+                format!("memory<32, 16> with P {{ {} }}", bank),
+                "parameter `P` has no default and needs a value, e.g. `--param P=4`",
+            ),
+        ] {
+            assert_eq!(
+                memories(&source, &[]),
+                Err(message.to_string()),
+                "{}",
+                source
+            );
+        }
+        // a loop variable goes out of scope after its block, and each memory
+        // only sees its own parameters
+        let two = format!(
+            "memory a<32, 16> with P = 1 {{ for i in 0..P {{ {} }} }} \
+             memory b<32, 16> {{ let P = 2; for i in 0..P {{ {} }} for i in 0..1 {{ {} }} }}",
+            bank, bank, bank
+        );
+        let parsed = memories(&two, &[("P", 3)]).unwrap();
+        assert_eq!(parsed[0].banks().len(), 3);
+        assert_eq!(parsed[1].banks().len(), 3);
+    }
+
+    #[test]
+    fn descriptions_hold_several_named_memories() {
+        let source = "import \"lib/arrays.mem\"
+            import \"../shared.mem\"
+            memory weights<32, 16> { bank { layout: [0:16] translation: INPUT } }
+            memory<8, 4> { bank { layout: [0:4] translation: INPUT } }";
+        let description = AstParser::parse_description(source, &[]).unwrap();
+        assert_eq!(description.imports, vec!["lib/arrays.mem", "../shared.mem"]);
+        let names = description
+            .memories
+            .iter()
+            .map(|x| x.component_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["weights", "mem_4_1"]);
+        assert_eq!(description.memories[0].size(), 16);
+        assert_eq!(description.memories[1].width(), 8);
+
+        let twice = "memory a<32, 16> { bank { layout: [0:16] translation: INPUT } }
+            memory a<32, 8> { bank { layout: [0:8] translation: INPUT } }";
+        let error = AstParser::parse_description(twice, &[]).err().unwrap();
+        assert_eq!(error.variant.message(), "memory `a` is described twice");
+        assert!(matches!(error.line_col, LineColLocation::Span((2, 13), _)));
+    }
+
+    /// the translation of a single bank memory
+    fn parse_translation(translation: &str) -> Result<TopLevelRoutingProgram, String> {
+        let source = format!(
+            "memory<32, 16> with N = 4 {{ let HALF = N / 2; bank {{ layout: [0:16] translation: {} }} }}",
+            translation
+        );
+        AstParser::parse_description(source, &[])
+            .map(|x| x.memories[0].banks()[0].routing(0).clone())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn expressions_become_translation_steps() {
+        use TerminalRoutingProgram as T;
+        let sequence = |steps: Vec<T>| -> TopLevelRoutingProgram {
+            SequenceRoutingProg::Sequence(steps).into()
+        };
+        for (source, translation) in [
+            ("INPUT", T::Noop.into()),
+            (
+                "(INPUT - 1) / 2",
+                sequence(vec![T::SubPortVal(1), T::Div(2)]),
+            ),
+            ("8 - INPUT", T::SubValPort(8).into()),
+            // constants are folded before they are applied
+            ("2 * 4 + INPUT", T::Add(8).into()),
+            ("INPUT * HALF + 1", sequence(vec![T::Mul(2), T::Add(1)])),
+            ("INPUT >> 1 & 3", sequence(vec![T::RShift(1), T::And(3)])),
+            ("N << 1", T::Constant(8).into()),
+        ] {
+            assert_eq!(parse_translation(source), Ok(translation), "{}", source);
+        }
+        let translation = parse_translation("(INPUT - 1) / 2 + 4").unwrap();
+        assert_eq!(translation.checked_eval(7), Some(7));
+        assert_eq!(translation.checked_eval(0), None);
+    }
+
+    #[test]
+    fn expressions_refuse_what_a_step_cannot_express() {
+        for (source, error) in [
+            ("INPUT + INPUT", "INPUT may only appear once"),
+            ("16 / INPUT", "INPUT must be the left operand"),
+            ("INPUT % (N - 4)", "divides by zero"),
+            ("INPUT + (1 - 2)", "out of range"),
+            ("INPUT + M", "unknown constant `M`"),
+        ] {
+            let message = parse_translation(source).unwrap_err();
+            assert!(message.contains(error), "{}\n{}", source, message);
+        }
+    }
+
+    #[test]
+    fn check_accepts_switches_with_a_default() {
+        let source = "memory<32, 16> { bank { layout: [0:16] translation: switch {
+            INPUT < 4 -> INPUT + 12, -> INPUT - 4
+        } } }";
+        assert_eq!(diagnostics(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn check_reports_cases_of_the_bank_never_taken() {
+        let switch = |cases: &str| {
+            diagnostics(&format!(
+                "memory<32, 16> {{ bank {{ layout: [8:16] translation: switch {{ {} }} }} }}",
+                cases
+            ))
+        };
+        // only the bank's addresses count
+        assert_eq!(
+            switch("INPUT < 8 -> INPUT, -> INPUT - 8"),
+            vec!["this case is unreachable".to_string()]
+        );
+        assert_eq!(
+            switch("INPUT < 12 -> INPUT - 8, INPUT < 14 -> INPUT - 8, -> INPUT - 8"),
+            vec!["this case overlaps an earlier case, e.g. for address 8".to_string()]
+        );
+        assert_eq!(
+            switch("INPUT < 12 -> INPUT - 8, INPUT >= 12 -> INPUT - 8, -> NOOP"),
+            vec!["this case is unreachable".to_string()]
+        );
+    }
+}
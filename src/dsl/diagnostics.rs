@@ -0,0 +1,129 @@
+use super::ast::Rule;
+use pest::error::ErrorVariant;
+use pest_consume::Error;
+
+/// a human readable description of what the parser expected in place of
+/// the given rule
+fn describe(rule: &Rule) -> String {
+    let description = match rule {
+        Rule::num => "a number",
+        Rule::hole | Rule::num_hole => "a hole `?`",
+        Rule::hex_num => "a hexadecimal number",
+        Rule::ident => "a bank name",
+        Rule::LEQ
+        | Rule::GEQ
+        | Rule::EQ
+        | Rule::NEQ
+        | Rule::LT
+        | Rule::GT
+        | Rule::comparison_operator => "a comparison operator",
+        Rule::range_ast
+        | Rule::cyclic_ast
+        | Rule::block_cyclic_ast
+        | Rule::set_ast
+        | Rule::layout_ast
+        | Rule::ast_partition => "a layout such as `[0:16:2]` or `cyclic(4, 0)`",
+        Rule::ast_expr_add
+        | Rule::ast_expr_sub
+        | Rule::ast_expr_mul
+        | Rule::ast_expr_div
+        | Rule::ast_expr_mod
+        | Rule::ast_expr_lshift
+        | Rule::ast_expr_rshift
+        | Rule::ast_expr_and
+        | Rule::ast_expr_xor => "an arithmetic operator",
+        Rule::ast_input
+        | Rule::ast_expression_atom
+        | Rule::ast_expression
+        | Rule::ast_lookup
+        | Rule::ast_noop
+        | Rule::ast_translation_terminal
+        | Rule::ast_translation_sequence
+        | Rule::ast_translation_mid_level
+        | Rule::ast_translation_switch
+        | Rule::ast_translation_top_level => "a translation such as `INPUT >> 1`",
+        Rule::ast_lookup_entry => "a lookup entry such as `3 -> 0`",
+        Rule::ast_comparison | Rule::ast_bool | Rule::ast_bool_expression => {
+            "a condition such as `INPUT < 8`"
+        }
+        Rule::ast_translation_switch_case | Rule::ast_translation_switch_default => {
+            "a switch case such as `INPUT < 8 -> INPUT`"
+        }
+        Rule::bank_latency | Rule::bank_storage | Rule::bank_attribute => {
+            "`latency:` or `storage:`"
+        }
+        Rule::bank_translation | Rule::bank_phase_translation | Rule::bank_translations => {
+            "`translation:`"
+        }
+        Rule::bank => "a bank",
+        Rule::ast_value => "a number or constant",
+        Rule::parameter | Rule::parameters => "a parameter such as `P = 4`",
+        Rule::constant_definition => "a constant such as `let HALF = N / 2;`",
+        Rule::bank_group => "a bank or `for` block",
+        Rule::component => "a memory such as `memory<32, 16> { ... }`",
+        Rule::EOI => "the end of the file",
+        other => return format!("{:?}", other),
+    };
+    description.to_string()
+}
+
+/// renders a parse or semantic error with the file name, the location, the
+/// offending source underlined and what was expected there
+pub fn render(mut error: Error<Rule>, path: &str) -> String {
+    // many rules share a description, so list each description only once
+    if let ErrorVariant::ParsingError { positives, .. } = &error.variant {
+        let mut expected: Vec<String> = vec![];
+        for description in positives.iter().map(describe) {
+            if !expected.contains(&description) {
+                expected.push(description);
+            }
+        }
+        let message = match expected.as_slice() {
+            [] => "unexpected input".to_string(),
+            [only] => format!("expected {}", only),
+            [rest @ .., last] => format!("expected {}, or {}", rest.join(", "), last),
+        };
+        error.variant = ErrorVariant::CustomError { message };
+    }
+    error.with_path(path).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ast::AstParser;
+    use super::render;
+
+    fn rendered(source: &str) -> String {
+        match AstParser::parse_description(source, &[]) {
+            Ok(_) => panic!("{} parsed", source),
+            Err(error) => render(error, "a.mem"),
+        }
+    }
+
+    #[test]
+    fn errors_point_at_their_location() {
+        let error = rendered("memory<32, 16> {\n  bank { layout: [0:16] translation: }\n}");
+        let expected = [
+            " --> a.mem:2:38",
+            "  |",
+            "2 |   bank { layout: [0:16] translation: }",
+            "  |                                      ^---",
+            "  |",
+            "  = expected a translation such as `INPUT >> 1`",
+        ];
+        assert_eq!(error.lines().collect::<Vec<_>>(), expected, "{}", error);
+    }
+
+    #[test]
+    fn rules_with_the_same_description_are_listed_once() {
+        // every arithmetic operator may follow `INPUT`, and either attribute
+        // may follow the translation
+        let error = rendered("memory<32, 16> { bank { layout: [0:16] translation: INPUT foo } }");
+        assert!(error.contains(" --> a.mem:1:59"), "{}", error);
+        assert!(
+            error.ends_with("= expected an arithmetic operator, or `latency:` or `storage:`"),
+            "{}",
+            error
+        );
+    }
+}
@@ -1,4 +1,5 @@
 pub mod ast;
+pub mod diagnostics;
 mod trace;
 pub use trace::bits_required;
 pub use trace::Trace;
@@ -1,12 +1,32 @@
 use serde::Deserialize;
 use serde_json::{self, Result};
 
+use super::super::structures;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PortInfo {
+    /// the name used for the port in emitted components
+    #[serde(default)]
+    name: Option<String>,
+    /// the number of cycles between a request being issued on this port and
+    /// its result being available
+    #[serde(default)]
+    latency: u64,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Trace {
     /// the number of entries in the logical memory
     size: usize,
     /// the bitwidth of the elements in the logical memory
     bitwidth: usize,
+    /// optional metadata for each port (column) of the trace
+    #[serde(default)]
+    ports: Vec<PortInfo>,
+    /// the cycle in which each line of the trace is issued. When omitted
+    /// line `i` is issued in cycle `i`
+    #[serde(default)]
+    cycles: Vec<u64>,
     /// the input trace
     trace: Vec<Vec<Option<usize>>>,
 }
@@ -22,18 +42,68 @@ impl Trace {
 
     pub fn parse_trace<S: AsRef<str>>(input: S) -> Result<Self> {
         let mut trace: Self = serde_json::from_str(input.as_ref())?;
+        trace.check_cycles()?;
+        trace.check_addresses()?;
         trace.normalize();
+        trace.check_port_names()?;
         Ok(trace)
     }
 
+    /// ensures that the cycle annotations, if present, cover every line and
+    /// are strictly increasing
+    fn check_cycles(&self) -> Result<()> {
+        if self.cycles.is_empty() {
+            return Ok(());
+        }
+        if self.cycles.len() != self.trace.len() {
+            return Err(serde::de::Error::custom(format!(
+                "expected {} cycle annotations but found {}",
+                self.trace.len(),
+                self.cycles.len()
+            )));
+        }
+        if self.cycles.windows(2).any(|x| x[0] >= x[1]) {
+            return Err(serde::de::Error::custom(
+                "cycle annotations must be strictly increasing",
+            ));
+        }
+        Ok(())
+    }
+
+    /// ensures that every requested address lies within the memory
+    fn check_addresses(&self) -> Result<()> {
+        let past = self.trace.iter().enumerate().find_map(|(line, requests)| {
+            requests
+                .iter()
+                .flatten()
+                .find(|x| **x >= self.size)
+                .map(|x| (line, *x))
+        });
+        match past {
+            Some((line, address)) => Err(serde::de::Error::custom(format!(
+                "line {} requests address {}, past the end of the memory of size {}",
+                line, address, self.size
+            ))),
+            None => Ok(()),
+        }
+    }
+
     /// removes trace lines which are all empty
     /// pads nones onto the end of lines which omit entries
+    /// pads unnamed ports onto the port metadata
     fn normalize(&mut self) {
         let trace = std::mem::take(&mut self.trace);
-        self.trace = trace
+        let cycles = std::mem::take(&mut self.cycles);
+        let cycles = if cycles.is_empty() {
+            (0..trace.len() as u64).collect()
+        } else {
+            cycles
+        };
+        (self.cycles, self.trace) = cycles
             .into_iter()
-            .filter(|x| x.iter().any(|x| x.is_some()))
-            .collect();
+            .zip(trace)
+            .filter(|(_, x)| x.iter().any(|x| x.is_some()))
+            .unzip();
         let ports_required = self.ports_required();
 
         for line in self.trace.iter_mut() {
@@ -41,23 +111,73 @@ impl Trace {
                 line.push(None)
             }
         }
+
+        while self.ports.len() < ports_required {
+            self.ports.push(PortInfo::default())
+        }
+    }
+
+    /// ensures that every port has a name of its own which is not a keyword
+    fn check_port_names(&self) -> Result<()> {
+        let names = self
+            .ports
+            .iter()
+            .map(|x| x.name.as_deref())
+            .collect::<Vec<_>>();
+        structures::check_port_names(&names).map_err(serde::de::Error::custom)
     }
 
     fn ports_required(&self) -> usize {
-        self.trace.iter().map(|x| x.len()).max().unwrap_or_default()
+        let columns = self.trace.iter().map(|x| x.len()).max().unwrap_or_default();
+        columns.max(self.ports.len())
     }
 
     pub fn num_ports(&self) -> usize {
-        self.trace.get(0).map_or(0, |x| x.len())
+        self.ports.len()
+    }
+
+    /// the name of the given port, if one was provided
+    pub fn port_name(&self, port: usize) -> Option<&str> {
+        self.ports.get(port).and_then(|x| x.name.as_deref())
+    }
+
+    /// the number of cycles between a request on the port and its result
+    pub fn latency(&self, port: usize) -> u64 {
+        self.ports.get(port).map_or(0, |x| x.latency)
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &Vec<Option<usize>>> {
         self.trace.iter()
     }
 
+    /// iterates over the trace lines along with the cycle they are issued in
+    pub fn iter_cycles(&self) -> impl Iterator<Item = (u64, &Vec<Option<usize>>)> {
+        self.cycles.iter().copied().zip(self.trace.iter())
+    }
+
+    /// the number of cycles spanned by the trace
+    pub fn cycle_count(&self) -> u64 {
+        self.cycles.last().map_or(0, |x| x + 1)
+    }
+
+    /// whether the two ports ever need a single bank at the same time, either
+    /// by issuing in the same cycle or by completing in the same cycle
+    pub fn ports_overlap(&self, first: usize, second: usize) -> bool {
+        let accesses = |port: usize| {
+            self.iter_cycles()
+                .filter(move |(_, line)| line[port].is_some())
+                .map(|(cycle, _)| cycle)
+        };
+        let (first_latency, second_latency) = (self.latency(first), self.latency(second));
+
+        accesses(first)
+            .any(|x| accesses(second).any(|y| x == y || x + first_latency == y + second_latency))
+    }
+
+    /// the number of bits holding every address of the memory
     #[inline]
     pub fn bits_required(&self) -> u32 {
-        bits_required(self.size)
+        (usize::BITS - self.size.saturating_sub(1).leading_zeros()).max(1)
     }
 }
 
@@ -65,3 +185,84 @@ pub fn bits_required(size: usize) -> u32 {
     let bits = std::mem::size_of::<usize>() * 8;
     (bits as u32) - size.leading_zeros() - 1
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Trace;
+
+    #[test]
+    fn lines_default_to_consecutive_cycles() {
+        let trace =
+            Trace::parse_trace(r#"{"size": 4, "bitwidth": 8, "trace": [[0], [1]]}"#).unwrap();
+        let cycles = trace.iter_cycles().map(|(x, _)| x).collect::<Vec<_>>();
+        assert_eq!(cycles, vec![0, 1]);
+        assert_eq!(trace.cycle_count(), 2);
+    }
+
+    #[test]
+    fn cycles_follow_the_lines_they_annotate() {
+        let trace = Trace::parse_trace(
+            r#"{"size": 4, "bitwidth": 8, "cycles": [0, 2, 5],
+                "trace": [[0, 1], [null, null], [3]]}"#,
+        )
+        .unwrap();
+        // the empty line is dropped along with its cycle
+        let lines = trace
+            .iter_cycles()
+            .map(|(cycle, line)| (cycle, line.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![(0, vec![Some(0), Some(1)]), (5, vec![Some(3), None])]
+        );
+        assert_eq!(trace.cycle_count(), 6);
+    }
+
+    #[test]
+    fn cycles_must_cover_every_line() {
+        let error =
+            Trace::parse_trace(r#"{"size": 4, "bitwidth": 8, "cycles": [0], "trace": [[0], [1]]}"#)
+                .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("expected 2 cycle annotations but found 1"));
+    }
+
+    #[test]
+    fn cycles_must_increase() {
+        for cycles in ["[1, 1]", "[2, 1]"] {
+            let error = Trace::parse_trace(format!(
+                r#"{{"size": 4, "bitwidth": 8, "cycles": {}, "trace": [[0], [1]]}}"#,
+                cycles
+            ))
+            .unwrap_err();
+            assert!(error.to_string().contains("strictly increasing"));
+        }
+    }
+
+    #[test]
+    fn ports_overlap_when_issuing_or_completing_together() {
+        let trace = |ports: &str, cycles: &str| {
+            Trace::parse_trace(format!(
+                r#"{{"size": 4, "bitwidth": 8, "ports": {}, "cycles": {},
+                    "trace": [[0, null], [null, 1]]}}"#,
+                ports, cycles
+            ))
+            .unwrap()
+        };
+        // different cycles and the same latency never meet
+        assert!(!trace("[{}, {}]", "[0, 1]").ports_overlap(0, 1));
+        // the first read completes in the cycle the second one does
+        assert!(trace(r#"[{"latency": 1}, {}]"#, "[0, 1]").ports_overlap(0, 1));
+        assert!(!trace(r#"[{"latency": 1}, {}]"#, "[0, 2]").ports_overlap(0, 1));
+    }
+
+    #[test]
+    fn addresses_must_lie_within_the_memory() {
+        let error = Trace::parse_trace(r#"{"size": 4, "bitwidth": 8, "trace": [[0], [1, 4]]}"#)
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("line 1 requests address 4, past the end of the memory of size 4"));
+    }
+}
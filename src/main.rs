@@ -1,15 +1,21 @@
 mod dsl;
+mod simulator;
 mod solver;
 mod structures;
 
 use dsl::ast::AstParser;
+use dsl::diagnostics;
 use dsl::Trace;
+use solver::{SolverOptions, StallBudget};
+use structures::image::ImageFormat;
+use structures::Component;
 
 use argh::FromArgs;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 #[derive(FromArgs)]
 /// Synthesize a memory from input traces or generate a calyx implementation
@@ -28,15 +34,50 @@ enum Command {
     Synthesize(SynthesizeCommand),
     Output(OutputCommand),
     Verify(VerifyCommand),
+    Check(CheckCommand),
+    Format(FormatCommand),
+    SplitData(SplitDataCommand),
+    Merge(MergeCommand),
+    Viz(VizCommand),
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
-/// Synthesize a memory implementation from a trace
+/// Synthesize a memory implementation from one or more traces, each trace
+/// being a separate phase of the memory's use
 #[argh(subcommand, name = "synthesize")]
 struct SynthesizeCommand {
-    /// file to read the trace from
+    /// files to read the traces from
     #[argh(positional)]
-    trace_file: String,
+    trace_files: Vec<String>,
+
+    /// use one translation for every phase rather than one per phase
+    #[argh(switch)]
+    single_routing: bool,
+
+    /// allow bank conflicts resolved by at most this many stall cycles
+    #[argh(option)]
+    max_stalls: Option<u64>,
+
+    /// allow bank conflicts resolved by stalling for at most this percentage
+    /// of the trace's cycles, instead of --max-stalls
+    #[argh(option)]
+    max_slowdown: Option<f64>,
+
+    /// the cost of a stall cycle relative to one unit of bank size
+    #[argh(option, default = "1")]
+    stall_cost: u64,
+
+    /// partial description to complete, with `?` marking the holes
+    #[argh(option)]
+    sketch: Option<String>,
+
+    /// also consider cyclic and block-cyclic layouts
+    #[argh(switch)]
+    cyclic_layouts: bool,
+
+    /// print the description as JSON rather than in the description language
+    #[argh(switch)]
+    json: bool,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -46,6 +87,65 @@ struct OutputCommand {
     /// file to read the description from
     #[argh(positional)]
     memory_description: String,
+
+    /// the value of a parameter of the description, as `NAME=VALUE`
+    #[argh(option)]
+    param: Vec<String>,
+
+    /// only emit the memory with this name
+    #[argh(option)]
+    memory: Option<String>,
+
+    /// the language to emit: calyx (the default), verilog, hls
+    /// or firrtl
+    #[argh(option, default = "Target::Calyx")]
+    target: Target,
+
+    /// also emit a Calyx component per memory with a `std_mem_d1` style
+    /// port per logical port, routing any address to a bank holding it
+    #[argh(switch)]
+    wrapper: bool,
+
+    /// emit a Calyx testbench running this trace on the memory instead, given
+    /// once per phase
+    #[argh(option)]
+    testbench: Vec<String>,
+
+    /// the initial contents of the logical memory for the testbench, as
+    /// read by `split-data`. By default every address holds its own address
+    #[argh(option)]
+    image: Option<String>,
+
+    /// where to write the testbench's data file, by default next to the first
+    /// trace. The expected memories are written alongside as `.expect`
+    #[argh(option)]
+    data: Option<String>,
+}
+
+/// The languages implementations can be emitted in
+#[derive(PartialEq, Debug)]
+enum Target {
+    Calyx,
+    Verilog,
+    Hls,
+    Firrtl,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "calyx" => Ok(Target::Calyx),
+            "verilog" | "systemverilog" => Ok(Target::Verilog),
+            "hls" => Ok(Target::Hls),
+            "firrtl" => Ok(Target::Firrtl),
+            _ => Err(format!(
+                "unknown target `{}`, expected calyx, verilog, hls or firrtl",
+                s
+            )),
+        }
+    }
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -56,9 +156,332 @@ struct VerifyCommand {
     #[argh(positional)]
     memory_description: String,
 
-    /// file to read the trace from
+    /// the value of a parameter of the description, as `NAME=VALUE`
+    #[argh(option)]
+    param: Vec<String>,
+
+    /// files to read the traces from, one per phase
+    #[argh(positional)]
+    trace_files: Vec<String>,
+
+    /// allow bank conflicts resolved by at most this many stall cycles
+    #[argh(option)]
+    max_stalls: Option<u64>,
+
+    /// allow bank conflicts resolved by stalling for at most this percentage
+    /// of the trace's cycles, instead of --max-stalls
+    #[argh(option)]
+    max_slowdown: Option<f64>,
+
+    /// the memory to verify when the description has several
+    #[argh(option)]
+    memory: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Report semantic errors in a description
+#[argh(subcommand, name = "check")]
+struct CheckCommand {
+    /// file to read the description from
+    #[argh(positional)]
+    memory_description: String,
+
+    /// the value of a parameter of the description, as `NAME=VALUE`
+    #[argh(option)]
+    param: Vec<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Rewrite descriptions in their canonical form
+#[argh(subcommand, name = "fmt")]
+struct FormatCommand {
+    /// files to format in place
     #[argh(positional)]
-    trace_file: String,
+    memory_descriptions: Vec<String>,
+
+    /// only report the files which are not formatted, without rewriting them
+    #[argh(switch)]
+    check: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Split the contents of a logical memory into initialization files for
+/// each bank
+#[argh(subcommand, name = "split-data")]
+struct SplitDataCommand {
+    /// file to read the description from
+    #[argh(positional)]
+    memory_description: String,
+
+    /// file holding the contents of the logical memory
+    #[argh(positional)]
+    image: String,
+
+    /// the value of a parameter of the description, as `NAME=VALUE`
+    #[argh(option)]
+    param: Vec<String>,
+
+    /// the memory to split for when the description has several
+    #[argh(option)]
+    memory: Option<String>,
+
+    /// the format of the image: json, hex or bin, by default taken from the
+    /// file's extension
+    #[argh(option)]
+    format: Option<ImageFormat>,
+
+    /// the format to write: json (the default) for a single Calyx data file,
+    /// or hex or bin for a file per bank
+    #[argh(option, default = "ImageFormat::Json")]
+    to: ImageFormat,
+
+    /// the directory to write the files per bank to
+    #[argh(option, default = "String::from(\".\")")]
+    dir: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Reconstruct the contents of a logical memory from dumps of its banks
+#[argh(subcommand, name = "merge")]
+struct MergeCommand {
+    /// file to read the description from
+    #[argh(positional)]
+    memory_description: String,
+
+    /// a Calyx data file holding every bank, or a file per bank holding
+    /// its own storage, in the order of the banks
+    #[argh(positional)]
+    dumps: Vec<String>,
+
+    /// the value of a parameter of the description, as `NAME=VALUE`
+    #[argh(option)]
+    param: Vec<String>,
+
+    /// the memory to merge for when the description has several
+    #[argh(option)]
+    memory: Option<String>,
+
+    /// the format of the dumps: json, hex or bin, by default taken from the
+    /// files' extensions
+    #[argh(option)]
+    format: Option<ImageFormat>,
+
+    /// the format to write the logical memory in: json (the default), hex
+    /// or bin
+    #[argh(option, default = "ImageFormat::Json")]
+    to: ImageFormat,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Draw how the addresses spread across banks and how the translations route
+/// them
+#[argh(subcommand, name = "viz")]
+struct VizCommand {
+    /// file to read the description from
+    #[argh(positional)]
+    memory_description: String,
+
+    /// files to read traces from, one per phase, to draw the bank each read
+    /// hits
+    #[argh(positional)]
+    trace_files: Vec<String>,
+
+    /// the value of a parameter of the description, as `NAME=VALUE`
+    #[argh(option)]
+    param: Vec<String>,
+
+    /// only draw the memory with this name
+    #[argh(option)]
+    memory: Option<String>,
+
+    /// the drawing to emit: svg (the default) for the address grid, or dot
+    /// for a Graphviz graph of the translations
+    #[argh(option, default = "Drawing::Svg")]
+    format: Drawing,
+}
+
+/// The drawings `viz` can emit
+#[derive(PartialEq, Debug)]
+enum Drawing {
+    Svg,
+    Dot,
+}
+
+impl FromStr for Drawing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "svg" => Ok(Drawing::Svg),
+            "dot" | "graphviz" => Ok(Drawing::Dot),
+            _ => Err(format!("unknown drawing `{}`, expected svg or dot", s)),
+        }
+    }
+}
+
+/// reports an error in the inputs and exits
+fn fail(message: String) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(1)
+}
+
+/// the stall budget given by either --max-stalls or --max-slowdown
+fn stall_budget(max_stalls: Option<u64>, max_slowdown: Option<f64>) -> Option<StallBudget> {
+    match (max_stalls, max_slowdown) {
+        (Some(_), Some(_)) => {
+            fail("--max-stalls and --max-slowdown cannot be used together".to_string())
+        }
+        (Some(cycles), None) => Some(StallBudget::Cycles(cycles)),
+        (None, Some(percent)) => Some(StallBudget::Slowdown(percent)),
+        (None, None) => None,
+    }
+}
+
+fn read_file(path: &str) -> String {
+    let mut contents = String::new();
+    File::open(Path::new(path))
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .unwrap_or_else(|e| fail(format!("couldn't read `{}`: {}", path, e)));
+    contents
+}
+
+/// the format of a memory image, from its extension unless one is given
+fn image_format(path: &str, format: Option<ImageFormat>) -> ImageFormat {
+    format.unwrap_or_else(|| {
+        Path::new(path)
+            .extension()
+            .and_then(|x| ImageFormat::from_extension(&x.to_string_lossy()))
+            .unwrap_or_else(|| {
+                fail(format!(
+                    "cannot tell the format of `{}`, give one with --format",
+                    path
+                ))
+            })
+    })
+}
+
+/// reads the values of a memory image of `width` bit words
+fn read_image(path: &str, format: Option<ImageFormat>, width: u64) -> Vec<u64> {
+    let contents =
+        std::fs::read(path).unwrap_or_else(|e| fail(format!("couldn't read `{}`: {}", path, e)));
+    image_format(path, format)
+        .decode(&contents, width)
+        .unwrap_or_else(|e| fail(format!("malformed image `{}`: {}", path, e)))
+}
+
+/// the only memory of a description, failing when it has several
+fn single_memory(path: &str, mut memories: Vec<Component>) -> Component {
+    match memories.len() {
+        0 => fail(format!("`{}` describes no memories", path)),
+        1 => memories.remove(0),
+        count => fail(format!(
+            "`{}` describes {} memories, choose one with --memory",
+            path, count
+        )),
+    }
+}
+
+fn read_trace(trace_file: &str) -> Trace {
+    Trace::parse_trace(read_file(trace_file))
+        .unwrap_or_else(|e| fail(format!("malformed trace file `{}`: {}", trace_file, e)))
+}
+
+/// parses the `NAME=VALUE` parameter values given on the command line
+fn read_parameters(parameters: &[String]) -> Vec<(String, u64)> {
+    parameters
+        .iter()
+        .map(|x| match x.split_once('=') {
+            Some((name, value)) => match value.trim().parse::<u64>() {
+                Ok(value) => (name.trim().to_string(), value),
+                Err(_) => fail(format!("parameter `{}` needs a non-negative number", x)),
+            },
+            None => fail(format!("expected `NAME=VALUE` for parameter `{}`", x)),
+        })
+        .collect()
+}
+
+/// reads the memories of a description file after those of the files it
+/// imports, skipping files which were already read
+fn read_memories(
+    path: &Path,
+    parameters: &[(String, u64)],
+    read: &mut Vec<PathBuf>,
+    declared: &mut Vec<String>,
+) -> Vec<Component> {
+    let display = path.display().to_string();
+    let canonical = path
+        .canonicalize()
+        .unwrap_or_else(|e| fail(format!("couldn't read `{}`: {}", display, e)));
+    if read.contains(&canonical) {
+        return vec![];
+    }
+    read.push(canonical);
+
+    // JSON descriptions hold a memory or a list of memories, as printed by
+    // `synthesize --json`
+    if path.extension().is_some_and(|x| x == "json") {
+        let contents = read_file(&display);
+        // deserializing checks the memories like parsing a description does
+        let memories = if contents.trim_start().starts_with('[') {
+            serde_json::from_str::<Vec<Component>>(&contents)
+        } else {
+            serde_json::from_str::<Component>(&contents).map(|x| vec![x])
+        };
+        return memories
+            .unwrap_or_else(|e| fail(format!("malformed description `{}`: {}", display, e)));
+    }
+
+    let description = AstParser::parse_description(read_file(&display), parameters)
+        .unwrap_or_else(|e| fail(diagnostics::render(e, &display)));
+    declared.extend(description.parameters);
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut memories = vec![];
+    for import in description.imports.iter() {
+        memories.extend(read_memories(
+            &directory.join(import),
+            parameters,
+            read,
+            declared,
+        ));
+    }
+    memories.extend(description.memories);
+    memories
+}
+
+/// reads every memory of a description and its imports, or only the named
+/// one when a name is given
+fn read_description(path: &str, parameters: &[String], name: Option<&str>) -> Vec<Component> {
+    let parameters = read_parameters(parameters);
+    let mut declared = vec![];
+    let memories = read_memories(Path::new(path), &parameters, &mut vec![], &mut declared);
+    for (name, _) in parameters.iter() {
+        if !declared.contains(name) {
+            fail(format!(
+                "no memory of `{}` has a parameter `{}`",
+                path, name
+            ));
+        }
+    }
+    for (idx, memory) in memories.iter().enumerate() {
+        let name = memory.component_name();
+        if memories[..idx].iter().any(|x| x.component_name() == name) {
+            fail(format!(
+                "memory `{}` is described twice, give the memories distinct names",
+                name
+            ));
+        }
+    }
+
+    match name {
+        Some(name) => {
+            let memory = memories
+                .into_iter()
+                .find(|x| x.component_name() == name)
+                .unwrap_or_else(|| fail(format!("`{}` describes no memory `{}`", path, name)));
+            vec![memory]
+        }
+        None => memories,
+    }
 }
 
 fn main() {
@@ -66,58 +489,404 @@ fn main() {
 
     let mut output: Box<dyn Write> = args.output.map_or_else(
         || Box::new(std::io::stdout()) as Box<dyn Write>,
-        |x| Box::new(File::create(&Path::new(&x)).unwrap()),
+        |x| {
+            Box::new(
+                File::create(Path::new(&x))
+                    .unwrap_or_else(|e| fail(format!("couldn't create `{}`: {}", x, e))),
+            )
+        },
     );
 
     match args.command {
         Command::Synthesize(s) => {
-            let mut file = File::open(&Path::new(&s.trace_file)).expect("Couldn't find trace file");
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)
-                .expect("Couldn't read trace file");
+            if s.trace_files.is_empty() {
+                fail("no trace files given".to_string());
+            }
+            let stall_budget = stall_budget(s.max_stalls, s.max_slowdown);
+            let traces = s
+                .trace_files
+                .iter()
+                .map(|x| read_trace(x))
+                .collect::<Vec<_>>();
 
-            let trace = Trace::parse_trace(contents).expect("malformed trace file");
+            let sketch = s.sketch.map(|sketch_file| {
+                AstParser::parse_sketch(read_file(&sketch_file))
+                    .unwrap_or_else(|e| fail(diagnostics::render(e, &sketch_file)))
+            });
+            let options = SolverOptions {
+                stall_budget,
+                stall_cost: s.stall_cost,
+                single_routing: s.single_routing,
+                sketch,
+                cyclic_layouts: s.cyclic_layouts,
+            };
 
-            let comp = solver::solve_trace(&trace);
-            write!(output, "{}", comp.pretty_print()).unwrap();
+            let comp = solver::solve_traces(&traces, &options)
+                .unwrap_or_else(|e| fail(format!("cannot synthesize a memory: {}", e)));
+            if s.json {
+                writeln!(output, "{}", serde_json::to_string_pretty(&comp).unwrap()).unwrap();
+            } else {
+                write!(output, "{}", comp.pretty_print()).unwrap();
+            }
         }
-        Command::Output(OutputCommand { memory_description }) => {
-            let mut file = File::open(&Path::new(&memory_description))
-                .expect("Couldn't find description file");
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)
-                .expect("Couldn't read trace file");
-            let description =
-                AstParser::parse_component(contents).expect("Couldn't parse description");
-            write!(output, "{}", description.emit_calyx_comp()).unwrap();
+        Command::Output(OutputCommand {
+            memory_description,
+            param,
+            memory,
+            target,
+            wrapper,
+            testbench,
+            image,
+            data,
+        }) => {
+            if wrapper && target != Target::Calyx {
+                fail("--wrapper is only supported when emitting Calyx".to_string());
+            }
+            let memories = read_description(&memory_description, &param, memory.as_deref());
+            if !testbench.is_empty() {
+                if target != Target::Calyx || wrapper {
+                    fail("--testbench only emits a Calyx testbench for the memory".to_string());
+                }
+                let comp = single_memory(&memory_description, memories);
+                let traces = testbench.iter().map(|x| read_trace(x)).collect::<Vec<_>>();
+                let image = match image {
+                    Some(path) => read_image(&path, None, comp.width()),
+                    None => (0..comp.size()).collect(),
+                };
+                let images = comp
+                    .bank_images(&image)
+                    .unwrap_or_else(|e| fail(format!("cannot load the image: {}", e)));
+                let bench = comp
+                    .calyx_testbench(&traces, &images)
+                    .unwrap_or_else(|e| fail(format!("cannot run the testbench: {}", e)));
+
+                let data = PathBuf::from(data.unwrap_or_else(|| testbench[0].clone()));
+                let data = if data.extension().is_some_and(|x| x == "data") {
+                    data
+                } else {
+                    data.with_extension("data")
+                };
+                for (path, contents) in [
+                    (data.clone(), &bench.data),
+                    (data.with_extension("expect"), &bench.expect),
+                ] {
+                    std::fs::write(&path, serde_json::to_string_pretty(contents).unwrap())
+                        .unwrap_or_else(|e| {
+                            fail(format!("cannot write `{}`: {}", path.display(), e))
+                        });
+                }
+                write!(output, "{}", bench.program).unwrap();
+                return;
+            }
+            match target {
+                Target::Calyx => write!(
+                    output,
+                    "{}",
+                    Component::emit_calyx_program(&memories, wrapper)
+                )
+                .unwrap(),
+                Target::Verilog => {
+                    let modules = memories
+                        .iter()
+                        .map(|x| x.emit_verilog())
+                        .collect::<Vec<_>>();
+                    write!(output, "{}", modules.join("\n")).unwrap()
+                }
+                Target::Hls => write!(output, "{}", Component::emit_hls_header(&memories)).unwrap(),
+                Target::Firrtl => {
+                    let circuit = Component::emit_firrtl_circuit(&memories).unwrap_or_else(|| {
+                        fail(format!("`{}` describes no memories", memory_description))
+                    });
+                    write!(output, "{}", circuit).unwrap()
+                }
+            }
         }
         Command::Verify(VerifyCommand {
             memory_description,
-            trace_file,
+            trace_files,
+            max_stalls,
+            max_slowdown,
+            param,
+            memory,
         }) => {
-            let mut description_file = File::open(&Path::new(&memory_description))
-                .expect("Couldn't find description file");
-            let mut description = String::new();
-            description_file
-                .read_to_string(&mut description)
-                .expect("Couldn't read description file");
-
-            let mut trace_file =
-                File::open(&Path::new(&trace_file)).expect("Couldn't find trace file");
-            let mut trace = String::new();
-            trace_file
-                .read_to_string(&mut trace)
-                .expect("Couldn't read trace file");
-
-            let trace = Trace::parse_trace(trace).expect("malformed trace file");
-            let comp = AstParser::parse_component(description).expect("Couldn't parse description");
-            let result = comp.vailidate(&trace);
-
-            if result {
-                println!("✅ Validated successfully")
-            } else {
-                println!("❌ Validation failed")
+            if trace_files.is_empty() {
+                fail("no trace files given".to_string());
+            }
+            let stall_budget = stall_budget(max_stalls, max_slowdown);
+            let comp = single_memory(
+                &memory_description,
+                read_description(&memory_description, &param, memory.as_deref()),
+            );
+
+            let mut failed = false;
+            for (phase, trace_file) in trace_files.iter().enumerate() {
+                let trace = read_trace(trace_file);
+                if trace_files.len() > 1 {
+                    println!("phase {} ({}):", phase, trace_file);
+                }
+                match comp.vailidate(&trace, phase, stall_budget.map(|x| x.cycles(&trace))) {
+                    Ok(schedule) => {
+                        println!("✅ Validated successfully");
+                        for stall in schedule.stalls.iter() {
+                            let delayed = stall
+                                .delayed
+                                .iter()
+                                .map(|(bank, delay)| {
+                                    format!("`{}` +{}", comp.banks()[*bank].port_name(*bank), delay)
+                                })
+                                .collect::<Vec<_>>();
+                            println!(
+                                "cycle {} (issued in {}): {} stall cycle(s), delayed {}",
+                                stall.cycle,
+                                stall.issued,
+                                stall.stalls,
+                                delayed.join(", ")
+                            );
+                        }
+                        if !schedule.stalls.is_empty() {
+                            println!("{} stall cycles in total", schedule.stall_cycles());
+                        }
+                        if let Some(last) = schedule.reads.last() {
+                            println!(
+                                "{} reads completed, the last result is available in cycle {} (port `{}`, address {})",
+                                schedule.reads.len(),
+                                last.cycle,
+                                comp.banks()[last.bank].port_name(last.bank),
+                                last.address
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        println!("❌ Validation failed: {}", e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Command::Check(CheckCommand {
+            memory_description,
+            param,
+        }) => {
+            let errors = AstParser::check_description(
+                read_file(&memory_description),
+                &read_parameters(&param),
+            )
+            .unwrap_or_else(|e| fail(diagnostics::render(e, &memory_description)));
+            for error in errors.iter() {
+                println!(
+                    "{}\n",
+                    diagnostics::render(error.clone(), &memory_description)
+                );
+            }
+            if !errors.is_empty() {
+                println!("❌ {} error(s) found", errors.len());
+                std::process::exit(1);
+            }
+            println!("✅ No errors found");
+        }
+        Command::Format(FormatCommand {
+            memory_descriptions,
+            check,
+        }) => {
+            let mut unformatted = false;
+            for path in memory_descriptions.iter() {
+                let source = read_file(path);
+                let unpreservable = AstParser::is_parameterized(&source)
+                    .and_then(|parameterized| {
+                        AstParser::has_comments(&source).map(|comments| parameterized || comments)
+                    })
+                    .unwrap_or_else(|e| fail(diagnostics::render(e, path)));
+                if unpreservable {
+                    fail(format!(
+                        "`{}` uses comments, constants, parameters or `for` blocks, which fmt cannot preserve",
+                        path
+                    ));
+                }
+                let description = AstParser::parse_description(&source, &[])
+                    .unwrap_or_else(|e| fail(diagnostics::render(e, path)));
+                let formatted = description.pretty_print();
+                // never rewrite a file into something meaning anything else
+                match AstParser::parse_description(&formatted, &[]) {
+                    Ok(reparsed)
+                        if reparsed.imports == description.imports
+                            && reparsed.memories == description.memories => {}
+                    _ => fail(format!("formatting `{}` would change its meaning", path)),
+                }
+                if formatted == source {
+                    continue;
+                }
+                if check {
+                    println!("{} is not formatted", path);
+                    unformatted = true;
+                } else {
+                    std::fs::write(path, formatted)
+                        .unwrap_or_else(|e| fail(format!("couldn't write `{}`: {}", path, e)));
+                }
+            }
+            if unformatted {
+                std::process::exit(1);
             }
         }
+        Command::SplitData(SplitDataCommand {
+            memory_description,
+            image,
+            param,
+            memory,
+            format,
+            to,
+            dir,
+        }) => {
+            let comp = single_memory(
+                &memory_description,
+                read_description(&memory_description, &param, memory.as_deref()),
+            );
+            let values = read_image(&image, format, comp.width());
+            let images = comp
+                .bank_images(&values)
+                .unwrap_or_else(|e| fail(format!("cannot split `{}`: {}", image, e)));
+            if to == ImageFormat::Json {
+                let data = serde_json::Value::Object(comp.calyx_data(&images));
+                writeln!(output, "{}", serde_json::to_string_pretty(&data).unwrap()).unwrap();
+                return;
+            }
+            // a file per storage bank, named after the bank's Calyx cell
+            for (idx, contents) in images.iter().enumerate() {
+                let Some(contents) = contents else {
+                    continue;
+                };
+                let path = Path::new(&dir).join(format!(
+                    "{}_bank_{}.{}",
+                    comp.component_name(),
+                    idx,
+                    to.extension()
+                ));
+                std::fs::write(&path, to.encode(contents, comp.width())).unwrap_or_else(|e| {
+                    fail(format!("couldn't write `{}`: {}", path.display(), e))
+                });
+                println!("{}", path.display());
+            }
+        }
+        Command::Viz(VizCommand {
+            memory_description,
+            trace_files,
+            param,
+            memory,
+            format,
+        }) => {
+            let memories = read_description(&memory_description, &param, memory.as_deref());
+            match format {
+                Drawing::Svg => {
+                    let memories = match trace_files.is_empty() {
+                        true => memories,
+                        false => vec![single_memory(&memory_description, memories)],
+                    };
+                    let traces = trace_files
+                        .iter()
+                        .map(|x| read_trace(x))
+                        .collect::<Vec<_>>();
+                    let svg = Component::emit_svg_document(&memories, &traces)
+                        .unwrap_or_else(|e| fail(format!("cannot draw the trace: {}", e)));
+                    write!(output, "{}", svg).unwrap();
+                }
+                Drawing::Dot => {
+                    if !trace_files.is_empty() {
+                        fail("traces are only drawn with --format svg".to_string());
+                    }
+                    write!(output, "{}", Component::emit_dot_graph(&memories)).unwrap();
+                }
+            }
+        }
+        Command::Merge(MergeCommand {
+            memory_description,
+            dumps,
+            param,
+            memory,
+            format,
+            to,
+        }) => {
+            let comp = single_memory(
+                &memory_description,
+                read_description(&memory_description, &param, memory.as_deref()),
+            );
+            let images = match dumps.as_slice() {
+                [] => fail("no bank dumps given".to_string()),
+                // a single JSON file holds every bank in the Calyx data format
+                [path] if image_format(path, format) == ImageFormat::Json => {
+                    let contents = std::fs::read(path)
+                        .unwrap_or_else(|e| fail(format!("couldn't read `{}`: {}", path, e)));
+                    comp.read_calyx_data(&contents)
+                        .unwrap_or_else(|e| fail(format!("malformed dump `{}`: {}", path, e)))
+                }
+                _ => dumps
+                    .iter()
+                    .map(|x| read_image(x, format, comp.width()))
+                    .collect(),
+            };
+            let image = comp
+                .merge_images(&images)
+                .unwrap_or_else(|e| fail(format!("cannot merge the dumps: {}", e)));
+            output.write_all(&to.encode(&image, comp.width())).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_description;
+    use crate::structures::Component;
+
+    /// writes the files into a fresh directory, returning its path
+    fn files(test: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("mem-syn-{}-{}", test, std::process::id()));
+        for (path, contents) in files {
+            let path = directory.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn imports_come_before_the_importing_memories() {
+        let directory = files(
+            "imports",
+            &[
+                (
+                    "top.mem",
+                    "import \"lib/arrays.mem\"
+                     memory top<32, 16> with P = 2 {
+                         for i in 0..P { bank { layout: cyclic(P, i) translation: INPUT / P } }
+                     }",
+                ),
+                // imports are relative to the importing file, and files which
+                // were already read are skipped
+                (
+                    "lib/arrays.mem",
+                    "import \"../top.mem\"
+                     memory weights<8, 4> { bank { layout: [0:4] translation: INPUT } }",
+                ),
+            ],
+        );
+        let top = directory.join("top.mem").display().to_string();
+        let memories = read_description(&top, &["P=4".to_string()], None);
+        let names = memories
+            .iter()
+            .map(|x| x.component_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["weights", "top"]);
+        assert_eq!(memories[1].banks().len(), 4);
+
+        let chosen = read_description(&top, &[], Some("weights"));
+        assert_eq!(chosen, vec![memories[0].clone()]);
+
+        // one Calyx program holds a component per memory, named after it
+        let program = Component::emit_calyx_program(&memories, false);
+        assert!(program.contains("component weights("), "{}", program);
+        assert!(program.contains("component top("), "{}", program);
+        std::fs::remove_dir_all(directory).unwrap();
     }
 }
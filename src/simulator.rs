@@ -0,0 +1,321 @@
+use super::structures::{Component, ValidationError};
+use super::Trace;
+
+/// A read which has finished and delivered its result
+#[derive(Debug, Clone)]
+pub struct Completion {
+    /// the cycle in which the result is available
+    pub cycle: u64,
+    /// the bank (port) of the component which issued the read
+    pub bank: usize,
+    /// the logical address which was read
+    pub address: usize,
+}
+
+/// The pipeline stall caused by bank conflicts within one line of the trace
+#[derive(Debug, Clone)]
+pub struct Stall {
+    /// the cycle of the trace line, as given in the trace
+    pub cycle: u64,
+    /// the cycle the line was actually issued in after earlier stalls
+    pub issued: u64,
+    /// the number of cycles the pipeline is stalled for
+    pub stalls: u64,
+    /// the banks (ports) which were delayed and by how many cycles
+    pub delayed: Vec<(usize, u64)>,
+}
+
+/// The result of running a trace on a component
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    /// every read in the order the results become available
+    pub reads: Vec<Completion>,
+    /// the stalls needed to resolve bank conflicts
+    pub stalls: Vec<Stall>,
+}
+
+impl Schedule {
+    /// the total number of stall cycles in the schedule
+    pub fn stall_cycles(&self) -> u64 {
+        self.stalls.iter().map(|x| x.stalls).sum()
+    }
+}
+
+/// A read which has been issued but has not yet completed
+#[derive(Debug, Clone)]
+struct InFlight {
+    bank: usize,
+    address: usize,
+    issued: u64,
+    completes: u64,
+}
+
+/// Cycle-level model of a component executing a trace. Every storage bank is
+/// pipelined so it accepts one request per cycle and delivers one result per
+/// cycle, with each port's requests completing `latency` cycles after issue.
+/// When stalls are allowed conflicting requests are issued in later cycles and
+/// the rest of the trace is delayed accordingly, otherwise conflicts are errors.
+pub struct Simulator<'a> {
+    component: &'a Component,
+    /// the phase selecting which translation each bank uses
+    phase: usize,
+    allow_stalls: bool,
+    /// the outstanding requests for each storage bank
+    in_flight: Vec<Vec<InFlight>>,
+    /// the total number of cycles the trace has been delayed by
+    delay: u64,
+    schedule: Schedule,
+}
+
+impl<'a> Simulator<'a> {
+    pub fn new(component: &'a Component) -> Self {
+        Self {
+            component,
+            phase: 0,
+            allow_stalls: false,
+            in_flight: vec![vec![]; component.banks().len()],
+            delay: 0,
+            schedule: Schedule::default(),
+        }
+    }
+
+    /// a simulator which resolves bank conflicts by stalling
+    pub fn stalling(component: &'a Component) -> Self {
+        Self {
+            allow_stalls: true,
+            ..Self::new(component)
+        }
+    }
+
+    /// selects the phase whose translations are used
+    pub fn in_phase(mut self, phase: usize) -> Self {
+        self.phase = phase;
+        self
+    }
+
+    /// runs the full trace
+    pub fn run(mut self, trace: &Trace) -> Result<Schedule, ValidationError> {
+        let banks = (0..trace.num_ports())
+            .map(|port| self.component.bank_for_port(trace, port))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (port, bank_idx) in banks.iter().enumerate() {
+            let bank = &self.component.banks()[*bank_idx];
+            if bank.latency() != trace.latency(port) {
+                return Err(ValidationError::LatencyMismatch {
+                    port: bank.port_name(*bank_idx),
+                    expected: trace.latency(port),
+                    found: bank.latency(),
+                });
+            }
+        }
+
+        for (cycle, line) in trace.iter_cycles() {
+            let issued = cycle + self.delay;
+            self.retire(issued);
+
+            let mut delayed = vec![];
+            for (port, request) in line.iter().enumerate() {
+                if let Some(address) = request {
+                    let delay = self.issue(issued, banks[port], *address)?;
+                    if delay != 0 {
+                        delayed.push((banks[port], delay));
+                    }
+                }
+            }
+
+            if let Some(stalls) = delayed.iter().map(|(_, x)| *x).max() {
+                self.delay += stalls;
+                self.schedule.stalls.push(Stall {
+                    cycle,
+                    issued,
+                    stalls,
+                    delayed,
+                });
+            }
+        }
+        self.retire(u64::MAX);
+
+        Ok(self.schedule)
+    }
+
+    /// issues a read no earlier than the given cycle, returning the number of
+    /// cycles it had to be delayed by
+    fn issue(
+        &mut self,
+        cycle: u64,
+        bank_idx: usize,
+        address: usize,
+    ) -> Result<u64, ValidationError> {
+        let bank = &self.component.banks()[bank_idx];
+        if !bank.can_read(address, self.phase) {
+            return Err(ValidationError::Unreachable {
+                cycle,
+                port: bank.port_name(bank_idx),
+                address,
+            });
+        }
+
+        let storage = self.component.storage_of(bank_idx);
+        let mut delay = 0;
+        loop {
+            let issued = cycle + delay;
+            let completes = issued + bank.latency();
+            let conflict = self.in_flight[storage]
+                .iter()
+                .find(|x| x.issued == issued || x.completes == completes);
+
+            match conflict {
+                Some(other) if !self.allow_stalls => {
+                    return Err(ValidationError::Conflict {
+                        cycle,
+                        first: self.component.banks()[other.bank].port_name(other.bank),
+                        second: bank.port_name(bank_idx),
+                    });
+                }
+                Some(_) => delay += 1,
+                None => {
+                    self.in_flight[storage].push(InFlight {
+                        bank: bank_idx,
+                        address,
+                        issued,
+                        completes,
+                    });
+                    return Ok(delay);
+                }
+            }
+        }
+    }
+
+    /// moves every request which completed before the given cycle out of
+    /// flight
+    fn retire(&mut self, cycle: u64) {
+        let mut retired = vec![];
+        for queue in self.in_flight.iter_mut() {
+            let (done, pending) = std::mem::take(queue)
+                .into_iter()
+                .partition(|x| x.completes < cycle);
+            *queue = pending;
+            retired.extend(done);
+        }
+
+        retired.sort_by_key(|x: &InFlight| (x.completes, x.bank));
+        self.schedule
+            .reads
+            .extend(retired.into_iter().map(|x| Completion {
+                cycle: x.completes,
+                bank: x.bank,
+                address: x.address,
+            }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Simulator;
+    use crate::dsl::ast::AstParser;
+    use crate::structures::{Component, ValidationError};
+    use crate::Trace;
+
+    /// two ports reading the same storage, with the given latency
+    fn shared(latency: u64) -> Component {
+        let latency = match latency {
+            0 => String::new(),
+            latency => format!("latency: {}", latency),
+        };
+        let source = format!(
+            "memory<8, 4> {{
+                bank {{ layout: [0:4] translation: INPUT {} }}
+                bank {{ layout: [0:4] translation: INPUT {} storage: 0 }}
+            }}",
+            latency, latency
+        );
+        AstParser::parse_description(source, &[])
+            .unwrap()
+            .memories
+            .remove(0)
+    }
+
+    fn trace(source: &str) -> Trace {
+        Trace::parse_trace(source).unwrap()
+    }
+
+    #[test]
+    fn conflicts_are_errors_without_stalls() {
+        let trace = trace(r#"{"size": 4, "bitwidth": 8, "trace": [[0, 1]]}"#);
+        let error = Simulator::new(&shared(0)).run(&trace).unwrap_err();
+        assert!(matches!(error, ValidationError::Conflict { cycle: 0, .. }));
+    }
+
+    #[test]
+    fn conflicts_stall_the_rest_of_the_trace() {
+        let trace = trace(r#"{"size": 4, "bitwidth": 8, "trace": [[0, 1], [2, null]]}"#);
+        let schedule = Simulator::stalling(&shared(0)).run(&trace).unwrap();
+
+        assert_eq!(schedule.stall_cycles(), 1);
+        let stall = &schedule.stalls[0];
+        assert_eq!((stall.cycle, stall.issued, stall.stalls), (0, 0, 1));
+        assert_eq!(stall.delayed, vec![(1, 1)]);
+
+        // the second line is issued a cycle late
+        let reads = schedule
+            .reads
+            .iter()
+            .map(|x| (x.cycle, x.bank, x.address))
+            .collect::<Vec<_>>();
+        assert_eq!(reads, vec![(0, 0, 0), (1, 1, 1), (2, 0, 2)]);
+    }
+
+    #[test]
+    fn pipelined_reads_do_not_conflict() {
+        let trace = trace(
+            r#"{"size": 4, "bitwidth": 8, "ports": [{"latency": 2}, {"latency": 2}],
+                "trace": [[0, null], [null, 1]]}"#,
+        );
+        let schedule = Simulator::new(&shared(2)).run(&trace).unwrap();
+        let reads = schedule
+            .reads
+            .iter()
+            .map(|x| (x.cycle, x.bank))
+            .collect::<Vec<_>>();
+        assert_eq!(reads, vec![(2, 0), (3, 1)]);
+    }
+
+    #[test]
+    fn completing_together_conflicts() {
+        let memory = AstParser::parse_description(
+            "memory<8, 4> {
+                bank { layout: [0:4] translation: INPUT latency: 1 }
+                bank { layout: [0:4] translation: INPUT storage: 0 }
+            }",
+            &[],
+        )
+        .unwrap()
+        .memories
+        .remove(0);
+        // the slow read of cycle 0 and the fast read of cycle 1 both
+        // complete in cycle 1
+        let trace = trace(
+            r#"{"size": 4, "bitwidth": 8, "ports": [{"latency": 1}, {}],
+                "trace": [[0, null], [null, 1]]}"#,
+        );
+        assert!(Simulator::new(&memory).run(&trace).is_err());
+        let schedule = Simulator::stalling(&memory).run(&trace).unwrap();
+        assert_eq!(schedule.stall_cycles(), 1);
+    }
+
+    #[test]
+    fn latencies_must_match_the_trace() {
+        let trace =
+            trace(r#"{"size": 4, "bitwidth": 8, "ports": [{"latency": 1}], "trace": [[0]]}"#);
+        let error = Simulator::new(&shared(0)).run(&trace).unwrap_err();
+        assert!(matches!(
+            error,
+            ValidationError::LatencyMismatch {
+                expected: 1,
+                found: 0,
+                ..
+            }
+        ));
+    }
+}
@@ -1,77 +1,438 @@
-use super::dsl::ast::AstParser;
+use super::dsl::ast::{AstParser, Hole, Sketch};
 use super::structures::*;
 use super::Trace;
+use std::fmt::{self, Display};
 use z3::{
     ast::{self as z3_ast, Ast, Bool, Datatype, Int, BV},
     DatatypeAccessor, DatatypeBuilder, DatatypeSort, Sort,
 };
 
+/// Limits on how many cycles the synthesized memory may stall for
+#[derive(Debug, Clone, Copy)]
+pub enum StallBudget {
+    /// at most this many stall cycles in total
+    Cycles(u64),
+    /// at most this percentage of the trace's cycles
+    Slowdown(f64),
+}
+
+impl StallBudget {
+    /// the number of stall cycles allowed for the given trace
+    pub fn cycles(&self, trace: &Trace) -> u64 {
+        match self {
+            StallBudget::Cycles(c) => *c,
+            StallBudget::Slowdown(percent) => {
+                (trace.cycle_count() as f64 * percent / 100.0).floor() as u64
+            }
+        }
+    }
+}
+
+/// Reasons no memory can be synthesized for the traces
+#[derive(Debug, Clone)]
+pub enum SynthesisError {
+    /// a trace describes a memory of a different size or width than the
+    /// first one
+    MismatchedTraces {
+        trace: usize,
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    /// the sketch describes a memory of a different size or width than the
+    /// traces
+    SketchShape {
+        expected: (usize, usize),
+        found: (u64, u64),
+    },
+    /// the sketch has a different number of banks than the traces have ports
+    SketchPorts { expected: usize, found: usize },
+    /// a bank of the sketch has translations for a different number of
+    /// phases than are synthesized
+    SketchPhases {
+        bank: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// no memory completing the sketch serves the traces
+    SketchUnsat,
+    /// no memory serves the traces
+    Unsat,
+    /// every candidate sharing banks stalled for longer than the budget
+    /// allows once simulated
+    StallSchedule { candidates: usize },
+    /// the solver gave up, for the given reason
+    Unknown(String),
+}
+
+impl Display for SynthesisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SynthesisError::MismatchedTraces {
+                trace,
+                expected,
+                found,
+            } => write!(
+                f,
+                "trace {} describes a memory of {} entries of {} bits, but the first trace \
+                 one of {} entries of {} bits",
+                trace + 1,
+                found.0,
+                found.1,
+                expected.0,
+                expected.1
+            ),
+            SynthesisError::SketchShape { expected, found } => write!(
+                f,
+                "the sketch describes a memory of {} entries of {} bits, but the traces one \
+                 of {} entries of {} bits",
+                found.0, found.1, expected.0, expected.1
+            ),
+            SynthesisError::SketchPorts { expected, found } => write!(
+                f,
+                "the sketch has {} banks, but the traces have {} ports",
+                found, expected
+            ),
+            SynthesisError::SketchPhases {
+                bank,
+                expected,
+                found,
+            } => write!(
+                f,
+                "bank {} of the sketch has translations for {} phases, but {} are synthesized",
+                bank, found, expected
+            ),
+            SynthesisError::SketchUnsat => {
+                write!(f, "no completion of the sketch serves the traces")
+            }
+            SynthesisError::Unsat => write!(f, "no memory serves the traces"),
+            SynthesisError::StallSchedule { candidates } => write!(
+                f,
+                "the stall schedules of the {} best memories exceed the budget",
+                candidates
+            ),
+            SynthesisError::Unknown(reason) => {
+                write!(
+                    f,
+                    "the solver could not decide whether a memory exists: {}",
+                    reason
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SolverOptions {
+    /// when present, ports may share a bank even when their accesses collide,
+    /// with the collisions resolved by stalling
+    pub stall_budget: Option<StallBudget>,
+    /// the cost of a single stall cycle relative to the partition cost
+    pub stall_cost: u64,
+    /// when solving several traces, use one translation for every phase
+    /// instead of one translation per phase
+    pub single_routing: bool,
+    /// a partial description whose holes are left for the solver to fill
+    pub sketch: Option<Sketch>,
+    /// also search cyclic and block-cyclic layouts, which is considerably
+    /// slower than searching ranges alone
+    pub cyclic_layouts: bool,
+}
+
+impl Default for SolverOptions {
+    fn default() -> Self {
+        Self {
+            stall_budget: None,
+            stall_cost: 1,
+            single_routing: false,
+            sketch: None,
+            cyclic_layouts: false,
+        }
+    }
+}
+
 struct ProblemContext<'a> {
     banks: Vec<Datatype<'a>>,
-    routing_fns: Vec<Datatype<'a>>,
+    /// the translation of each bank, indexed by phase then bank. A single
+    /// phase is shared by all traces
+    routing_fns: Vec<Vec<Datatype<'a>>>,
+    /// the index of the bank whose storage each port reads from
+    storage: Vec<Int<'a>>,
+    /// the layouts a sketch gives which the partition datatype cannot hold,
+    /// such as sets, used as they are
+    fixed_layouts: Vec<Option<TopLevelMemoryLayout>>,
+    /// the translations a sketch gives in full, indexed like `routing_fns`.
+    /// These are applied to the requested addresses directly
+    fixed_routing: Vec<Vec<Option<TopLevelRoutingProgram>>>,
     addr_size: u32,
     terminals_prog: DatatypeSort<'a>,
     partition_type: DatatypeSort<'a>,
+    /// whether banks may use cyclic layouts rather than only ranges
+    cyclic_layouts: bool,
 }
 
 impl<'a> ProblemContext<'a> {
-    fn partition_cost(&self) -> Int<'a> {
+    /// the number of banks which own their storage
+    fn bank_count(&self) -> Int<'a> {
         let ctx = self.banks[0].get_ctx();
+        let owners = self
+            .storage
+            .iter()
+            .enumerate()
+            .map(|(idx, storage)| {
+                storage
+                    ._eq(&Int::from_u64(ctx, idx as u64))
+                    .ite(&Int::from_u64(ctx, 1), &Int::from_u64(ctx, 0))
+            })
+            .collect::<Vec<_>>();
+        Int::add(ctx, &owners.iter().collect::<Vec<_>>())
+    }
 
-        self.banks
+    /// restricts each port to either owning its storage or sharing the
+    /// storage of a lower indexed bank with an identical layout. Ports which
+    /// overlap in time may not share unless stalls are allowed, in which case
+    /// only ports with the same latency can be serialized by stalling
+    fn storage_conditions(&self, traces: &[Trace], allow_stalls: bool) -> Bool<'a> {
+        let ctx = self.banks[0].get_ctx();
+        let mut acc = Bool::from_bool(ctx, true);
+        for (idx, storage) in self.storage.iter().enumerate() {
+            acc &= storage.ge(&Int::from_u64(ctx, 0)) & storage.le(&Int::from_u64(ctx, idx as u64));
+            for other in 0..idx {
+                let shares = storage._eq(&Int::from_u64(ctx, other as u64));
+                acc &= shares.implies(
+                    &(self.storage[other]._eq(&Int::from_u64(ctx, other as u64))
+                        & self.same_layout(idx, other)),
+                );
+                let conflicting = traces.iter().any(|trace| {
+                    let stallable = allow_stalls && trace.latency(idx) == trace.latency(other);
+                    idx < trace.num_ports()
+                        && other < trace.num_ports()
+                        && trace.ports_overlap(idx, other)
+                        && !stallable
+                });
+                if conflicting {
+                    acc &= !storage._eq(&self.storage[other]);
+                }
+            }
+        }
+        acc
+    }
+
+    /// the total number of stall cycles along with the conditions bounding
+    /// it. A line stalls for one cycle less than the largest number of its
+    /// requests which share a single storage bank. Requests delayed into a
+    /// later line's cycles may collide again in the simulator, so this is only
+    /// a lower bound
+    fn stall_conditions(&self, trace: &Trace, phase: usize) -> (Int<'a>, Bool<'a>) {
+        let ctx = self.banks[0].get_ctx();
+        let mut acc = Bool::from_bool(ctx, true);
+        let mut total = Int::from_u64(ctx, 0);
+
+        let lines = trace
             .iter()
-            .map(|bank| {
-                let start = self.partition_type.variants[0].accessors[0]
-                    .apply(&[bank])
-                    .as_int()
-                    .unwrap();
+            .enumerate()
+            .filter(|(_, line)| line.iter().filter(|x| x.is_some()).count() > 1);
 
-                let finish = self.partition_type.variants[0].accessors[1]
-                    .apply(&[bank])
-                    .as_int()
-                    .unwrap();
+        for (line_idx, line) in lines {
+            let stalls = Int::new_const(ctx, format!("stalls_{}_{}", phase, line_idx));
+            acc &= stalls.ge(&Int::from_u64(ctx, 0));
+            for owner in 0..self.storage.len() {
+                let users = line
+                    .iter()
+                    .zip(self.storage.iter())
+                    .filter(|(request, _)| request.is_some())
+                    .map(|(_, storage)| {
+                        storage
+                            ._eq(&Int::from_u64(ctx, owner as u64))
+                            .ite(&Int::from_u64(ctx, 1), &Int::from_u64(ctx, 0))
+                    })
+                    .collect::<Vec<_>>();
+                let users = Int::add(ctx, &users.iter().collect::<Vec<_>>());
+                acc &= (&stalls + &Int::from_u64(ctx, 1)).ge(&users);
+            }
+            total += stalls;
+        }
+        (total, acc)
+    }
 
-                let stride = self.partition_type.variants[0].accessors[2]
-                    .apply(&[bank])
-                    .as_int()
-                    .unwrap();
-                ((finish - start) / stride) + Int::from_u64(ctx, 1)
+    /// whether the bank uses the given variant of the partition datatype:
+    /// 0 for ranges, 1 for cyclic and 2 for block-cyclic layouts
+    fn layout_is(&self, bank: &Datatype<'a>, variant: usize) -> Bool<'a> {
+        self.partition_type.variants[variant]
+            .tester
+            .apply(&[bank])
+            .as_bool()
+            .unwrap()
+    }
+
+    fn layout_field(&self, bank: &Datatype<'a>, variant: usize, field: usize) -> Int<'a> {
+        self.partition_type.variants[variant].accessors[field]
+            .apply(&[bank])
+            .as_int()
+            .unwrap()
+    }
+
+    /// whether two banks have the same layout
+    fn same_layout(&self, idx: usize, other: usize) -> Bool<'a> {
+        match (&self.fixed_layouts[idx], &self.fixed_layouts[other]) {
+            (None, None) => self.banks[idx]._eq(&self.banks[other]),
+            (layout, other) => Bool::from_bool(self.banks[idx].get_ctx(), layout == other),
+        }
+    }
+
+    /// the number of addresses held by a bank in a memory of the given size
+    fn layout_size(&self, bank: &Datatype<'a>, size: usize) -> Int<'a> {
+        let ctx = bank.get_ctx();
+        let zero = Int::from_u64(ctx, 0);
+        let one = Int::from_u64(ctx, 1);
+        let size = Int::from_u64(ctx, size as u64);
+
+        let start = self.layout_field(bank, 0, 0);
+        let finish = self.layout_field(bank, 0, 1);
+        let stride = self.layout_field(bank, 0, 2);
+        // the range ends before `finish`
+        let range = (finish - start + &stride - &one) / stride;
+        if !self.cyclic_layouts {
+            return range;
+        }
+
+        let factor = self.layout_field(bank, 1, 0);
+        let offset = self.layout_field(bank, 1, 1);
+        let cyclic = (&size - offset + &factor - &one) / factor;
+
+        let block = self.layout_field(bank, 2, 0);
+        let factor = self.layout_field(bank, 2, 1);
+        let offset = self.layout_field(bank, 2, 2);
+        let period = &block * &factor;
+        let partial = size.modulo(&period) - offset * &block;
+        let partial = partial.lt(&zero).ite(&zero, &partial);
+        let partial = partial.gt(&block).ite(&block, &partial);
+        let block_cyclic = (size / period) * block + partial;
+
+        self.layout_is(bank, 0)
+            .ite(&range, &self.layout_is(bank, 1).ite(&cyclic, &block_cyclic))
+    }
+
+    fn partition_cost(&self, size: usize) -> Int<'a> {
+        let ctx = self.banks[0].get_ctx();
+
+        self.banks
+            .iter()
+            .zip(self.storage.iter())
+            .enumerate()
+            .map(|(idx, (bank, storage))| {
+                let bank_size = match &self.fixed_layouts[idx] {
+                    Some(layout) => Int::from_u64(ctx, layout.size() as u64),
+                    None => self.layout_size(bank, size),
+                };
+                // shared storage is only paid for once
+                storage
+                    ._eq(&Int::from_u64(ctx, idx as u64))
+                    .ite(&bank_size, &Int::from_u64(ctx, 1))
             })
             .fold(Int::from_u64(ctx, 1), |acc, x| acc * x)
     }
 
     fn partition_conditions(&self, size: usize) -> Bool<'a> {
         let ctx = self.banks[0].get_ctx();
+        let zero = Int::from_u64(ctx, 0);
+        let size = Int::from_u64(ctx, size as u64);
         let mut acc = Bool::from_bool(ctx, true);
         for bank in self.banks.iter() {
-            let test = self.partition_type.variants[0]
-                .tester
-                .apply(&[bank])
-                .as_bool()
-                .unwrap();
+            let start = self.layout_field(bank, 0, 0);
+            let finish = self.layout_field(bank, 0, 1);
+            let stride = self.layout_field(bank, 0, 2);
+            acc &= self.layout_is(bank, 0).implies(
+                &(start.ge(&zero) & finish.gt(&start) & finish.le(&size) & stride.gt(&zero)),
+            );
+            if !self.cyclic_layouts {
+                continue;
+            }
 
-            let start = self.partition_type.variants[0].accessors[0]
-                .apply(&[bank])
-                .as_int()
-                .unwrap();
+            let factor = self.layout_field(bank, 1, 0);
+            let offset = self.layout_field(bank, 1, 1);
+            acc &= self.layout_is(bank, 1).implies(
+                &(factor.gt(&zero) & factor.le(&size) & offset.ge(&zero) & offset.lt(&factor)),
+            );
 
-            let finish = self.partition_type.variants[0].accessors[1]
-                .apply(&[bank])
-                .as_int()
-                .unwrap();
+            let block = self.layout_field(bank, 2, 0);
+            let factor = self.layout_field(bank, 2, 1);
+            let offset = self.layout_field(bank, 2, 2);
+            acc &= self.layout_is(bank, 2).implies(
+                &(block.gt(&zero)
+                    & block.le(&size)
+                    & factor.gt(&zero)
+                    & factor.le(&size)
+                    & offset.ge(&zero)
+                    & offset.lt(&factor)),
+            );
+        }
+        acc
+    }
 
-            let stride = self.partition_type.variants[0].accessors[2]
-                .apply(&[bank])
-                .as_int()
-                .unwrap();
+    /// fixes the parts of the layouts and translations held in the
+    /// datatypes which the sketch gives, along with the storage of its banks
+    fn sketch_conditions(&self, sketch: &Sketch) -> Bool<'a> {
+        let ctx = self.banks[0].get_ctx();
+        let mut acc = Bool::from_bool(ctx, true);
+        for (idx, bank_sketch) in sketch.memory.banks().iter().enumerate() {
+            let bank = &self.banks[idx];
+            let layout = match bank_sketch.layout().layouts() {
+                [layout] if !sketch.has(Hole::Layout { bank: idx }) => Some(layout),
+                _ => None,
+            };
+            let fields = match layout {
+                Some(MemoryLayout::Range {
+                    start,
+                    finish,
+                    stride,
+                }) => Some((0, vec![start, finish, stride])),
+                Some(MemoryLayout::Cyclic { factor, offset, .. }) => {
+                    Some((1, vec![factor, offset]))
+                }
+                Some(MemoryLayout::BlockCyclic {
+                    block,
+                    factor,
+                    offset,
+                    ..
+                }) => Some((2, vec![block, factor, offset])),
+                _ => None,
+            };
+            if let Some((variant, values)) = fields {
+                acc &= self.layout_is(bank, variant);
+                for (field, value) in values.into_iter().enumerate() {
+                    if !sketch.has(Hole::LayoutField { bank: idx, field }) {
+                        acc &= self
+                            .layout_field(bank, variant, field)
+                            ._eq(&Int::from_u64(ctx, *value as u64));
+                    }
+                }
+            }
 
-            let bound_conditions = test.implies(
-                &(start.ge(&Int::from_u64(ctx, 0))
-                    & finish.gt(&start)
-                    & finish.le(&Int::from_u64(ctx, size as u64))
-                    & stride.gt(&Int::from_u64(ctx, 0))),
-            );
-            acc &= bound_conditions
+            // translations with their number left open keep their operation
+            for (phase, routing_fn) in self.routing_fns.iter().enumerate() {
+                let shared = phase.min(bank_sketch.phases() - 1);
+                if !sketch.has(Hole::TranslationValue {
+                    bank: idx,
+                    phase: shared,
+                }) {
+                    continue;
+                }
+                let variant = match bank_sketch.routing(phase) {
+                    TopLevelRoutingProgram::Prog(SequenceRoutingProg::Prog(step)) => {
+                        terminal_variant(step)
+                    }
+                    _ => unreachable!("holes are only left in single steps"),
+                };
+                acc &= self.terminals_prog.variants[variant]
+                    .tester
+                    .apply(&[&routing_fn[idx]])
+                    .as_bool()
+                    .unwrap();
+            }
+
+            if let Some(storage) = bank_sketch.storage() {
+                acc &= self.storage[idx]._eq(&Int::from_u64(ctx, storage as u64));
+            }
         }
         acc
     }
@@ -80,37 +441,61 @@ impl<'a> ProblemContext<'a> {
         &self,
         input_index: &z3_ast::Int<'a>,
         bank_idx: usize,
+        phase: usize,
     ) -> (Bool<'a>, z3_ast::Int<'a>) {
         let ctx = input_index.get_ctx();
-        let (out, cond) =
-            self.apply_terminal(input_index, bank_idx, &self.routing_fns[bank_idx], ctx);
-
-        let bank = &self.banks[bank_idx];
+        let phase = phase.min(self.routing_fns.len() - 1);
+        let (out, cond) = match &self.fixed_routing[phase][bank_idx] {
+            Some(routing) => match routing.checked_eval(input_index.as_u64().unwrap()) {
+                Some(index) => (Int::from_u64(ctx, index), Bool::from_bool(ctx, true)),
+                None => (Int::from_u64(ctx, 0), Bool::from_bool(ctx, false)),
+            },
+            None => self.apply_terminal(
+                input_index,
+                bank_idx,
+                phase,
+                &self.routing_fns[phase][bank_idx],
+                ctx,
+            ),
+        };
 
-        // let test = self.partition_type.variants[0]
-        //     .tester
-        //     .apply(&[bank])
-        //     .as_bool()
-        //     .unwrap();
+        if let Some(layout) = &self.fixed_layouts[bank_idx] {
+            let addresses = layout.addresses();
+            let index_actual = addresses.iter().enumerate().rev().fold(
+                Int::from_u64(ctx, 0),
+                |acc, (idx, address)| {
+                    out._eq(&Int::from_u64(ctx, idx as u64))
+                        .ite(&Int::from_u64(ctx, *address as u64), &acc)
+                },
+            );
+            let validity = out.lt(&Int::from_u64(ctx, addresses.len() as u64));
+            return ((cond & validity).simplify(), index_actual);
+        }
 
-        let start = self.partition_type.variants[0].accessors[0]
-            .apply(&[bank])
-            .as_int()
-            .unwrap();
+        let bank = &self.banks[bank_idx];
 
-        let finish = self.partition_type.variants[0].accessors[1]
-            .apply(&[bank])
-            .as_int()
-            .unwrap();
+        let start = self.layout_field(bank, 0, 0);
+        let finish = self.layout_field(bank, 0, 1);
+        let stride = self.layout_field(bank, 0, 2);
+        let range = start + (&out * stride);
+        if !self.cyclic_layouts {
+            return ((cond & range.lt(&finish)).simplify(), range);
+        }
 
-        let stride = self.partition_type.variants[0].accessors[2]
-            .apply(&[bank])
-            .as_int()
-            .unwrap();
+        let factor = self.layout_field(bank, 1, 0);
+        let offset = self.layout_field(bank, 1, 1);
+        let cyclic = &out * factor + offset;
 
-        let index_actual = start + (out * stride);
+        let block = self.layout_field(bank, 2, 0);
+        let factor = self.layout_field(bank, 2, 1);
+        let offset = self.layout_field(bank, 2, 2);
+        let block_cyclic = (&out / &block) * &block * factor + offset * &block + out.modulo(&block);
 
-        let validity = index_actual.lt(&finish);
+        // cyclic layouts end with the memory, which the caller checks
+        let validity = self.layout_is(bank, 0).implies(&range.lt(&finish));
+        let index_actual = self
+            .layout_is(bank, 0)
+            .ite(&range, &self.layout_is(bank, 1).ite(&cyclic, &block_cyclic));
 
         ((cond & validity).simplify(), index_actual)
     }
@@ -119,16 +504,34 @@ impl<'a> ProblemContext<'a> {
         &self,
         input_index: &z3_ast::Int<'a>,
         bank_idx: usize,
+        phase: usize,
         datatype: &Datatype<'a>,
         ctx: &'a z3::Context,
     ) -> (Int<'a>, Bool<'a>) {
         assert_eq!(datatype.get_sort(), self.terminals_prog.sort);
         let out = Int::new_const(
             ctx,
-            format!("out_{}_{}", bank_idx, input_index.as_u64().unwrap()),
+            format!(
+                "out_{}_{}_{}",
+                phase,
+                bank_idx,
+                input_index.as_u64().unwrap()
+            ),
         );
         let in_bv = BV::from_int(input_index, self.addr_size);
         let out_bv = BV::from_int(&out, self.addr_size);
+        let variant = |idx: usize| {
+            let test = self.terminals_prog.variants[idx]
+                .tester
+                .apply(&[datatype])
+                .as_bool()
+                .unwrap();
+            let held_int = self.terminals_prog.variants[idx].accessors[0]
+                .apply(&[datatype])
+                .as_bv()
+                .unwrap();
+            (test, held_int)
+        };
         let bools = vec![
             // No Op
             {
@@ -158,7 +561,7 @@ impl<'a> ProblemContext<'a> {
 
                 (!test) | shifted._eq(&out)
             },
-            // ADD
+            // ADD, which may not overflow the address
             {
                 let test = self.terminals_prog.variants[2]
                     .tester
@@ -170,9 +573,11 @@ impl<'a> ProblemContext<'a> {
                     .as_bv()
                     .unwrap();
 
-                (!test) | ((&held_int + &in_bv)._eq(&out_bv))
+                (!test)
+                    | ((&held_int + &in_bv)._eq(&out_bv)
+                        & in_bv.bvadd_no_overflow(&held_int, false))
             },
-            // SUB PV
+            // SUB PV, which may not underflow
             {
                 let test = self.terminals_prog.variants[3]
                     .tester
@@ -184,9 +589,9 @@ impl<'a> ProblemContext<'a> {
                     .as_bv()
                     .unwrap();
 
-                (!test) | ((&in_bv - &held_int)._eq(&out_bv))
+                (!test) | ((&in_bv - &held_int)._eq(&out_bv) & in_bv.bvuge(&held_int))
             },
-            // SUB VP
+            // SUB VP, which may not underflow
             {
                 let test = self.terminals_prog.variants[4]
                     .tester
@@ -198,7 +603,7 @@ impl<'a> ProblemContext<'a> {
                     .as_bv()
                     .unwrap();
 
-                (!test) | (&(&held_int - &in_bv)._eq(&out_bv))
+                (!test) | ((&held_int - &in_bv)._eq(&out_bv) & held_int.bvuge(&in_bv))
             },
             // CONST
             {
@@ -214,6 +619,46 @@ impl<'a> ProblemContext<'a> {
 
                 (!test) | held_int._eq(&out_bv)
             },
+            // LSHIFT, which may not shift bits out of the address
+            {
+                let (test, held_int) = variant(6);
+                let shifted = in_bv.bvshl(&held_int);
+                (!test)
+                    | (shifted._eq(&out_bv)
+                        & shifted.bvlshr(&held_int)._eq(&in_bv)
+                        & held_int.bvult(&BV::from_u64(ctx, self.addr_size as u64, self.addr_size)))
+            },
+            // AND
+            {
+                let (test, held_int) = variant(7);
+                (!test) | in_bv.bvand(&held_int)._eq(&out_bv)
+            },
+            // XOR
+            {
+                let (test, held_int) = variant(8);
+                (!test) | in_bv.bvxor(&held_int)._eq(&out_bv)
+            },
+            // MOD
+            {
+                let (test, held_int) = variant(9);
+                (!test)
+                    | (in_bv.bvurem(&held_int)._eq(&out_bv)
+                        & !held_int._eq(&BV::from_u64(ctx, 0, self.addr_size)))
+            },
+            // DIV
+            {
+                let (test, held_int) = variant(10);
+                (!test)
+                    | (in_bv.bvudiv(&held_int)._eq(&out_bv)
+                        & !held_int._eq(&BV::from_u64(ctx, 0, self.addr_size)))
+            },
+            // MUL, which may not overflow the address
+            {
+                let (test, held_int) = variant(11);
+                (!test)
+                    | (in_bv.bvmul(&held_int)._eq(&out_bv)
+                        & in_bv.bvmul_no_overflow(&held_int, false))
+            },
         ];
 
         let b = Bool::and(ctx, &bools.iter().collect::<Vec<_>>());
@@ -221,24 +666,87 @@ impl<'a> ProblemContext<'a> {
         (out.clone(), (b & out_bv.to_int(false)._eq(&out)).simplify())
     }
 
-    fn extract_description(&self, model: &z3::Model, trace: &Trace) -> Component {
+    /// requires some port to use a different storage than in the model
+    fn other_storage(&self, model: &z3::Model<'a>) -> Bool<'a> {
+        let ctx = self.banks[0].get_ctx();
+        let differs = self
+            .storage
+            .iter()
+            .map(|storage| !storage._eq(&model.eval(storage, true).unwrap()))
+            .collect::<Vec<_>>();
+        Bool::or(ctx, &differs.iter().collect::<Vec<_>>())
+    }
+
+    fn extract_description(
+        &self,
+        model: &z3::Model,
+        traces: &[Trace],
+        sketch: Option<&Sketch>,
+    ) -> Component {
         let banks = self
             .banks
             .iter()
-            .zip(self.routing_fns.iter())
-            .map(|(bank, route)| {
-                let memory_layout =
-                    AstParser::parse_partition(format!("{:?}", model.eval(bank, true).unwrap()))
-                        .unwrap();
-                let routing = AstParser::parse_z3_address_translation(format!(
-                    "{:?}",
-                    model.eval(route, true).unwrap()
-                ))
-                .unwrap();
-                MemoryBank::new(routing, memory_layout)
+            .zip(self.storage.iter())
+            .enumerate()
+            .map(|(idx, (bank, storage))| {
+                let memory_layout = match &self.fixed_layouts[idx] {
+                    Some(layout) => layout.clone(),
+                    None => {
+                        AstParser::parse_partition(format!("{:?}", model.eval(bank, true).unwrap()))
+                            .unwrap()
+                            .with_memory_size(traces[0].size())
+                    }
+                };
+                let routing = self
+                    .routing_fns
+                    .iter()
+                    .zip(self.fixed_routing.iter())
+                    .map(|(phase, fixed)| match &fixed[idx] {
+                        Some(routing) => routing.clone(),
+                        None => AstParser::parse_z3_address_translation(format!(
+                            "{:?}",
+                            model.eval(&phase[idx], true).unwrap()
+                        ))
+                        .unwrap(),
+                    })
+                    .collect::<Vec<_>>();
+                let storage = model
+                    .eval(storage, true)
+                    .and_then(|x| x.as_u64())
+                    .map(|x| x as usize)
+                    .filter(|x| *x != idx);
+                let bank = MemoryBank::new(routing, memory_layout)
+                    .expect("every phase has a translation")
+                    .with_storage(storage);
+                match sketch.map(|x| &x.memory.banks()[idx]) {
+                    Some(given) => bank
+                        .with_name(given.name().map(String::from))
+                        .with_latency(given.latency()),
+                    None => bank,
+                }
             })
             .collect::<Vec<_>>();
-        Component::from_trace(banks, trace)
+        Component::from_traces(banks, traces)
+            .with_name(sketch.and_then(|x| x.memory.name()).map(String::from))
+    }
+}
+
+/// the variant of the translation datatype holding the step
+fn terminal_variant(step: &TerminalRoutingProgram) -> usize {
+    match step {
+        TerminalRoutingProgram::Noop => 0,
+        TerminalRoutingProgram::RShift(_) => 1,
+        TerminalRoutingProgram::Add(_) => 2,
+        TerminalRoutingProgram::SubPortVal(_) => 3,
+        TerminalRoutingProgram::SubValPort(_) => 4,
+        TerminalRoutingProgram::Constant(_) => 5,
+        TerminalRoutingProgram::LShift(_) => 6,
+        TerminalRoutingProgram::And(_) => 7,
+        TerminalRoutingProgram::Xor(_) => 8,
+        TerminalRoutingProgram::Mod(_) => 9,
+        TerminalRoutingProgram::Div(_) => 10,
+        TerminalRoutingProgram::Mul(_) => 11,
+        TerminalRoutingProgram::Lookup(_) => unreachable!("lookups have no datatype variant"),
     }
 }
 
@@ -277,62 +785,231 @@ fn terminal_routing_program(ctx: &z3::Context, size: u32) -> z3::DatatypeSort {
                 DatatypeAccessor::Sort(Sort::bitvector(ctx, size)),
             )],
         )
+        .variant(
+            "LShift",
+            vec![(
+                "lshift_v",
+                DatatypeAccessor::Sort(Sort::bitvector(ctx, size)),
+            )],
+        )
+        .variant(
+            "BitAnd",
+            vec![(
+                "bitand_v",
+                DatatypeAccessor::Sort(Sort::bitvector(ctx, size)),
+            )],
+        )
+        .variant(
+            "Xor",
+            vec![("xor_v", DatatypeAccessor::Sort(Sort::bitvector(ctx, size)))],
+        )
+        .variant(
+            "Mod",
+            vec![("mod_v", DatatypeAccessor::Sort(Sort::bitvector(ctx, size)))],
+        )
+        .variant(
+            "Div",
+            vec![("div_v", DatatypeAccessor::Sort(Sort::bitvector(ctx, size)))],
+        )
+        .variant(
+            "Mul",
+            vec![("mul_v", DatatypeAccessor::Sort(Sort::bitvector(ctx, size)))],
+        )
         .finish();
     terminal
 }
 
-fn terminal_partition(ctx: &z3::Context) -> z3::DatatypeSort {
-    let part = DatatypeBuilder::new(ctx, "Partition")
-        .variant(
-            "Range",
-            vec![
-                ("start_v", DatatypeAccessor::Sort(Sort::int(ctx))),
-                ("end_v", DatatypeAccessor::Sort(Sort::int(ctx))),
-                ("stride_v", DatatypeAccessor::Sort(Sort::int(ctx))),
-            ],
-        )
-        .finish();
-    part
+/// the partition datatype, with the cyclic variants only when they are
+/// searched since they slow down solving even when unused
+fn terminal_partition(ctx: &z3::Context, cyclic_layouts: bool) -> z3::DatatypeSort {
+    let part = DatatypeBuilder::new(ctx, "Partition").variant(
+        "Range",
+        vec![
+            ("start_v", DatatypeAccessor::Sort(Sort::int(ctx))),
+            ("end_v", DatatypeAccessor::Sort(Sort::int(ctx))),
+            ("stride_v", DatatypeAccessor::Sort(Sort::int(ctx))),
+        ],
+    );
+    if !cyclic_layouts {
+        return part.finish();
+    }
+    part.variant(
+        "Cyclic",
+        vec![
+            ("factor_v", DatatypeAccessor::Sort(Sort::int(ctx))),
+            ("offset_v", DatatypeAccessor::Sort(Sort::int(ctx))),
+        ],
+    )
+    .variant(
+        "BlockCyclic",
+        vec![
+            ("block_v", DatatypeAccessor::Sort(Sort::int(ctx))),
+            ("block_factor_v", DatatypeAccessor::Sort(Sort::int(ctx))),
+            ("block_offset_v", DatatypeAccessor::Sort(Sort::int(ctx))),
+        ],
+    )
+    .finish()
 }
 
-pub fn solve_trace(input: &Trace) -> Component {
+/// the number of candidates whose stall schedule exceeds the budget before
+/// synthesis gives up
+const STALL_CANDIDATES: usize = 16;
+
+/// synthesizes a single set of banks serving every trace, with each trace
+/// being a separate phase of the memory's use
+pub fn solve_traces(
+    inputs: &[Trace],
+    options: &SolverOptions,
+) -> Result<Component, SynthesisError> {
+    let input = &inputs[0];
+    let shape = |x: &Trace| (x.size(), x.bitwidth());
+    if let Some(trace) = inputs.iter().position(|x| shape(x) != shape(input)) {
+        return Err(SynthesisError::MismatchedTraces {
+            trace,
+            expected: shape(input),
+            found: shape(&inputs[trace]),
+        });
+    }
+    let num_ports = inputs.iter().map(|x| x.num_ports()).max().unwrap();
+    let num_phases = if options.single_routing {
+        1
+    } else {
+        inputs.len()
+    };
+    if let Some(sketch) = &options.sketch {
+        let memory = &sketch.memory;
+        if (memory.size(), memory.width()) != (input.size() as u64, input.bitwidth() as u64) {
+            return Err(SynthesisError::SketchShape {
+                expected: shape(input),
+                found: (memory.size(), memory.width()),
+            });
+        }
+        if memory.banks().len() != num_ports {
+            return Err(SynthesisError::SketchPorts {
+                expected: num_ports,
+                found: memory.banks().len(),
+            });
+        }
+        let phases = memory.banks().iter().map(|x| x.phases());
+        if let Some((bank, found)) = phases
+            .enumerate()
+            .find(|(_, x)| ![1, num_phases].contains(x))
+        {
+            return Err(SynthesisError::SketchPhases {
+                bank,
+                expected: num_phases,
+                found,
+            });
+        }
+    }
+
     let addr_size = input.bits_required();
     let ctx = z3::Context::new(&z3::Config::default());
     let solver = z3::Optimize::new(&ctx);
 
     let terminal_rprogs = terminal_routing_program(&ctx, addr_size);
-    let term_part = terminal_partition(&ctx);
+    let given_banks = options.sketch.iter().flat_map(|x| {
+        x.memory
+            .banks()
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| !x.has(Hole::Layout { bank: *idx }))
+    });
+    let cyclic_layouts = options.cyclic_layouts
+        || given_banks.clone().any(|(_, x)| {
+            matches!(
+                x.layout().layouts(),
+                [MemoryLayout::Cyclic { .. } | MemoryLayout::BlockCyclic { .. }]
+            )
+        });
+    let term_part = terminal_partition(&ctx, cyclic_layouts);
 
-    let banks = (0..input.num_ports())
+    let banks = (0..num_ports)
         .map(|i| z3_ast::Datatype::new_const(&ctx, format!("bank_{}", i), &term_part.sort))
         .collect::<Vec<_>>();
 
-    let routing_fns = (0..input.num_ports())
-        .map(|x| Datatype::new_const(&ctx, format!("map_addr_{}", x), &terminal_rprogs.sort))
+    let routing_fns = (0..num_phases)
+        .map(|phase| {
+            (0..num_ports)
+                .map(|x| {
+                    Datatype::new_const(
+                        &ctx,
+                        format!("map_addr_{}_{}", phase, x),
+                        &terminal_rprogs.sort,
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let storage = (0..num_ports)
+        .map(|x| Int::new_const(&ctx, format!("storage_{}", x)))
+        .collect::<Vec<_>>();
+
+    let mut fixed_layouts = vec![None; num_ports];
+    for (idx, bank) in given_banks {
+        if !matches!(
+            bank.layout().layouts(),
+            [MemoryLayout::Range { .. }
+                | MemoryLayout::Cyclic { .. }
+                | MemoryLayout::BlockCyclic { .. }]
+        ) {
+            fixed_layouts[idx] = Some(bank.layout().clone());
+        }
+    }
+    let fixed_routing = (0..num_phases)
+        .map(|phase| {
+            (0..num_ports)
+                .map(|idx| {
+                    let sketch = options.sketch.as_ref()?;
+                    let bank = &sketch.memory.banks()[idx];
+                    let shared = phase.min(bank.phases() - 1);
+                    let open = sketch.has(Hole::Translation {
+                        bank: idx,
+                        phase: shared,
+                    }) || sketch.has(Hole::TranslationValue {
+                        bank: idx,
+                        phase: shared,
+                    });
+                    (!open).then(|| bank.routing(phase).clone())
+                })
+                .collect::<Vec<_>>()
+        })
         .collect::<Vec<_>>();
 
     let prob_ctx = ProblemContext {
         banks,
         routing_fns,
+        storage,
+        fixed_layouts,
+        fixed_routing,
         addr_size,
         terminals_prog: terminal_rprogs,
         partition_type: term_part,
+        cyclic_layouts,
     };
 
     solver.assert(&prob_ctx.partition_conditions(input.size()));
+    solver.assert(&prob_ctx.storage_conditions(inputs, options.stall_budget.is_some()));
+    if let Some(sketch) = &options.sketch {
+        solver.assert(&prob_ctx.sketch_conditions(sketch));
+    }
+
+    for (phase, input) in inputs.iter().enumerate() {
+        for line in input.iter() {
+            for (bank_idx, request) in line.iter().enumerate() {
+                if let Some(request_index) = request {
+                    let req_int = z3_ast::Int::from_u64(&ctx, *request_index as u64);
+                    let (cond1, index_maps_to) = prob_ctx.map_addr(&req_int, bank_idx, phase);
 
-    for line in input.iter() {
-        for (bank_idx, request) in line.iter().enumerate() {
-            if let Some(request_index) = request {
-                let req_int = z3_ast::Int::from_u64(&ctx, *request_index as u64);
-                let (cond1, index_maps_to) = prob_ctx.map_addr(&req_int, bank_idx);
-
-                let index_correctness_bool = index_maps_to._eq(&req_int);
-                solver.assert(&cond1);
-                solver.assert(&index_correctness_bool);
-                solver
-                    .assert(&(index_maps_to.lt(&z3_ast::Int::from_u64(&ctx, input.size() as u64))));
-                solver.assert(&index_maps_to.ge(&Int::from_u64(&ctx, 0)));
+                    let index_correctness_bool = index_maps_to._eq(&req_int);
+                    solver.assert(&cond1);
+                    solver.assert(&index_correctness_bool);
+                    solver.assert(
+                        &(index_maps_to.lt(&z3_ast::Int::from_u64(&ctx, input.size() as u64))),
+                    );
+                    solver.assert(&index_maps_to.ge(&Int::from_u64(&ctx, 0)));
+                }
             }
         }
     }
@@ -356,12 +1033,167 @@ pub fn solve_trace(input: &Trace) -> Component {
     //     solver.assert(&z3_ast::Bool::or(&ctx, &borrow_bools));
     // }
 
-    solver.minimize(&prob_ctx.partition_cost());
-
-    solver.check(&[]);
+    // objectives are lexicographic, so fewer banks always wins over smaller
+    // ones. When stalls are allowed they are traded against the partition
+    // cost instead
+    match options.stall_budget {
+        None => {
+            solver.minimize(&prob_ctx.bank_count());
+            solver.minimize(&prob_ctx.partition_cost(input.size()));
+        }
+        Some(budget) => {
+            // the budget applies to each phase separately
+            let mut stalls = Int::from_u64(&ctx, 0);
+            for (phase, input) in inputs.iter().enumerate() {
+                let (phase_stalls, stall_conditions) = prob_ctx.stall_conditions(input, phase);
+                solver.assert(&stall_conditions);
+                solver.assert(&phase_stalls.le(&Int::from_u64(&ctx, budget.cycles(input))));
+                stalls += phase_stalls;
+            }
+            solver.minimize(
+                &(prob_ctx.partition_cost(input.size())
+                    + stalls * Int::from_u64(&ctx, options.stall_cost)),
+            );
+            solver.minimize(&prob_ctx.bank_count());
+        }
+    }
 
     // println!("{:?}", solver);
 
-    let model = solver.get_model().unwrap();
-    prob_ctx.extract_description(&model, input)
+    for _ in 0..STALL_CANDIDATES {
+        match solver.check(&[]) {
+            z3::SatResult::Sat => {
+                let model = solver.get_model().unwrap();
+                let component =
+                    prob_ctx.extract_description(&model, inputs, options.sketch.as_ref());
+                match options.stall_budget {
+                    // the solver counts stalls per line regardless of latency,
+                    // so the sharing is only kept when the simulator's
+                    // schedule fits the budget as well
+                    Some(budget) if !within_budget(&component, inputs, budget) => {
+                        solver.assert(&prob_ctx.other_storage(&model));
+                    }
+                    _ => return Ok(component),
+                }
+            }
+            z3::SatResult::Unsat if options.sketch.is_some() => {
+                return Err(SynthesisError::SketchUnsat)
+            }
+            // a range of the whole memory serves any trace, so only the
+            // storage ruled out for stalling too long can leave nothing
+            z3::SatResult::Unsat => return Err(SynthesisError::Unsat),
+            z3::SatResult::Unknown => {
+                return Err(SynthesisError::Unknown(
+                    solver
+                        .get_reason_unknown()
+                        .unwrap_or_else(|| "no reason given".to_string()),
+                ))
+            }
+        }
+    }
+    Err(SynthesisError::StallSchedule {
+        candidates: STALL_CANDIDATES,
+    })
+}
+
+/// whether running every trace on the component, each in its own phase,
+/// stalls for no more cycles than the budget allows
+fn within_budget(component: &Component, traces: &[Trace], budget: StallBudget) -> bool {
+    traces.iter().enumerate().all(|(phase, trace)| {
+        component
+            .vailidate(trace, phase, Some(budget.cycles(trace)))
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{solve_traces, SolverOptions, StallBudget, SynthesisError};
+    use crate::Trace;
+
+    /// two ports reading the even and odd addresses in the same cycles
+    fn interleaved() -> Trace {
+        Trace::parse_trace(
+            r#"{"size": 8, "bitwidth": 8, "trace": [[0, 1], [2, 3], [4, 5], [6, 7]]}"#,
+        )
+        .unwrap()
+    }
+
+    fn solve(budget: Option<StallBudget>) -> crate::structures::Component {
+        let options = SolverOptions {
+            stall_budget: budget,
+            ..SolverOptions::default()
+        };
+        solve_traces(&[interleaved()], &options).unwrap()
+    }
+
+    #[test]
+    fn stalling_results_fit_the_budget() {
+        // sharing a bank of 8 and stalling once per line is cheaper than two
+        // banks of 4
+        let shared = solve(Some(StallBudget::Cycles(4)));
+        assert_eq!(shared.storage_of(1), 0);
+        let schedule = shared.vailidate(&interleaved(), 0, Some(4)).unwrap();
+        assert_eq!(schedule.stall_cycles(), 4);
+
+        for budget in [None, Some(StallBudget::Cycles(3))] {
+            let separate = solve(budget);
+            assert_eq!(separate.storage_of(1), 1);
+            assert!(separate.vailidate(&interleaved(), 0, None).is_ok());
+        }
+    }
+
+    #[test]
+    fn banks_are_sized_by_the_addresses_they_hold() {
+        // the cheapest memory holds exactly the even and odd addresses, so each
+        // bank is costed at the four entries of its range
+        let comp = solve(None);
+        assert_eq!(comp.banks().len(), 2);
+        for (bank, first) in comp.banks().iter().zip([0, 1]) {
+            assert_eq!(bank.size(), 4, "{}", comp.pretty_print());
+            let expected: Vec<usize> = (first..8).step_by(2).collect();
+            assert_eq!(bank.layout().addresses(), expected);
+        }
+    }
+
+    #[test]
+    fn phases_are_served_by_one_memory() {
+        let traces = [
+            interleaved(),
+            Trace::parse_trace(r#"{"size": 8, "bitwidth": 8, "trace": [[0, 4], [3, 7]]}"#).unwrap(),
+        ];
+        for single_routing in [false, true] {
+            let options = SolverOptions {
+                single_routing,
+                ..SolverOptions::default()
+            };
+            let comp = solve_traces(&traces, &options).unwrap();
+            let phases = if single_routing { 1 } else { 2 };
+            assert_eq!(comp.phases(), phases);
+            for (phase, trace) in traces.iter().enumerate() {
+                let validated = comp.vailidate(trace, phase, None);
+                assert!(validated.is_ok(), "{}", comp.pretty_print());
+            }
+        }
+    }
+
+    #[test]
+    fn phases_need_traces_of_the_same_memory() {
+        let other = Trace::parse_trace(r#"{"size": 16, "bitwidth": 8, "trace": [[0]]}"#).unwrap();
+        let error = solve_traces(&[interleaved(), other], &SolverOptions::default()).unwrap_err();
+        assert!(matches!(
+            error,
+            SynthesisError::MismatchedTraces { trace: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn memories_of_any_size_are_addressed_in_full() {
+        let trace =
+            Trace::parse_trace(r#"{"size": 18, "bitwidth": 8, "trace": [[0, 16], [5, 17]]}"#)
+                .unwrap();
+        let comp = solve_traces(std::slice::from_ref(&trace), &SolverOptions::default()).unwrap();
+        let validated = comp.vailidate(&trace, 0, None);
+        assert!(validated.is_ok(), "{}", comp.pretty_print());
+    }
 }
@@ -1,232 +1,803 @@
-use std::fmt::Write;
+pub mod calyx;
+mod firrtl;
+mod hls;
+pub mod image;
+mod infix;
+mod testbench;
+mod verilog;
+mod viz;
+mod wrapper;
 
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Write};
+
+use self::calyx::{Assignment, Atom, Cell, Control, Group, Guard, Namer, Port};
 use super::dsl::bits_required;
+use super::simulator::{Schedule, Simulator};
 use super::Trace;
 
 const INPUT: &str = "INPUT";
 
-#[derive(Debug, Clone)]
+/// the words of the description language, which cannot name a port
+const KEYWORDS: &[&str] = &[
+    "memory",
+    "bank",
+    "layout",
+    "translation",
+    "phase",
+    "latency",
+    "storage",
+    "switch",
+    "lookup",
+    "cyclic",
+    "block_cyclic",
+    "for",
+    "in",
+    "with",
+    "let",
+    "import",
+    "INPUT",
+    "NOOP",
+];
+
+/// checks that every port, named or not, has a name of its own. Unnamed
+/// ports are called `bank_{idx}`, so a port given such a name may collide
+/// with one
+pub fn check_port_names(names: &[Option<&str>]) -> Result<(), DescriptionError> {
+    let names = names
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| (name.is_some(), port_name(*name, idx)))
+        .collect::<Vec<_>>();
+    for (idx, (named, name)) in names.iter().enumerate() {
+        if *named && KEYWORDS.iter().any(|x| x.eq_ignore_ascii_case(name)) {
+            return Err(DescriptionError::ReservedPort(name.clone()));
+        }
+        if names[..idx].iter().any(|(_, x)| x == name) {
+            return Err(DescriptionError::DuplicatePort(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// the name of a port, falling back to the positional name
+fn port_name(name: Option<&str>, idx: usize) -> String {
+    match name {
+        Some(name) => name.to_string(),
+        None => format!("bank_{}", idx),
+    }
+}
+
+/// the width of an unsigned constant
+fn value_width(value: u64) -> u64 {
+    (u64::BITS - value.leading_zeros()).max(1) as u64
+}
+
+/// the width of an index into `count` elements
+fn index_width(count: usize) -> u64 {
+    if count <= 1 {
+        1
+    } else {
+        bits_required(count - 1) as u64 + 1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawComponent")]
 pub struct Component {
+    /// the name of the memory, used for the emitted Calyx component
+    name: Option<String>,
     /// The number of slots in the logical memory
     size: u64,
     /// Bitwidth of the stored data elements
     width: u64,
-    /// Width of the indexing ports
-    address_bit_width: u64,
     /// Number of parallel ports in the memory
     port_count: u64,
     /// the list of memory banks where the index corresponds to the input port
     banks: Vec<MemoryBank>,
 }
 
+/// A component as it is serialized, checked like a parsed description when
+/// read back
+#[derive(Deserialize)]
+struct RawComponent {
+    name: Option<String>,
+    size: u64,
+    width: u64,
+    banks: Vec<MemoryBank>,
+}
+
+impl TryFrom<RawComponent> for Component {
+    type Error = DescriptionError;
+
+    fn try_from(raw: RawComponent) -> Result<Self, Self::Error> {
+        let banks = raw
+            .banks
+            .into_iter()
+            .map(|x| x.with_memory_size(raw.size as usize))
+            .collect();
+        let component = Component::from_parse(raw.size, raw.width, banks).with_name(raw.name);
+        component.check_banks(false)?;
+        component.check_layouts()?;
+        Ok(component)
+    }
+}
+
 impl Component {
-    pub fn from_trace(banks: Vec<MemoryBank>, trace: &Trace) -> Self {
+    /// checks the phases, port names and storage of the banks. The layouts
+    /// of banks sharing storage must agree unless they may still contain
+    /// holes for the solver to fill in
+    pub fn check_banks(&self, holes: bool) -> Result<(), DescriptionError> {
+        if self.banks.is_empty() {
+            return Err(DescriptionError::NoBanks);
+        }
+        check_port_names(&self.banks.iter().map(|x| x.name()).collect::<Vec<_>>())?;
+        let phases = self.phases();
+        for (idx, bank) in self.banks.iter().enumerate() {
+            if bank.phases() != 1 && bank.phases() != phases {
+                return Err(DescriptionError::PhaseMismatch {
+                    bank: idx,
+                    phases: bank.phases(),
+                    expected: phases,
+                });
+            }
+            if let Some(storage) = bank.storage() {
+                let owner = self
+                    .banks
+                    .get(storage)
+                    .ok_or(DescriptionError::MissingStorage { bank: idx, storage })?;
+                if owner.storage().is_some() {
+                    return Err(DescriptionError::SharedStorage { bank: idx, storage });
+                }
+                if owner.layout() != bank.layout() && !holes {
+                    return Err(DescriptionError::StorageLayout { bank: idx, storage });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// checks that every layout only holds addresses of the memory
+    pub fn check_layouts(&self) -> Result<(), DescriptionError> {
+        let size = self.size as usize;
+        match self
+            .banks
+            .iter()
+            .position(|bank| !bank.layout().layouts().iter().all(|x| x.fits(size)))
+        {
+            Some(bank) => Err(DescriptionError::LayoutPastMemory { bank, size }),
+            None => Ok(()),
+        }
+    }
+
+    /// builds a component serving several traces, one per phase. Port
+    /// metadata the banks lack is taken from the first trace describing each
+    /// port
+    pub fn from_traces(mut banks: Vec<MemoryBank>, traces: &[Trace]) -> Self {
+        for (idx, bank) in banks.iter_mut().enumerate() {
+            let trace = traces.iter().find(|x| idx < x.num_ports()).unwrap();
+            bank.name = bank.name.take().or_else(|| {
+                traces
+                    .iter()
+                    .find_map(|x| x.port_name(idx))
+                    .map(String::from)
+            });
+            if bank.latency == 0 {
+                bank.latency = trace.latency(idx);
+            }
+        }
         Self {
-            size: trace.size() as u64,
-            width: trace.bitwidth() as u64,
-            address_bit_width: trace.bits_required() as u64,
-            port_count: trace.num_ports() as u64,
+            name: None,
+            size: traces[0].size() as u64,
+            width: traces[0].bitwidth() as u64,
+            port_count: banks.len() as u64,
             banks,
         }
     }
     pub fn from_parse(size: u64, width: u64, banks: Vec<MemoryBank>) -> Self {
         Self {
+            name: None,
             size,
             width,
-            address_bit_width: bits_required(width as usize) as u64,
             port_count: banks.len() as u64,
             banks,
         }
     }
 
-    fn emit_input_ports(&self) -> String {
-        let mut w = String::new();
-        let last_idx = self.banks.len() - 1;
-        for (idx, _) in self.banks.iter().enumerate() {
-            if idx != last_idx {
-                write!(w, "bank_{}_addr:{}, ", idx, self.address_bit_width).unwrap()
-            } else {
-                write!(w, "bank_{}_addr:{}", idx, self.address_bit_width).unwrap()
-            }
+    /// the number of phases the component distinguishes between
+    pub fn phases(&self) -> usize {
+        self.banks
+            .iter()
+            .map(|x| x.routing.len())
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// the number of slots in the logical memory
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// the width of the stored data elements
+    pub fn width(&self) -> u64 {
+        self.width
+    }
+
+    /// the width of the logical ports' addresses
+    pub fn address_width(&self) -> u64 {
+        index_width(self.size as usize)
+    }
+
+    /// the width of the indices into the storage serving the given bank
+    pub fn index_width(&self, bank_idx: usize) -> u64 {
+        index_width(self.banks[self.storage_of(bank_idx)].size())
+    }
+
+    /// the width of the phase selection input
+    fn phase_width(&self) -> u64 {
+        (bits_required(self.phases().max(2) - 1) + 1) as u64
+    }
+
+    pub fn with_name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// the name of the emitted Calyx component, falling back to one built
+    /// from the memory's shape when the memory is unnamed
+    pub fn component_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("mem_{}_{}", self.size, self.port_count),
         }
-        w
     }
-    fn emit_output_ports(&self) -> String {
-        let mut w = String::new();
-        let last_idx = self.banks.len() - 1;
-        for (idx, _) in self.banks.iter().enumerate() {
-            if idx != last_idx {
-                write!(w, "read_bank_{}_addr:{}, ", idx, self.width).unwrap()
-            } else {
-                write!(w, "read_bank_{}_addr:{}", idx, self.width).unwrap()
+
+    /// emits a Calyx program holding a component for each memory, each
+    /// followed by its logical wrapper when `wrappers` is set
+    pub fn emit_calyx_program(components: &[Component], wrappers: bool) -> String {
+        let components = components
+            .iter()
+            .flat_map(|x| {
+                let mut components = vec![x.calyx_component()];
+                if wrappers {
+                    components.push(x.calyx_wrapper());
+                }
+                components
+            })
+            .collect::<Vec<_>>();
+        calyx::Program::new(components).to_string()
+    }
+
+    /// the Calyx component implementing the memory, with an external memory
+    /// per bank addressed through each port's translation. Banks whose ports
+    /// all have a latency are `seq_mem_d1`s read by a group, and the data of
+    /// ports with a longer latency passes through a register per extra cycle
+    pub fn calyx_component(&self) -> calyx::Component {
+        let mut names = Namer::default();
+        let mut component = calyx::Component::new(&self.component_name());
+        if self.phases() > 1 {
+            component
+                .inputs
+                .push(Port::new("phase", self.phase_width()));
+        }
+        for (idx, bank) in self.banks.iter().enumerate() {
+            let port = bank.port_name(idx);
+            if self.is_shared(idx) {
+                component.inputs.push(Port::new(&format!("{}_en", port), 1));
             }
+            component
+                .inputs
+                .push(Port::new(&format!("{}_addr", port), self.address_width()));
+            component
+                .outputs
+                .push(Port::new(&format!("read_{}_addr", port), self.width));
         }
-        w
-    }
-    fn emit_cells(&self) -> String {
-        let mut w = String::new();
+        for port in component.inputs.iter().chain(component.outputs.iter()) {
+            names.reserve(&port.name);
+        }
+
         for (idx, bank) in self.banks.iter().enumerate() {
-            writeln!(
-                w,
-                "{}@external bank_{} = std_mem_d1({width}, {size}, {addr_width});",
-                " ".repeat(8),
-                idx,
-                width = self.width,
-                size = bank.size(),
-                addr_width = self.address_bit_width
-            )
-            .unwrap();
+            if bank.storage.is_some() {
+                continue;
+            }
+            let name = format!("bank_{}", idx);
+            names.reserve(&name);
+            let args = vec![self.width, bank.size() as u64, self.index_width(idx)];
+            let prototype = if self.is_sequential(idx) {
+                "seq_mem_d1"
+            } else {
+                "std_mem_d1"
+            };
+            component
+                .cells
+                .push(Cell::new(&name, prototype, args).external());
         }
-        w
-    }
-    fn emit_wires(&self) -> (String, String) {
-        let mut w = String::new();
-        let mut c = String::new();
         for (idx, bank) in self.banks.iter().enumerate() {
-            let (ic, iw) = bank.emit_wires(idx, self.address_bit_width as usize);
-            writeln!(w, "{}", iw).unwrap();
-            writeln!(c, "{}", ic).unwrap();
+            bank.emit_calyx(idx, self, &mut names, &mut component);
+        }
+
+        let mut schedules = vec![];
+        for (storage, bank) in self.banks.iter().enumerate() {
+            if bank.storage.is_none() {
+                schedules.push(self.emit_calyx_reads(storage, &mut names, &mut component));
+            }
         }
-        (c, w)
+        component.control = Control::par(schedules);
+        component
     }
 
-    pub fn emit_calyx_comp(&self) -> String {
-        let mut w = String::new();
-        let (translation_cells, wires) = self.emit_wires();
-        // TODO: fix this nightmare
-        writeln!(
-            w,
-            r#"
-import "primitives/core.futil";
-component mem_{size}_{port_count}({input_ports}) -> ({output_ports}) {{
-    cells {{
-{cells}
-{translation_cells}
-    }}
-    wires {{
-{wires}
-
-    }}
-    control {{}}
-}}
-
-"#,
-            size = self.size,
-            port_count = self.port_count,
-            input_ports = self.emit_input_ports(),
-            output_ports = self.emit_output_ports(),
-            cells = self.emit_cells(),
-            translation_cells = translation_cells,
-            wires = wires,
-        )
-        .unwrap();
-        w
+    /// whether every port reading the given bank's storage has a latency,
+    /// so that the storage can be a sequential memory
+    pub fn is_sequential(&self, bank_idx: usize) -> bool {
+        let storage = self.storage_of(bank_idx);
+        (0..self.banks.len())
+            .filter(|x| self.storage_of(*x) == storage)
+            .all(|x| self.banks[x].latency > 0)
     }
 
-    pub fn vailidate(&self, trace: &Trace) -> bool {
-        for line in trace.iter() {
-            for (idx, request) in line.iter().enumerate() {
-                if let Some(request) = request {
-                    if !self.banks[idx].can_read(*request) {
-                        return false;
-                    }
+    /// adds the cells and groups delivering the data read from the storage to
+    /// the ports using it and returns their schedule: the group requesting
+    /// the read from a sequential memory followed by a stage per remaining
+    /// cycle of latency, registering the data of the ports still waiting
+    fn emit_calyx_reads(
+        &self,
+        storage: usize,
+        names: &mut Namer,
+        component: &mut calyx::Component,
+    ) -> Control {
+        let cell = format!("bank_{}", storage);
+        let sequential = self.is_sequential(storage);
+        let users = (0..self.banks.len())
+            .filter(|x| self.storage_of(*x) == storage)
+            .collect::<Vec<_>>();
+        let mut schedule = vec![];
+        if sequential {
+            let group = names.fresh(&format!("{}_read", cell));
+            component.groups.push(Group {
+                name: group.clone(),
+                assignments: vec![
+                    Assignment::new(Atom::cell(&cell, "read_en"), Atom::constant(1, 1)),
+                    Assignment::new(Atom::hole(&group, "done"), Atom::cell(&cell, "read_done")),
+                ],
+            });
+            schedule.push(Control::Enable(group));
+        }
+
+        // the cycles of latency left after the read itself
+        let stages = |x: usize| self.banks[x].latency.saturating_sub(sequential as u64);
+        let mut outputs = vec![Atom::cell(&cell, "read_data"); users.len()];
+        for stage in 0..users.iter().map(|x| stages(*x)).max().unwrap_or(0) {
+            let mut registers = vec![];
+            for (user, output) in users.iter().zip(outputs.iter_mut()) {
+                if stages(*user) <= stage {
+                    continue;
                 }
+                let port = self.banks[*user].port_name(*user);
+                let register = names.fresh(&format!("{}_reg", port));
+                let group = names.fresh(&format!("{}_stage", port));
+                component
+                    .cells
+                    .push(Cell::new(&register, "std_reg", vec![self.width]));
+                component.groups.push(Group {
+                    name: group.clone(),
+                    assignments: vec![
+                        Assignment::new(Atom::cell(&register, "in"), output.clone()),
+                        Assignment::new(Atom::cell(&register, "write_en"), Atom::constant(1, 1)),
+                        Assignment::new(Atom::hole(&group, "done"), Atom::cell(&register, "done")),
+                    ],
+                });
+                *output = Atom::cell(&register, "out");
+                registers.push(Control::Enable(group));
+            }
+            schedule.push(Control::par(registers));
+        }
+
+        for (user, output) in users.iter().zip(outputs) {
+            let port = self.banks[*user].port_name(*user);
+            component.wires.push(Assignment::new(
+                Atom::this(&format!("read_{}_addr", port)),
+                output,
+            ));
+        }
+        Control::seq(schedule)
+    }
+
+    pub fn banks(&self) -> &[MemoryBank] {
+        &self.banks
+    }
+
+    /// the index of the bank whose storage serves the given bank's port
+    pub fn storage_of(&self, bank_idx: usize) -> usize {
+        self.banks[bank_idx].storage.unwrap_or(bank_idx)
+    }
+
+    /// whether any other port is routed to the storage of the given bank
+    pub fn is_shared(&self, bank_idx: usize) -> bool {
+        let storage = self.storage_of(bank_idx);
+        (0..self.banks.len()).any(|x| x != bank_idx && self.storage_of(x) == storage)
+    }
+
+    /// finds the bank serving the given trace port. Named trace ports are
+    /// matched against bank names, unnamed ones are matched by position
+    pub fn bank_for_port(&self, trace: &Trace, port: usize) -> Result<usize, ValidationError> {
+        match trace.port_name(port) {
+            Some(name) => self
+                .banks
+                .iter()
+                .position(|x| x.name.as_deref() == Some(name))
+                .ok_or_else(|| ValidationError::MissingPort {
+                    port: name.to_string(),
+                }),
+            None if port < self.banks.len() => Ok(port),
+            None => Err(ValidationError::MissingPort {
+                port: format!("#{}", port),
+            }),
+        }
+    }
+
+    /// checks the trace against the description's translations for the given
+    /// phase. Without a stall budget any bank conflict is an error, otherwise
+    /// conflicts are resolved by stalling for at most `stall_budget` cycles in
+    /// total
+    pub fn vailidate(
+        &self,
+        trace: &Trace,
+        phase: usize,
+        stall_budget: Option<u64>,
+    ) -> Result<Schedule, ValidationError> {
+        match stall_budget {
+            None => Simulator::new(self).in_phase(phase).run(trace),
+            Some(budget) => {
+                let schedule = Simulator::stalling(self).in_phase(phase).run(trace)?;
+                if schedule.stall_cycles() > budget {
+                    return Err(ValidationError::StallBudget {
+                        stalls: schedule.stall_cycles(),
+                        budget,
+                    });
+                }
+                Ok(schedule)
             }
         }
-        true
     }
 }
 
+/// Reasons a description can fail to satisfy a trace
 #[derive(Debug, Clone)]
+pub enum ValidationError {
+    /// the trace names a port which the description does not have
+    MissingPort { port: String },
+    /// the port cannot reach the requested address
+    Unreachable {
+        cycle: u64,
+        port: String,
+        address: usize,
+    },
+    /// two ports need the same storage in the same cycle
+    Conflict {
+        cycle: u64,
+        first: String,
+        second: String,
+    },
+    /// the port's latency differs from the one required by the trace
+    LatencyMismatch {
+        port: String,
+        expected: u64,
+        found: u64,
+    },
+    /// resolving the bank conflicts needs more stalls than allowed
+    StallBudget { stalls: u64, budget: u64 },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::MissingPort { port } => {
+                write!(f, "the description has no port `{}`", port)
+            }
+            ValidationError::Unreachable {
+                cycle,
+                port,
+                address,
+            } => write!(
+                f,
+                "port `{}` cannot read address {} (cycle {})",
+                port, address, cycle
+            ),
+            ValidationError::Conflict {
+                cycle,
+                first,
+                second,
+            } => write!(
+                f,
+                "ports `{}` and `{}` both need their shared bank in cycle {}",
+                first, second, cycle
+            ),
+            ValidationError::LatencyMismatch {
+                port,
+                expected,
+                found,
+            } => write!(
+                f,
+                "port `{}` has latency {} but the trace expects {}",
+                port, found, expected
+            ),
+            ValidationError::StallBudget { stalls, budget } => write!(
+                f,
+                "bank conflicts need {} stall cycles but only {} are allowed",
+                stalls, budget
+            ),
+        }
+    }
+}
+
+/// Reasons the parts of a description cannot be built
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DescriptionError {
+    /// a range which does not start before it finishes
+    EmptyRange,
+    /// a range with a stride of zero
+    ZeroStride,
+    /// a cyclic layout with a zero block size or factor
+    ZeroFactor,
+    /// a cyclic layout whose offset is not less than its factor
+    OffsetPastFactor,
+    /// a bank without any translation
+    MissingTranslation,
+    /// a memory without any banks
+    NoBanks,
+    /// a bank with translations for a different number of phases than the
+    /// rest of the memory
+    PhaseMismatch {
+        bank: usize,
+        phases: usize,
+        expected: usize,
+    },
+    /// two ports with the same name
+    DuplicatePort(String),
+    /// a port named after a keyword of the description language
+    ReservedPort(String),
+    /// a bank using the storage of a bank which does not exist
+    MissingStorage { bank: usize, storage: usize },
+    /// a bank using the storage of a bank which itself uses another's
+    SharedStorage { bank: usize, storage: usize },
+    /// a bank whose layout differs from that of the bank owning its storage
+    StorageLayout { bank: usize, storage: usize },
+    /// a bank holding addresses past the end of the memory
+    LayoutPastMemory { bank: usize, size: usize },
+}
+
+impl Display for DescriptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            DescriptionError::EmptyRange => "a range must start before it finishes",
+            DescriptionError::ZeroStride => "a range must have a non-zero stride",
+            DescriptionError::ZeroFactor => "cyclic layouts need a non-zero block size and factor",
+            DescriptionError::OffsetPastFactor => {
+                "the offset of a cyclic layout must be less than its factor"
+            }
+            DescriptionError::MissingTranslation => "a bank needs a translation",
+            DescriptionError::NoBanks => "a memory needs a bank",
+            DescriptionError::PhaseMismatch {
+                bank,
+                phases,
+                expected,
+            } => {
+                return write!(
+                    f,
+                    "bank {} has translations for {} phases but the memory has {}",
+                    bank, phases, expected
+                )
+            }
+            DescriptionError::DuplicatePort(name) => {
+                return write!(f, "duplicate port name `{}`", name)
+            }
+            DescriptionError::ReservedPort(name) => {
+                return write!(f, "`{}` is a keyword and cannot name a port", name)
+            }
+            DescriptionError::MissingStorage { bank, storage } => {
+                return write!(
+                    f,
+                    "bank {} uses the storage of missing bank {}",
+                    bank, storage
+                )
+            }
+            DescriptionError::SharedStorage { bank, storage } => {
+                return write!(
+                    f,
+                    "bank {} uses the storage of bank {} which is itself shared",
+                    bank, storage
+                )
+            }
+            DescriptionError::StorageLayout { bank, storage } => {
+                return write!(
+                    f,
+                    "bank {} has a different layout from its storage bank {}",
+                    bank, storage
+                )
+            }
+            DescriptionError::LayoutPastMemory { bank, size } => {
+                return write!(
+                    f,
+                    "the layout of bank {} holds addresses past the end of the memory of size {}",
+                    bank, size
+                )
+            }
+        };
+        write!(f, "{}", message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawMemoryBank")]
 pub struct MemoryBank {
-    routing: TopLevelRoutingProgram,
+    /// the name of the port served by this bank
+    name: Option<String>,
+    /// the translation used in each phase, a single translation is shared by
+    /// every phase
+    routing: Vec<TopLevelRoutingProgram>,
     memory_layout: TopLevelMemoryLayout,
+    /// the number of cycles between a request and its result
+    #[serde(default)]
+    latency: u64,
+    /// the bank whose storage this port reads from, if not its own
+    storage: Option<usize>,
+}
+
+/// A bank as it is serialized, checked by `MemoryBank::new` when read back
+#[derive(Deserialize)]
+struct RawMemoryBank {
+    name: Option<String>,
+    routing: Vec<TopLevelRoutingProgram>,
+    memory_layout: TopLevelMemoryLayout,
+    #[serde(default)]
+    latency: u64,
+    storage: Option<usize>,
+}
+
+impl TryFrom<RawMemoryBank> for MemoryBank {
+    type Error = DescriptionError;
+
+    fn try_from(raw: RawMemoryBank) -> Result<Self, Self::Error> {
+        Ok(MemoryBank::new(raw.routing, raw.memory_layout)?
+            .with_name(raw.name)
+            .with_latency(raw.latency)
+            .with_storage(raw.storage))
+    }
 }
 
 impl MemoryBank {
-    pub fn new(routing: TopLevelRoutingProgram, memory_layout: TopLevelMemoryLayout) -> Self {
-        Self {
+    /// a bank with the given translation for each phase, or a single
+    /// translation shared by every phase
+    pub fn new(
+        routing: Vec<TopLevelRoutingProgram>,
+        memory_layout: TopLevelMemoryLayout,
+    ) -> Result<Self, DescriptionError> {
+        if routing.is_empty() {
+            return Err(DescriptionError::MissingTranslation);
+        }
+        Ok(Self {
+            name: None,
             routing,
             memory_layout,
-        }
+            latency: 0,
+            storage: None,
+        })
     }
+
+    pub fn with_latency(mut self, latency: u64) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn with_storage(mut self, storage: Option<usize>) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// bounds the bank's layout by the size of the logical memory
+    pub fn with_memory_size(mut self, size: usize) -> Self {
+        self.memory_layout = self.memory_layout.with_memory_size(size);
+        self
+    }
+
+    pub fn latency(&self) -> u64 {
+        self.latency
+    }
+
+    pub fn storage(&self) -> Option<usize> {
+        self.storage
+    }
+
+    pub fn layout(&self) -> &TopLevelMemoryLayout {
+        &self.memory_layout
+    }
+
+    /// the number of distinct translations the bank has
+    pub fn phases(&self) -> usize {
+        self.routing.len()
+    }
+
+    /// the translation used in the given phase
+    pub fn routing(&self, phase: usize) -> &TopLevelRoutingProgram {
+        self.routing.get(phase).unwrap_or(&self.routing[0])
+    }
+
+    pub fn with_name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// the name of the port served by this bank, falling back to the
+    /// positional name when the bank is unnamed
+    pub fn port_name(&self, bank_idx: usize) -> String {
+        port_name(self.name(), bank_idx)
+    }
+
     pub fn size(&self) -> usize {
         self.memory_layout.size()
     }
 
-    pub fn emit_wires(&self, bank_idx: usize, addr_width: usize) -> (String, String) {
-        let mut c = String::new();
-        let mut w = String::new();
+    /// adds the cells and wires translating this bank's port into the
+    /// indices of `memory`, sliced or padded to their width
+    pub fn emit_calyx(
+        &self,
+        bank_idx: usize,
+        memory: &Component,
+        names: &mut Namer,
+        component: &mut calyx::Component,
+    ) {
+        let port = self.port_name(bank_idx);
+        let storage = format!("bank_{}", memory.storage_of(bank_idx));
+        let addr_width = memory.address_width();
+        let index_width = memory.index_width(bank_idx);
+        let width = self
+            .routing
+            .iter()
+            .flat_map(|x| x.constants())
+            .map(value_width)
+            .fold(addr_width, u64::max);
+        let source = component.resize(
+            names,
+            Atom::this(&format!("{}_addr", port)),
+            addr_width,
+            width,
+        );
+        let enable = if memory.is_shared(bank_idx) {
+            Guard::Port(Atom::this(&format!("{}_en", port)))
+        } else {
+            Guard::True
+        };
 
-        if let TopLevelRoutingProgram::Prog(SequenceRoutingProg::Prog(p)) = &self.routing {
-            match p {
-                TerminalRoutingProgram::RShift(rs) => {
-                    writeln!(c, "rsh_{} = std_rsh({});", bank_idx, addr_width).unwrap();
-                    writeln!(w, "rsh_{idx}.left = bank_{idx}_addr;", idx = bank_idx).unwrap();
-                    writeln!(w, "rsh_{}.right = {}'d{};", bank_idx, addr_width, rs).unwrap();
-                    writeln!(w, "bank_{idx}.addr0 = rsh_{idx}.out;", idx = bank_idx).unwrap();
-                    writeln!(
-                        w,
-                        "read_bank_{idx}_addr = bank_{idx}.read_data;",
-                        idx = bank_idx
-                    )
-                    .unwrap();
-                }
-                TerminalRoutingProgram::Add(a) => {
-                    writeln!(c, "add_{} = std_add({});", bank_idx, addr_width).unwrap();
-                    writeln!(w, "add_{idx}.left = bank_{idx}_addr;", idx = bank_idx).unwrap();
-                    writeln!(w, "add_{}.right = {}'d{};", bank_idx, addr_width, a).unwrap();
-                    writeln!(w, "bank_{idx}.addr0 = add_{idx}.out;", idx = bank_idx).unwrap();
-                    writeln!(
-                        w,
-                        "read_bank_{idx}_addr = bank_{idx}.read_data;",
-                        idx = bank_idx
-                    )
-                    .unwrap();
-                }
-                TerminalRoutingProgram::SubPortVal(v) => {
-                    writeln!(c, "sub_{} = std_sub({});", bank_idx, addr_width).unwrap();
-                    writeln!(w, "sub_{idx}.left = bank_{idx}_addr;", idx = bank_idx).unwrap();
-                    writeln!(w, "sub_{}.right = {}'d{};", bank_idx, addr_width, v).unwrap();
-                    writeln!(w, "bank_{idx}.addr0 = sub_{idx}.out;", idx = bank_idx).unwrap();
-                    writeln!(
-                        w,
-                        "read_bank_{idx}_addr = bank_{idx}.read_data;",
-                        idx = bank_idx
-                    )
-                    .unwrap();
-                }
-                TerminalRoutingProgram::SubValPort(v) => {
-                    writeln!(c, "sub_{} = std_sub({});", bank_idx, addr_width).unwrap();
-                    writeln!(w, "sub_{idx}.right = bank_{idx}_addr;", idx = bank_idx).unwrap();
-                    writeln!(w, "sub_{}.left = {}'d{};", bank_idx, addr_width, v).unwrap();
-                    writeln!(w, "bank_{idx}.addr0 = sub_{idx}.out;", idx = bank_idx).unwrap();
-                    writeln!(
-                        w,
-                        "read_bank_{idx}_addr = bank_{idx}.read_data;",
-                        idx = bank_idx
-                    )
-                    .unwrap();
-                }
-                TerminalRoutingProgram::Constant(_) => todo!(), // useless in elemental context
-                TerminalRoutingProgram::Noop => {
-                    writeln!(w, "bank_{idx}.addr0 = bank_{idx}_addr;", idx = bank_idx).unwrap();
-                    writeln!(
-                        w,
-                        "read_bank_{idx}_addr = bank_{idx}.read_data;",
-                        idx = bank_idx
-                    )
-                    .unwrap();
-                }
+        for (phase, routing) in self.routing.iter().enumerate() {
+            let mut guard = enable.clone();
+            if self.routing.len() > 1 {
+                guard = guard.and(Guard::Compare(
+                    Atom::this("phase"),
+                    ComparisonOperator::Equal,
+                    Atom::constant(memory.phase_width(), phase as u64),
+                ));
+            }
+            for (case, index) in routing.emit_calyx(&source, width, names, component) {
+                let index = component.resize(names, index, width, index_width);
+                component.wires.push(Assignment::guarded(
+                    Atom::cell(&storage, "addr0"),
+                    guard.clone().and(case),
+                    index,
+                ));
             }
-        } else {
-            todo!("Cannot do more complex routing");
         }
-        (c, w)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TopLevelMemoryLayout {
     mems: Vec<MemoryLayout>,
 }
@@ -235,18 +806,102 @@ impl TopLevelMemoryLayout {
     pub fn new(mems: Vec<MemoryLayout>) -> Self {
         Self { mems }
     }
+
+    /// the layouts making up the bank, stored one after another
+    pub fn layouts(&self) -> &[MemoryLayout] {
+        &self.mems
+    }
+
     pub fn size(&self) -> usize {
         self.mems.iter().map(|x| x.size()).sum()
     }
+
+    /// bounds the cyclic layouts by the size of the logical memory
+    pub fn with_memory_size(self, size: usize) -> Self {
+        Self {
+            mems: self
+                .mems
+                .into_iter()
+                .map(|x| x.with_memory_size(size))
+                .collect(),
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawMemoryLayout")]
 pub enum MemoryLayout {
     Range {
         start: usize,
         finish: usize,
         stride: usize,
     },
+    /// every `factor`th address starting from `offset`, up to the end of the
+    /// memory at `finish`
+    Cyclic {
+        factor: usize,
+        offset: usize,
+        finish: usize,
+    },
+    /// blocks of `block` consecutive addresses dealt out to `factor` banks in
+    /// turn, holding the blocks numbered `offset` modulo `factor`
+    BlockCyclic {
+        block: usize,
+        factor: usize,
+        offset: usize,
+        finish: usize,
+    },
+    /// exactly the listed addresses, stored in the order given
+    Set(Vec<usize>),
+}
+
+/// A layout as it is serialized, checked by the `MemoryLayout` constructors
+/// when read back
+#[derive(Deserialize)]
+enum RawMemoryLayout {
+    Range {
+        start: usize,
+        finish: usize,
+        stride: usize,
+    },
+    Cyclic {
+        factor: usize,
+        offset: usize,
+        finish: usize,
+    },
+    BlockCyclic {
+        block: usize,
+        factor: usize,
+        offset: usize,
+        finish: usize,
+    },
+    Set(Vec<usize>),
+}
+
+impl TryFrom<RawMemoryLayout> for MemoryLayout {
+    type Error = DescriptionError;
+
+    fn try_from(raw: RawMemoryLayout) -> Result<Self, Self::Error> {
+        match raw {
+            RawMemoryLayout::Range {
+                start,
+                finish,
+                stride,
+            } => MemoryLayout::new(start, finish, Some(stride)),
+            RawMemoryLayout::Cyclic {
+                factor,
+                offset,
+                finish,
+            } => Ok(MemoryLayout::cyclic(factor, offset)?.with_memory_size(finish)),
+            RawMemoryLayout::BlockCyclic {
+                block,
+                factor,
+                offset,
+                finish,
+            } => Ok(MemoryLayout::block_cyclic(block, factor, offset)?.with_memory_size(finish)),
+            RawMemoryLayout::Set(addresses) => Ok(MemoryLayout::Set(addresses)),
+        }
+    }
 }
 
 #[macro_export]
@@ -264,7 +919,7 @@ macro_rules! memory {
     };
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TopLevelRoutingProgram {
     Switch(
         Vec<(Condition, SequenceRoutingProg)>,
@@ -273,24 +928,33 @@ pub enum TopLevelRoutingProgram {
     Prog(SequenceRoutingProg),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SequenceRoutingProg {
     Sequence(Vec<TerminalRoutingProgram>),
     Prog(TerminalRoutingProgram),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TerminalRoutingProgram {
     RShift(usize),
+    LShift(usize),
     // these all contain the other value
     Add(u64),
     SubPortVal(u64),
     SubValPort(u64),
     Constant(u64),
+    And(u64),
+    Xor(u64),
+    Mod(u64),
+    Div(u64),
+    Mul(u64),
+    /// maps each listed address to the paired index, translating the other
+    /// addresses past the end of the bank
+    Lookup(Vec<(u64, u64)>),
     Noop,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Condition {
     ComparisonPortVal(u64, ComparisonOperator),
     ComparisonValPort(u64, ComparisonOperator),
@@ -299,7 +963,7 @@ pub enum Condition {
     Not(Box<Condition>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ComparisonOperator {
     LessThan,
     Equal,
@@ -338,49 +1002,292 @@ impl Condition {
             Condition::Not(c1) => !c1.eval(port_val),
         }
     }
+
+    /// the values the condition compares against
+    fn constants(&self) -> Vec<u64> {
+        match self {
+            Condition::ComparisonPortVal(val, _) | Condition::ComparisonValPort(val, _) => {
+                vec![*val]
+            }
+            Condition::And(c1, c2) | Condition::Or(c1, c2) => {
+                [c1.constants(), c2.constants()].concat()
+            }
+            Condition::Not(c1) => c1.constants(),
+        }
+    }
+
+    /// the condition as a guard over the address `source`, whose width must
+    /// fit every constant of the condition
+    pub fn calyx_guard(&self, source: &Atom, width: u64) -> Guard {
+        match self {
+            Condition::ComparisonPortVal(val, op) => {
+                Guard::Compare(source.clone(), op.clone(), Atom::constant(width, *val))
+            }
+            Condition::ComparisonValPort(val, op) => {
+                Guard::Compare(Atom::constant(width, *val), op.clone(), source.clone())
+            }
+            Condition::And(c1, c2) => c1
+                .calyx_guard(source, width)
+                .and(c2.calyx_guard(source, width)),
+            Condition::Or(c1, c2) => c1
+                .calyx_guard(source, width)
+                .or(c2.calyx_guard(source, width)),
+            Condition::Not(c1) => c1.calyx_guard(source, width).negate(),
+        }
+    }
 }
 
 impl TerminalRoutingProgram {
-    pub fn eval(&self, port_val: u64) -> u64 {
+    /// translates the address, or `None` when the arithmetic underflows or
+    /// overflows. Addresses missing from a lookup table translate past the
+    /// end of every bank
+    pub fn checked_eval(&self, port_val: u64) -> Option<u64> {
+        match self {
+            TerminalRoutingProgram::Add(v) => port_val.checked_add(*v),
+            TerminalRoutingProgram::SubPortVal(v) => port_val.checked_sub(*v),
+            TerminalRoutingProgram::SubValPort(v) => v.checked_sub(port_val),
+            TerminalRoutingProgram::Constant(c) => Some(*c),
+            TerminalRoutingProgram::RShift(amount) => u32::try_from(*amount)
+                .ok()
+                .and_then(|x| port_val.checked_shr(x)),
+            TerminalRoutingProgram::LShift(amount) => u32::try_from(*amount)
+                .ok()
+                .and_then(|x| port_val.checked_shl(x))
+                .filter(|x| x >> amount == port_val),
+            TerminalRoutingProgram::And(mask) => Some(port_val & mask),
+            TerminalRoutingProgram::Xor(v) => Some(port_val ^ v),
+            TerminalRoutingProgram::Mod(v) => port_val.checked_rem(*v),
+            TerminalRoutingProgram::Div(v) => port_val.checked_div(*v),
+            TerminalRoutingProgram::Mul(v) => port_val.checked_mul(*v),
+            TerminalRoutingProgram::Lookup(table) => Some(
+                table
+                    .iter()
+                    .find(|(address, _)| *address == port_val)
+                    .map_or(u64::MAX, |(_, index)| *index),
+            ),
+            TerminalRoutingProgram::Noop => Some(port_val),
+        }
+    }
+
+    /// the Calyx cell implementing this translation as the cell name prefix,
+    /// the primitive, the constant operand and whether the constant is the
+    /// left operand, or `None` when no cell is needed. Multiplication,
+    /// division and modulo by powers of two are reduced to shifts and masks,
+    /// the rest use the combinational primitives
+    pub fn calyx_operation(&self) -> Option<(&'static str, &'static str, u64, bool)> {
+        match self {
+            TerminalRoutingProgram::RShift(rs) => Some(("rsh", "std_rsh", *rs as u64, false)),
+            TerminalRoutingProgram::LShift(ls) => Some(("lsh", "std_lsh", *ls as u64, false)),
+            TerminalRoutingProgram::Add(a) => Some(("add", "std_add", *a, false)),
+            TerminalRoutingProgram::SubPortVal(v) => Some(("sub", "std_sub", *v, false)),
+            TerminalRoutingProgram::SubValPort(v) => Some(("sub", "std_sub", *v, true)),
+            TerminalRoutingProgram::And(mask) => Some(("and", "std_and", *mask, false)),
+            TerminalRoutingProgram::Xor(v) => Some(("xor", "std_xor", *v, false)),
+            TerminalRoutingProgram::Mul(v) if v.is_power_of_two() => {
+                Some(("lsh", "std_lsh", v.trailing_zeros() as u64, false))
+            }
+            TerminalRoutingProgram::Div(v) if v.is_power_of_two() => {
+                Some(("rsh", "std_rsh", v.trailing_zeros() as u64, false))
+            }
+            TerminalRoutingProgram::Mod(v) if v.is_power_of_two() => {
+                Some(("and", "std_and", v - 1, false))
+            }
+            TerminalRoutingProgram::Mul(v) => Some(("mul", "std_unsyn_mult", *v, false)),
+            TerminalRoutingProgram::Div(v) => Some(("div", "std_unsyn_div", *v, false)),
+            TerminalRoutingProgram::Mod(v) => Some(("mod", "std_unsyn_mod", *v, false)),
+            TerminalRoutingProgram::Constant(_)
+            | TerminalRoutingProgram::Lookup(_)
+            | TerminalRoutingProgram::Noop => None,
+        }
+    }
+
+    /// the constants the emitted cells use
+    fn constants(&self) -> Vec<u64> {
         match self {
-            TerminalRoutingProgram::Add(v) => (port_val + v),
-            TerminalRoutingProgram::SubPortVal(v) => (port_val - v),
-            TerminalRoutingProgram::SubValPort(v) => (v - port_val),
-            TerminalRoutingProgram::Constant(c) => *c,
-            TerminalRoutingProgram::RShift(amount) => port_val >> amount,
-            TerminalRoutingProgram::Noop => port_val,
+            TerminalRoutingProgram::Constant(value) => vec![*value],
+            TerminalRoutingProgram::Lookup(table) => table
+                .iter()
+                .flat_map(|(address, index)| [*address, *index])
+                .collect(),
+            _ => self.calyx_operation().map(|x| x.2).into_iter().collect(),
+        }
+    }
+
+    /// adds the cells computing this step from `source` to the component and
+    /// returns their output
+    pub fn emit_calyx(
+        &self,
+        source: Atom,
+        width: u64,
+        names: &mut Namer,
+        component: &mut calyx::Component,
+    ) -> Atom {
+        match self {
+            TerminalRoutingProgram::Constant(value) => Atom::constant(width, *value),
+            TerminalRoutingProgram::Lookup(table) => {
+                // a wire driven with the index matching the address, or all
+                // ones for the other addresses as in `checked_eval`
+                let cell = names.fresh("lut");
+                component
+                    .cells
+                    .push(Cell::new(&cell, "std_wire", vec![width]));
+                let mut missing = Guard::True;
+                for (address, index) in table {
+                    let matches = Guard::Compare(
+                        source.clone(),
+                        ComparisonOperator::Equal,
+                        Atom::constant(width, *address),
+                    );
+                    component.wires.push(Assignment::guarded(
+                        Atom::cell(&cell, "in"),
+                        matches.clone(),
+                        Atom::constant(width, *index),
+                    ));
+                    missing = missing.and(matches.negate());
+                }
+                component.wires.push(Assignment::guarded(
+                    Atom::cell(&cell, "in"),
+                    missing,
+                    Atom::constant(width, u64::MAX >> (64 - width)),
+                ));
+                Atom::cell(&cell, "out")
+            }
+            _ => match self.calyx_operation() {
+                Some((name, primitive, value, value_on_left)) => {
+                    let cell = names.fresh(name);
+                    component
+                        .cells
+                        .push(Cell::new(&cell, primitive, vec![width]));
+                    let value = Atom::constant(width, value);
+                    let (left, right) = if value_on_left {
+                        (value, source)
+                    } else {
+                        (source, value)
+                    };
+                    component
+                        .wires
+                        .push(Assignment::new(Atom::cell(&cell, "left"), left));
+                    component
+                        .wires
+                        .push(Assignment::new(Atom::cell(&cell, "right"), right));
+                    Atom::cell(&cell, "out")
+                }
+                None => source,
+            },
         }
     }
 }
 
 impl SequenceRoutingProg {
-    pub fn eval(&self, port_val: u64) -> u64 {
+    pub fn checked_eval(&self, port_val: u64) -> Option<u64> {
+        match self {
+            SequenceRoutingProg::Sequence(s) => {
+                s.iter().try_fold(port_val, |acc, x| x.checked_eval(acc))
+            }
+            SequenceRoutingProg::Prog(p) => p.checked_eval(port_val),
+        }
+    }
+
+    fn constants(&self) -> Vec<u64> {
         match self {
-            SequenceRoutingProg::Sequence(s) => s.iter().fold(port_val, |acc, x| x.eval(acc)),
-            SequenceRoutingProg::Prog(p) => p.eval(port_val),
+            SequenceRoutingProg::Sequence(steps) => {
+                steps.iter().flat_map(|x| x.constants()).collect()
+            }
+            SequenceRoutingProg::Prog(p) => p.constants(),
+        }
+    }
+
+    /// each step of a sequence reads the output of the previous one
+    pub fn emit_calyx(
+        &self,
+        source: Atom,
+        width: u64,
+        names: &mut Namer,
+        component: &mut calyx::Component,
+    ) -> Atom {
+        match self {
+            SequenceRoutingProg::Sequence(steps) => steps.iter().fold(source, |source, step| {
+                step.emit_calyx(source, width, names, component)
+            }),
+            SequenceRoutingProg::Prog(p) => p.emit_calyx(source, width, names, component),
         }
     }
 }
 
 impl TopLevelRoutingProgram {
-    pub fn eval(&self, port_val: u64) -> u64 {
+    pub fn checked_eval(&self, port_val: u64) -> Option<u64> {
         match self {
             TopLevelRoutingProgram::Switch(vec, default) => {
                 for (cond, prog) in vec.iter() {
                     if cond.eval(port_val) {
-                        return prog.eval(port_val);
+                        return prog.checked_eval(port_val);
                     }
                 }
-                default.eval(port_val)
+                default.checked_eval(port_val)
             }
-            TopLevelRoutingProgram::Prog(p) => p.eval(port_val),
+            TopLevelRoutingProgram::Prog(p) => p.checked_eval(port_val),
+        }
+    }
+
+    /// translates the address, with failed arithmetic translating past the
+    /// end of every bank
+    pub fn eval(&self, port_val: u64) -> u64 {
+        self.checked_eval(port_val).unwrap_or(u64::MAX)
+    }
+
+    /// the constants of the translation's cells and conditions, which the
+    /// width it is computed at must fit
+    pub fn constants(&self) -> Vec<u64> {
+        match self {
+            TopLevelRoutingProgram::Switch(cases, default) => cases
+                .iter()
+                .flat_map(|(condition, prog)| [condition.constants(), prog.constants()].concat())
+                .chain(default.constants())
+                .collect(),
+            TopLevelRoutingProgram::Prog(p) => p.constants(),
+        }
+    }
+
+    /// adds the cells translating `source` to the component and returns the
+    /// translated address along with the guard under which it applies. A
+    /// switch gives one per case, which applies when its condition holds and
+    /// no earlier one does
+    pub fn emit_calyx(
+        &self,
+        source: &Atom,
+        width: u64,
+        names: &mut Namer,
+        component: &mut calyx::Component,
+    ) -> Vec<(Guard, Atom)> {
+        match self {
+            TopLevelRoutingProgram::Switch(cases, default) => {
+                let mut translations = vec![];
+                let mut earlier = Guard::True;
+                for (condition, prog) in cases {
+                    let condition = condition.calyx_guard(source, width);
+                    translations.push((
+                        earlier.clone().and(condition.clone()),
+                        prog.emit_calyx(source.clone(), width, names, component),
+                    ));
+                    earlier = earlier.and(condition.negate());
+                }
+                translations.push((
+                    earlier,
+                    default.emit_calyx(source.clone(), width, names, component),
+                ));
+                translations
+            }
+            TopLevelRoutingProgram::Prog(p) => vec![(
+                Guard::True,
+                p.emit_calyx(source.clone(), width, names, component),
+            )],
         }
     }
 }
 
 impl MemoryBank {
-    pub fn can_read(&self, index: usize) -> bool {
-        let routed_index = self.routing.eval(index as u64);
+    pub fn can_read(&self, index: usize, phase: usize) -> bool {
+        let routed_index = self.routing(phase).eval(index as u64);
         let result = self.memory_layout.get(&(routed_index as usize));
         result.map(|x| x == index).unwrap_or(false)
     }
@@ -406,15 +1313,90 @@ impl From<TerminalRoutingProgram> for TopLevelRoutingProgram {
 }
 
 impl MemoryLayout {
-    pub fn new(start: usize, finish: usize, stride: Option<usize>) -> Self {
+    pub fn new(
+        start: usize,
+        finish: usize,
+        stride: Option<usize>,
+    ) -> Result<Self, DescriptionError> {
         let stride = stride.unwrap_or(1);
-        assert!(start < finish);
-        assert!(stride != 0);
+        if start >= finish {
+            return Err(DescriptionError::EmptyRange);
+        }
+        if stride == 0 {
+            return Err(DescriptionError::ZeroStride);
+        }
 
-        Self::Range {
+        Ok(Self::Range {
             start,
             finish,
             stride,
+        })
+    }
+
+    /// a cyclic layout which is unbounded until `with_memory_size` is called
+    pub fn cyclic(factor: usize, offset: usize) -> Result<Self, DescriptionError> {
+        Self::block_cyclic(1, factor, offset).map(|_| Self::Cyclic {
+            factor,
+            offset,
+            finish: usize::MAX,
+        })
+    }
+
+    /// a block-cyclic layout which is unbounded until `with_memory_size` is
+    /// called
+    pub fn block_cyclic(
+        block: usize,
+        factor: usize,
+        offset: usize,
+    ) -> Result<Self, DescriptionError> {
+        if block == 0 || factor == 0 {
+            return Err(DescriptionError::ZeroFactor);
+        }
+        if offset >= factor {
+            return Err(DescriptionError::OffsetPastFactor);
+        }
+
+        Ok(Self::BlockCyclic {
+            block,
+            factor,
+            offset,
+            finish: usize::MAX,
+        })
+    }
+
+    /// whether every address the layout holds lies within a memory of the
+    /// given size. Block-cyclic layouts also need their blocks to divide it
+    pub fn fits(&self, size: usize) -> bool {
+        match self {
+            MemoryLayout::Range { finish, .. } => *finish <= size,
+            MemoryLayout::Cyclic { offset, .. } => *offset < size,
+            MemoryLayout::BlockCyclic { block, offset, .. } => {
+                size % block == 0 && offset * block < size
+            }
+            MemoryLayout::Set(addresses) => addresses.iter().all(|x| *x < size),
+        }
+    }
+
+    /// ends the cyclic layouts at the end of a memory of the given size
+    pub fn with_memory_size(self, size: usize) -> Self {
+        match self {
+            MemoryLayout::Range { .. } | MemoryLayout::Set(_) => self,
+            MemoryLayout::Cyclic { factor, offset, .. } => MemoryLayout::Cyclic {
+                factor,
+                offset,
+                finish: size,
+            },
+            MemoryLayout::BlockCyclic {
+                block,
+                factor,
+                offset,
+                ..
+            } => MemoryLayout::BlockCyclic {
+                block,
+                factor,
+                offset,
+                finish: size,
+            },
         }
     }
 
@@ -426,6 +1408,18 @@ impl MemoryLayout {
                 finish,
                 stride,
             } => target >= start && target < finish && ((target - start) % stride) == 0,
+            MemoryLayout::Cyclic {
+                factor,
+                offset,
+                finish,
+            } => target < finish && target % factor == *offset,
+            MemoryLayout::BlockCyclic {
+                block,
+                factor,
+                offset,
+                finish,
+            } => target < finish && (target / block) % factor == *offset,
+            MemoryLayout::Set(addresses) => addresses.contains(target),
         }
     }
 
@@ -433,6 +1427,11 @@ impl MemoryLayout {
         if self._contains(target) {
             let out = match self {
                 MemoryLayout::Range { start, stride, .. } => (target - start) / stride,
+                MemoryLayout::Cyclic { factor, .. } => target / factor,
+                MemoryLayout::BlockCyclic { block, factor, .. } => {
+                    (target / (block * factor)) * block + target % block
+                }
+                MemoryLayout::Set(addresses) => addresses.iter().position(|x| x == target).unwrap(),
             };
             return Some(out);
         }
@@ -445,36 +1444,37 @@ impl MemoryLayout {
                 start,
                 finish,
                 stride,
-            } => ((finish - start) / stride) + 1,
+            } => (finish - start).div_ceil(*stride),
+            MemoryLayout::Cyclic {
+                factor,
+                offset,
+                finish,
+            } => finish.saturating_sub(*offset).div_ceil(*factor),
+            MemoryLayout::BlockCyclic {
+                block,
+                factor,
+                offset,
+                finish,
+            } => {
+                let period = block * factor;
+                let partial = (finish % period).saturating_sub(offset * block);
+                (finish / period) * block + partial.min(*block)
+            }
+            MemoryLayout::Set(addresses) => addresses.len(),
         }
     }
 
     pub fn _gen_array(&self) -> Vec<usize> {
-        let mut out = Vec::with_capacity(self.size());
-        match self {
-            MemoryLayout::Range {
-                start,
-                finish,
-                stride,
-            } => {
-                let mut current = *start;
-                while current < *finish {
-                    out.push(current);
-                    current += stride;
-                }
+        let out = (0..self.size())
+            .map_while(|idx| self.get(&idx))
+            .collect::<Vec<_>>();
 
-                debug_assert!(out
-                    .iter()
-                    .enumerate()
-                    .all(|(i, x)| self._index_of(x).unwrap_or_else(|| panic!("{:?}", x)) == i));
-                debug_assert!(out
-                    .iter()
-                    .enumerate()
-                    .all(|(i, x)| self.get(&i).unwrap() == *x));
+        debug_assert!(out
+            .iter()
+            .enumerate()
+            .all(|(i, x)| self._index_of(x).unwrap_or_else(|| panic!("{:?}", x)) == i));
 
-                out
-            }
-        }
+        out
     }
 
     pub fn _last_idx(&self) -> usize {
@@ -485,13 +1485,27 @@ impl MemoryLayout {
         if *idx >= self.size() {
             return None;
         }
-        match self {
-            MemoryLayout::Range { start, stride, .. } => Some(start + (stride * idx)),
-        }
+        let target = match self {
+            MemoryLayout::Range { start, stride, .. } => start + (stride * idx),
+            MemoryLayout::Cyclic { factor, offset, .. } => idx * factor + offset,
+            MemoryLayout::BlockCyclic {
+                block,
+                factor,
+                offset,
+                ..
+            } => (idx / block) * block * factor + offset * block + idx % block,
+            MemoryLayout::Set(addresses) => addresses[*idx],
+        };
+        Some(target).filter(|x| self._contains(x))
     }
 }
 
 impl TopLevelMemoryLayout {
+    /// every address held, in the order they are stored
+    pub fn addresses(&self) -> Vec<usize> {
+        self.mems.iter().flat_map(|x| x._gen_array()).collect()
+    }
+
     pub fn _contains(&self, target: &usize) -> bool {
         self.mems.iter().any(|x| x._contains(target))
     }
@@ -512,13 +1526,12 @@ impl TopLevelMemoryLayout {
     }
 
     pub fn get(&self, idx: &usize) -> Option<usize> {
-        let mut bottom_idx = 0_usize;
+        let mut offset = *idx;
         for mem in self.mems.iter() {
-            if idx - bottom_idx < mem.size() {
-                return mem.get(&(idx - bottom_idx));
-            } else {
-                bottom_idx += mem.size();
+            if offset < mem.size() {
+                return mem.get(&offset);
             }
+            offset -= mem.size();
         }
         None
     }
@@ -534,6 +1547,19 @@ impl TerminalRoutingProgram {
     pub fn pretty_print(&self) -> String {
         match self {
             TerminalRoutingProgram::RShift(n) => format!("{} >> {}", INPUT, n),
+            TerminalRoutingProgram::LShift(n) => format!("{} << {}", INPUT, n),
+            TerminalRoutingProgram::And(n) => format!("{} & {}", INPUT, n),
+            TerminalRoutingProgram::Xor(n) => format!("{} ^ {}", INPUT, n),
+            TerminalRoutingProgram::Mod(n) => format!("{} % {}", INPUT, n),
+            TerminalRoutingProgram::Div(n) => format!("{} / {}", INPUT, n),
+            TerminalRoutingProgram::Mul(n) => format!("{} * {}", INPUT, n),
+            TerminalRoutingProgram::Lookup(table) => {
+                let entries = table
+                    .iter()
+                    .map(|(address, index)| format!("{} -> {}", address, index))
+                    .collect::<Vec<_>>();
+                format!("lookup {{{}}}", entries.join(", "))
+            }
             TerminalRoutingProgram::Add(n) => format!("{} + {}", INPUT, n),
             TerminalRoutingProgram::SubPortVal(n) => format!("{} - {}", INPUT, n),
             TerminalRoutingProgram::SubValPort(n) => format!("{} - {}", n, INPUT),
@@ -544,23 +1570,11 @@ impl TerminalRoutingProgram {
 }
 
 impl SequenceRoutingProg {
-    pub fn pretty_print(&self, level: usize) -> String {
+    pub fn pretty_print(&self) -> String {
         match self {
             SequenceRoutingProg::Sequence(s) => {
-                let mut string = String::new();
-                write!(string, "[").unwrap();
-                write!(string, "{}{}", " ".repeat(level * 4), s[0].pretty_print()).unwrap();
-                for element in s.iter().skip(1) {
-                    writeln!(
-                        string,
-                        ",{}{}",
-                        " ".repeat(level * 4),
-                        element.pretty_print()
-                    )
-                    .unwrap();
-                }
-                write!(string, "{}]", " ".repeat(level * 4)).unwrap();
-                string
+                let steps = s.iter().map(|x| x.pretty_print()).collect::<Vec<_>>();
+                format!("[{}]", steps.join("; "))
             }
             SequenceRoutingProg::Prog(p) => p.pretty_print(),
         }
@@ -590,43 +1604,48 @@ impl Condition {
                 format!("{} {} {}", val, op.pretty_print(), INPUT)
             }
             Condition::And(first, second) => {
-                format!("({} && {})", first.pretty_print(), second.pretty_print())
+                format!("({} && {})", first.operand(), second.operand())
             }
             Condition::Or(first, second) => {
-                format!("({} || {})", first.pretty_print(), second.pretty_print())
+                format!("({} || {})", first.operand(), second.operand())
             }
             Condition::Not(c) => format!("!({})", c.pretty_print()),
         }
     }
+
+    /// prints the condition as an operand of `&&` or `||`, where a negation
+    /// needs parentheses of its own
+    fn operand(&self) -> String {
+        match self {
+            Condition::Not(_) => format!("({})", self.pretty_print()),
+            _ => self.pretty_print(),
+        }
+    }
 }
 
 impl TopLevelRoutingProgram {
+    /// prints the translation as it appears after `translation:` on a line
+    /// indented by `level`, with the cases of a switch on their own lines
     pub fn pretty_print(&self, level: usize) -> String {
         match self {
             TopLevelRoutingProgram::Switch(cases, default) => {
                 let mut string = String::new();
-                writeln!(string, "{}switch {{", " ".repeat(level * 4)).unwrap();
+                writeln!(string, "switch {{").unwrap();
                 for (cond, prog) in cases {
                     writeln!(
                         string,
-                        "{}\t{} -> {},",
-                        " ".repeat(level * 4),
+                        "{}{} -> {},",
+                        indent(level + 1),
                         cond.pretty_print(),
-                        prog.pretty_print(level + 1)
+                        prog.pretty_print()
                     )
                     .unwrap();
                 }
-                writeln!(
-                    string,
-                    "{}\t -> {}",
-                    " ".repeat(level * 4),
-                    default.pretty_print(level + 1)
-                )
-                .unwrap();
-                writeln!(string, "{}}}", " ".repeat(level * 4)).unwrap();
+                writeln!(string, "{}-> {}", indent(level + 1), default.pretty_print()).unwrap();
+                write!(string, "{}}}", indent(level)).unwrap();
                 string
             }
-            TopLevelRoutingProgram::Prog(p) => p.pretty_print(level),
+            TopLevelRoutingProgram::Prog(p) => p.pretty_print(),
         }
     }
 }
@@ -639,66 +1658,500 @@ impl MemoryLayout {
                 finish,
                 stride,
             } => format!("[{}:{}:{}]", start, finish, stride),
+            MemoryLayout::Cyclic { factor, offset, .. } => {
+                format!("cyclic({}, {})", factor, offset)
+            }
+            MemoryLayout::BlockCyclic {
+                block,
+                factor,
+                offset,
+                ..
+            } => format!("block_cyclic({}, {}, {})", block, factor, offset),
+            MemoryLayout::Set(addresses) => {
+                let addresses = addresses.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+                format!("{{{}}}", addresses.join(", "))
+            }
         }
     }
 }
 
 impl TopLevelMemoryLayout {
-    pub fn pretty_print(&self, level: usize) -> String {
-        match self.mems.len() {
-            0 => unreachable!(),
-            1 => self.mems[0].pretty_print(),
-            _ => {
-                let mut string = String::new();
-                writeln!(string, "{}[", " ".repeat(level * 4)).unwrap();
-                write!(
-                    string,
-                    "{}\t{}",
-                    " ".repeat(level * 4),
-                    self.mems[0].pretty_print()
-                )
-                .unwrap();
-                for x in self.mems.iter().skip(1) {
-                    write!(string, ",\n{}\t{}", " ".repeat(level * 4), x.pretty_print()).unwrap();
-                }
-                writeln!(string, "{}\n]", " ".repeat(level * 4)).unwrap();
-                string
+    pub fn pretty_print(&self) -> String {
+        match self.mems.as_slice() {
+            [] => unreachable!(),
+            [mem] => mem.pretty_print(),
+            mems => {
+                let mems = mems.iter().map(|x| x.pretty_print()).collect::<Vec<_>>();
+                format!("[{}]", mems.join(", "))
             }
         }
     }
 }
 
+/// the indentation of a line nested `level` blocks deep
+fn indent(level: usize) -> String {
+    " ".repeat(level * 4)
+}
+
 impl MemoryBank {
     pub fn pretty_print(&self, level: usize) -> String {
         let mut string = String::new();
-        writeln!(string, "{}bank {{", " ".repeat(level * 4)).unwrap();
-        writeln!(
-            string,
-            "{}\tlayout: {}",
-            " ".repeat(level * 4),
-            self.memory_layout.pretty_print(level + 1)
-        )
-        .unwrap();
+        match &self.name {
+            Some(name) => writeln!(string, "{}bank {} {{", indent(level), name).unwrap(),
+            None => writeln!(string, "{}bank {{", indent(level)).unwrap(),
+        }
         writeln!(
             string,
-            "{}\ttranslation: {}",
-            " ".repeat(level * 4),
-            self.routing.pretty_print(level + 1)
+            "{}layout: {}",
+            indent(level + 1),
+            self.memory_layout.pretty_print()
         )
         .unwrap();
-        writeln!(string, "{}}}", " ".repeat(level * 4)).unwrap();
+        if let [routing] = self.routing.as_slice() {
+            writeln!(
+                string,
+                "{}translation: {}",
+                indent(level + 1),
+                routing.pretty_print(level + 1)
+            )
+            .unwrap();
+        } else {
+            for (phase, routing) in self.routing.iter().enumerate() {
+                writeln!(
+                    string,
+                    "{}translation phase {}: {}",
+                    indent(level + 1),
+                    phase,
+                    routing.pretty_print(level + 1)
+                )
+                .unwrap();
+            }
+        }
+        if self.latency != 0 {
+            writeln!(string, "{}latency: {}", indent(level + 1), self.latency).unwrap();
+        }
+        if let Some(storage) = self.storage {
+            writeln!(string, "{}storage: {}", indent(level + 1), storage).unwrap();
+        }
+        writeln!(string, "{}}}", indent(level)).unwrap();
         string
     }
 }
 
 impl Component {
+    /// prints the description in its canonical form, which parses back into
+    /// an identical component
     pub fn pretty_print(&self) -> String {
-        let mut string = String::new();
-        writeln!(string, "memory<{},{}> {{", self.width, self.size).unwrap();
-        for bank in &self.banks {
-            writeln!(string, "{}", bank.pretty_print(1)).unwrap();
+        let banks = self
+            .banks
+            .iter()
+            .map(|bank| bank.pretty_print(1))
+            .collect::<Vec<_>>();
+        let name = match &self.name {
+            Some(name) => format!(" {}", name),
+            None => String::new(),
+        };
+        format!(
+            "memory{}<{},{}> {{\n{}}}\n",
+            name,
+            self.width,
+            self.size,
+            banks.join("\n")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Component, MemoryBank, MemoryLayout, TerminalRoutingProgram, TopLevelMemoryLayout,
+    };
+    use crate::dsl::ast::AstParser;
+    use crate::Trace;
+
+    #[test]
+    fn lookup_misses_translate_past_every_bank() {
+        let table = TerminalRoutingProgram::Lookup(vec![(3, 0), (7, 1)]);
+        assert_eq!(table.checked_eval(3), Some(0));
+        assert_eq!(table.checked_eval(7), Some(1));
+        assert_eq!(table.checked_eval(5), Some(u64::MAX));
+    }
+
+    #[test]
+    fn deserializing_checks_layouts() {
+        let layout = MemoryLayout::new(2, 8, Some(3)).unwrap();
+        let json = serde_json::to_string(&layout).unwrap();
+        assert_eq!(serde_json::from_str::<MemoryLayout>(&json).unwrap(), layout);
+
+        for json in [
+            r#"{"Range": {"start": 0, "finish": 8, "stride": 0}}"#,
+            r#"{"Range": {"start": 8, "finish": 8, "stride": 1}}"#,
+            r#"{"Cyclic": {"factor": 0, "offset": 0, "finish": 8}}"#,
+            r#"{"BlockCyclic": {"block": 2, "factor": 2, "offset": 2, "finish": 8}}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<MemoryLayout>(json).is_err(),
+                "{}",
+                json
+            );
         }
-        writeln!(string, "}}").unwrap();
-        string
+    }
+
+    #[test]
+    fn deserializing_checks_banks() {
+        let layout = MemoryLayout::cyclic(2, 1).unwrap().with_memory_size(8);
+        let bank = MemoryBank::new(
+            vec![TerminalRoutingProgram::RShift(1).into()],
+            super::TopLevelMemoryLayout::new(vec![layout]),
+        )
+        .unwrap()
+        .with_latency(2);
+        let json = serde_json::to_string(&bank).unwrap();
+        assert_eq!(serde_json::from_str::<MemoryBank>(&json).unwrap(), bank);
+
+        let json = r#"{"name": null, "routing": [], "storage": null,
+            "memory_layout": {"mems": [{"Set": [1]}]}}"#;
+        assert!(serde_json::from_str::<MemoryBank>(json).is_err());
+    }
+
+    #[test]
+    fn deserializing_checks_components() {
+        let comp = crate::dsl::ast::AstParser::parse_description(
+            "memory<32, 16> {
+                bank { layout: cyclic(2, 0) translation: INPUT / 2 }
+                bank { layout: cyclic(2, 0) translation: INPUT / 2 storage: 0 }
+            }",
+            &[],
+        )
+        .unwrap()
+        .memories
+        .remove(0);
+        let json = serde_json::to_string(&comp).unwrap();
+        assert_eq!(serde_json::from_str::<Component>(&json).unwrap(), comp);
+
+        let cyclic = r#""Cyclic":{"factor":2,"offset":0,"finish":16}"#;
+        for (from, to, count) in [
+            // missing storage
+            (r#""storage":0"#, r#""storage":2"#, 1),
+            // shared storage
+            (r#""storage":null"#, r#""storage":1"#, 1),
+            // layouts differing from their storage's
+            (r#""offset":0"#, r#""offset":1"#, 1),
+            // layouts past the memory
+            (cyclic, r#""Range":{"start":0,"finish":20,"stride":2}"#, 2),
+        ] {
+            let broken = json.replacen(from, to, count);
+            assert_ne!(broken, json);
+            assert!(
+                serde_json::from_str::<Component>(&broken).is_err(),
+                "{}",
+                broken
+            );
+        }
+        // cyclic layouts end with the memory however far they claim to go
+        let unbounded = json.replace(r#""finish":16"#, &format!(r#""finish":{}"#, usize::MAX));
+        assert_eq!(serde_json::from_str::<Component>(&unbounded).unwrap(), comp);
+    }
+
+    #[test]
+    fn calyx_constants_fit_their_width() {
+        let comp = crate::dsl::ast::AstParser::parse_description(
+            "memory mem<32, 16> {
+                bank {
+                    layout: {3, 7}
+                    translation: switch {
+                        INPUT < 100 -> lookup {3 -> 0, 7 -> 1, 300 -> 1},
+                        -> [INPUT + 20; INPUT - 21]
+                    }
+                }
+            }",
+            &[],
+        )
+        .unwrap()
+        .memories
+        .remove(0);
+        let calyx = Component::emit_calyx_program(&[comp], false);
+        // the translation is computed wide enough for 300
+        assert!(calyx.contains("std_pad(4, 9)"), "{}", calyx);
+        assert!(calyx.contains("9'd300"), "{}", calyx);
+        let literals = calyx
+            .split(|c: char| !c.is_ascii_alphanumeric() && c != '\'')
+            .filter_map(|x| x.split_once("'d"))
+            .map(|(width, value)| (width.parse::<u32>().unwrap(), value.parse::<u64>().unwrap()))
+            .collect::<Vec<_>>();
+        assert!(!literals.is_empty());
+        for (width, value) in literals {
+            assert!(value >> width == 0, "{}'d{} in\n{}", width, value, calyx);
+        }
+    }
+
+    #[test]
+    fn ranges_end_before_their_finish() {
+        for (start, finish, stride, size) in [
+            (0, 16, 1, 16),
+            (0, 16, 2, 8),
+            (1, 16, 2, 8),
+            (1, 15, 2, 7),
+            (0, 15, 4, 4),
+            (0, 17, 4, 5),
+            (3, 4, 5, 1),
+        ] {
+            let range = MemoryLayout::new(start, finish, Some(stride)).unwrap();
+            assert_eq!(range.size(), size, "{:?}", range);
+            assert_eq!(range._gen_array().len(), size, "{:?}", range);
+        }
+        // later layouts of a bank start right after the earlier ones
+        let bank = TopLevelMemoryLayout::new(vec![
+            MemoryLayout::new(0, 4, None).unwrap(),
+            MemoryLayout::new(8, 12, None).unwrap(),
+        ]);
+        assert_eq!(bank.size(), 8);
+        assert_eq!(bank.get(&4), Some(8));
+        assert_eq!(bank.addresses(), vec![0, 1, 2, 3, 8, 9, 10, 11]);
+    }
+
+    fn memory(source: &str) -> Result<Component, String> {
+        AstParser::parse_description(source, &[])
+            .map(|mut x| x.memories.remove(0))
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn named_ports_are_matched_to_trace_columns() {
+        let comp = memory(
+            "memory<8, 16> {
+                bank left { layout: [0:8] translation: INPUT }
+                bank { layout: [8:16] translation: INPUT - 8 }
+            }",
+        )
+        .unwrap();
+        assert_eq!(comp.banks()[0].port_name(0), "left");
+        assert_eq!(comp.banks()[1].port_name(1), "bank_1");
+        assert!(comp.emit_verilog().contains("input logic [3:0] left_addr"));
+
+        let trace = Trace::parse_trace(
+            r#"{"size": 16, "bitwidth": 8, "ports": [{"name": "left"}, {}],
+                "trace": [[1, 9], [7, 15]]}"#,
+        )
+        .unwrap();
+        assert_eq!(comp.bank_for_port(&trace, 0).unwrap(), 0);
+        assert_eq!(comp.bank_for_port(&trace, 1).unwrap(), 1);
+        assert!(comp.vailidate(&trace, 0, None).is_ok());
+
+        let unknown = Trace::parse_trace(
+            r#"{"size": 16, "bitwidth": 8, "ports": [{"name": "right"}], "trace": [[1]]}"#,
+        )
+        .unwrap();
+        assert!(comp.bank_for_port(&unknown, 0).is_err());
+    }
+
+    #[test]
+    fn port_names_are_unique_and_not_keywords() {
+        for (banks, error) in [
+            ("bank a { BODY } bank a { BODY }", "duplicate port name `a`"),
+            // the second bank is called `bank_1` when unnamed
+            (
+                "bank bank_1 { BODY } bank { BODY }",
+                "duplicate port name `bank_1`",
+            ),
+            ("bank switch { BODY }", "`switch` is a keyword"),
+            ("bank Latency { BODY }", "`Latency` is a keyword"),
+        ] {
+            let banks = banks.replace("BODY", "layout: [0:8] translation: INPUT");
+            let error_message = memory(&format!("memory<8, 8> {{ {} }}", banks)).unwrap_err();
+            assert!(error_message.contains(error), "{}", error_message);
+        }
+        let trace = Trace::parse_trace(
+            r#"{"size": 8, "bitwidth": 8, "ports": [{"name": "bank_1"}], "trace": [[0, 1]]}"#,
+        );
+        assert!(trace.unwrap_err().to_string().contains("`bank_1`"));
+    }
+
+    #[test]
+    fn translation_steps_refuse_to_wrap() {
+        use TerminalRoutingProgram as T;
+        for (step, address, index) in [
+            (T::Add(3), 5, Some(8)),
+            (T::Add(u64::MAX), 1, None),
+            (T::SubPortVal(2), 5, Some(3)),
+            (T::SubPortVal(6), 5, None),
+            (T::SubValPort(7), 5, Some(2)),
+            (T::SubValPort(4), 5, None),
+            (T::LShift(2), 5, Some(20)),
+            (T::LShift(62), 5, None),
+            (T::RShift(1), 5, Some(2)),
+            (T::And(6), 5, Some(4)),
+            (T::Xor(6), 5, Some(3)),
+            (T::Mul(3), 5, Some(15)),
+            (T::Mul(u64::MAX), 5, None),
+            (T::Div(2), 5, Some(2)),
+            (T::Div(0), 5, None),
+            (T::Mod(3), 5, Some(2)),
+            (T::Mod(0), 5, None),
+        ] {
+            assert_eq!(step.checked_eval(address), index, "{:?}", step);
+        }
+    }
+
+    #[test]
+    fn powers_of_two_become_shifts_and_masks() {
+        use TerminalRoutingProgram as T;
+        let primitive = |step: T| step.calyx_operation().map(|x| (x.1, x.2));
+        assert_eq!(primitive(T::Mul(8)), Some(("std_lsh", 3)));
+        assert_eq!(primitive(T::Div(4)), Some(("std_rsh", 2)));
+        assert_eq!(primitive(T::Mod(4)), Some(("std_and", 3)));
+        assert_eq!(primitive(T::Mul(3)), Some(("std_unsyn_mult", 3)));
+        assert_eq!(primitive(T::Mod(3)), Some(("std_unsyn_mod", 3)));
+    }
+
+    #[test]
+    fn solver_steps_parse_in_hex_and_binary() {
+        use TerminalRoutingProgram as T;
+        for (source, step) in [
+            ("(Mul #x3)", T::Mul(3)),
+            ("(Div #b100)", T::Div(4)),
+            ("(Mod #x5)", T::Mod(5)),
+            ("(LShift #x1)", T::LShift(1)),
+            ("(BitAnd #b011)", T::And(3)),
+            ("(Xor #xa)", T::Xor(10)),
+        ] {
+            let parsed = AstParser::parse_z3_address_translation(source).unwrap();
+            assert_eq!(parsed, step.into(), "{}", source);
+        }
+        // literals too wide for an address are errors rather than panics
+        let wide = format!("(Add #x1{})", "0".repeat(16));
+        let error = AstParser::parse_z3_address_translation(&wide).unwrap_err();
+        assert!(
+            error.to_string().contains("non-negative number"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn cyclic_layouts_deal_out_addresses() {
+        let cyclic = MemoryLayout::cyclic(3, 1).unwrap().with_memory_size(10);
+        assert_eq!(cyclic.size(), 3);
+        assert_eq!(cyclic._gen_array(), vec![1, 4, 7]);
+        assert_eq!(cyclic._index_of(&7), Some(2));
+        assert_eq!(cyclic._index_of(&8), None);
+
+        // blocks of two dealt out to three banks, the last block cut short
+        let blocks = MemoryLayout::block_cyclic(2, 3, 1)
+            .unwrap()
+            .with_memory_size(10);
+        assert_eq!(blocks.size(), 4);
+        assert_eq!(blocks._gen_array(), vec![2, 3, 8, 9]);
+        assert_eq!(blocks._index_of(&9), Some(3));
+        let last = MemoryLayout::block_cyclic(4, 2, 1)
+            .unwrap()
+            .with_memory_size(10);
+        assert_eq!(last._gen_array(), vec![4, 5, 6, 7]);
+
+        let comp = memory(
+            "memory<8, 8> {
+                bank { layout: cyclic(2, 0) translation: INPUT / 2 }
+                bank { layout: cyclic(2, 1) translation: (INPUT - 1) / 2 }
+            }",
+        )
+        .unwrap();
+        assert_eq!(comp.banks()[1].size(), 4);
+        let trace =
+            Trace::parse_trace(r#"{"size": 8, "bitwidth": 8, "trace": [[0, 1], [6, 7], [1, 0]]}"#)
+                .unwrap();
+        // the last line asks each bank for an address the other holds
+        let error = comp.vailidate(&trace, 0, None).unwrap_err();
+        assert!(error.to_string().contains("cannot read address 1"));
+    }
+
+    #[test]
+    fn cyclic_layouts_need_a_factor_past_their_offset() {
+        assert!(MemoryLayout::cyclic(0, 0).is_err());
+        assert!(MemoryLayout::cyclic(2, 2).is_err());
+        assert!(MemoryLayout::block_cyclic(0, 2, 1).is_err());
+        assert!(MemoryLayout::block_cyclic(2, 2, 1).is_ok());
+    }
+
+    #[test]
+    fn set_layouts_read_through_lookup_tables() {
+        let comp = memory(
+            "memory<8, 16> {
+                bank { layout: {3, 7, 12} translation: lookup {3 -> 0, 7 -> 1, 12 -> 2} }
+            }",
+        )
+        .unwrap();
+        assert_eq!(comp.banks()[0].size(), 3);
+        let readable = (0..16)
+            .filter(|x| comp.banks()[0].can_read(*x, 0))
+            .collect::<Vec<_>>();
+        assert_eq!(readable, vec![3, 7, 12]);
+
+        let trace = |requests: &str| {
+            Trace::parse_trace(format!(
+                r#"{{"size": 16, "bitwidth": 8, "trace": {}}}"#,
+                requests
+            ))
+            .unwrap()
+        };
+        assert!(comp.vailidate(&trace("[[3], [12], [7]]"), 0, None).is_ok());
+        assert!(comp.vailidate(&trace("[[3], [4]]"), 0, None).is_err());
+    }
+
+    #[test]
+    fn latencies_make_sequential_memories_and_registers() {
+        use super::calyx::{Atom, Control};
+        let comp = memory(
+            "memory<32, 16> {
+                bank { layout: [0:8] translation: INPUT latency: 2 }
+                bank { layout: [0:8] translation: INPUT latency: 1 storage: 0 }
+                bank { layout: [8:16] translation: INPUT - 8 }
+            }",
+        )
+        .unwrap();
+        assert!(comp.is_sequential(1));
+        assert!(!comp.is_sequential(2));
+        let calyx = comp.calyx_component();
+        let prototype = |name: &str| {
+            calyx
+                .cells
+                .iter()
+                .find(|x| x.name == name)
+                .map(|x| x.prototype.as_str())
+        };
+        assert_eq!(prototype("bank_0"), Some("seq_mem_d1"));
+        assert_eq!(prototype("bank_1"), None);
+        assert_eq!(prototype("bank_2"), Some("std_mem_d1"));
+        // the read takes the first cycle of latency, and only the port with a
+        // second one registers its data
+        assert_eq!(prototype("bank_0_reg0"), Some("std_reg"));
+        assert_eq!(prototype("bank_1_reg0"), None);
+        assert_eq!(
+            calyx.control,
+            Control::Seq(vec![
+                Control::Enable("bank_0_read0".to_string()),
+                Control::Enable("bank_0_stage0".to_string()),
+            ])
+        );
+        let driver = |port: &str| {
+            calyx
+                .wires
+                .iter()
+                .find(|x| x.dst == Atom::this(port))
+                .map(|x| x.src.clone())
+        };
+        assert_eq!(
+            driver("read_bank_0_addr"),
+            Some(Atom::cell("bank_0_reg0", "out"))
+        );
+        assert_eq!(
+            driver("read_bank_1_addr"),
+            Some(Atom::cell("bank_0", "read_data"))
+        );
+        assert_eq!(
+            driver("read_bank_2_addr"),
+            Some(Atom::cell("bank_2", "read_data"))
+        );
+        let program = Component::emit_calyx_program(&[comp], false);
+        assert!(program.contains("import \"primitives/memories.futil\";"));
     }
 }
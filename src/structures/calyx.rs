@@ -0,0 +1,612 @@
+//! A small Calyx AST used to emit well-formed programs. Components are built
+//! from cells, guarded assignments, groups and control, and printed through
+//! their `Display` implementations
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+
+use super::ComparisonOperator;
+
+/// hands out cell and group names that are unique within a component
+#[derive(Debug, Default)]
+pub struct Namer {
+    used: HashSet<String>,
+    counters: HashMap<String, usize>,
+}
+
+impl Namer {
+    /// marks a name chosen elsewhere, such as a port, as taken
+    pub fn reserve(&mut self, name: &str) {
+        self.used.insert(name.to_string());
+    }
+
+    /// the name itself when it is not yet taken, otherwise a fresh one
+    pub fn claim(&mut self, name: &str) -> String {
+        if self.used.insert(name.to_string()) {
+            name.to_string()
+        } else {
+            self.fresh(name)
+        }
+    }
+
+    /// a name made of the prefix and a counter that is not yet taken
+    pub fn fresh(&mut self, prefix: &str) -> String {
+        loop {
+            let counter = self.counters.entry(prefix.to_string()).or_default();
+            let name = format!("{}{}", prefix, counter);
+            *counter += 1;
+            if self.used.insert(name.clone()) {
+                return name;
+            }
+        }
+    }
+}
+
+/// a value read or written by an assignment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Atom {
+    /// a port of the component being defined
+    This(String),
+    /// a port of one of its cells
+    Cell(String, String),
+    /// a hole of one of its groups
+    Hole(String, String),
+    Constant {
+        width: u64,
+        value: u64,
+    },
+}
+
+impl Atom {
+    pub fn this(port: &str) -> Self {
+        Atom::This(port.to_string())
+    }
+
+    pub fn cell(cell: &str, port: &str) -> Self {
+        Atom::Cell(cell.to_string(), port.to_string())
+    }
+
+    pub fn hole(group: &str, hole: &str) -> Self {
+        Atom::Hole(group.to_string(), hole.to_string())
+    }
+
+    pub fn constant(width: u64, value: u64) -> Self {
+        Atom::Constant { width, value }
+    }
+}
+
+impl Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Atom::This(port) => write!(f, "{}", port),
+            Atom::Cell(cell, port) => write!(f, "{}.{}", cell, port),
+            Atom::Hole(group, hole) => write!(f, "{}[{}]", group, hole),
+            Atom::Constant { width, value } => write!(f, "{}'d{}", width, value),
+        }
+    }
+}
+
+/// the condition under which an assignment is active
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Guard {
+    True,
+    Port(Atom),
+    Compare(Atom, ComparisonOperator, Atom),
+    And(Box<Guard>, Box<Guard>),
+    Or(Box<Guard>, Box<Guard>),
+    Not(Box<Guard>),
+}
+
+impl Guard {
+    /// the conjunction of the guards, dropping guards that always hold
+    pub fn and(self, other: Guard) -> Guard {
+        match (self, other) {
+            (Guard::True, x) | (x, Guard::True) => x,
+            (x, y) => Guard::And(Box::new(x), Box::new(y)),
+        }
+    }
+
+    pub fn or(self, other: Guard) -> Guard {
+        Guard::Or(Box::new(self), Box::new(other))
+    }
+
+    pub fn negate(self) -> Guard {
+        Guard::Not(Box::new(self))
+    }
+}
+
+impl Guard {
+    fn for_each_atom(&mut self, f: &mut dyn FnMut(&mut Atom)) {
+        match self {
+            Guard::True => {}
+            Guard::Port(atom) => f(atom),
+            Guard::Compare(left, _, right) => {
+                f(left);
+                f(right);
+            }
+            Guard::And(left, right) | Guard::Or(left, right) => {
+                left.for_each_atom(f);
+                right.for_each_atom(f);
+            }
+            Guard::Not(guard) => guard.for_each_atom(f),
+        }
+    }
+
+    /// the guards joined by nested disjunctions
+    fn disjuncts(&self) -> Vec<String> {
+        match self {
+            Guard::Or(left, right) => {
+                let mut disjuncts = left.disjuncts();
+                disjuncts.extend(right.disjuncts());
+                disjuncts
+            }
+            Guard::And(..) => vec![format!("({})", self)],
+            guard => vec![guard.to_string()],
+        }
+    }
+}
+
+impl Display for Guard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Guard::True => write!(f, "1'd1"),
+            Guard::Port(atom) => write!(f, "{}", atom),
+            Guard::Compare(left, op, right) => {
+                write!(f, "({} {} {})", left, op.pretty_print(), right)
+            }
+            Guard::And(left, right) => write!(f, "{} & {}", left, right),
+            Guard::Or(..) => write!(f, "({})", self.disjuncts().join(" | ")),
+            Guard::Not(guard) => match **guard {
+                Guard::And(..) => write!(f, "!({})", guard),
+                _ => write!(f, "!{}", guard),
+            },
+        }
+    }
+}
+
+/// drives `dst` with `src` while the guard holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub dst: Atom,
+    pub src: Atom,
+    pub guard: Guard,
+}
+
+impl Assignment {
+    pub fn new(dst: Atom, src: Atom) -> Self {
+        Self {
+            dst,
+            src,
+            guard: Guard::True,
+        }
+    }
+
+    pub fn guarded(dst: Atom, guard: Guard, src: Atom) -> Self {
+        Self { dst, src, guard }
+    }
+}
+
+impl Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.guard {
+            Guard::True => write!(f, "{} = {};", self.dst, self.src),
+            guard => write!(f, "{} = {} ? {};", self.dst, guard, self.src),
+        }
+    }
+}
+
+/// an instance of a primitive or component
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cell {
+    pub name: String,
+    pub prototype: String,
+    pub args: Vec<u64>,
+    pub external: bool,
+}
+
+impl Cell {
+    pub fn new(name: &str, prototype: &str, args: Vec<u64>) -> Self {
+        Self {
+            name: name.to_string(),
+            prototype: prototype.to_string(),
+            args,
+            external: false,
+        }
+    }
+
+    /// marks the cell as visible outside of the program
+    pub fn external(mut self) -> Self {
+        self.external = true;
+        self
+    }
+}
+
+impl Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = self.args.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        write!(
+            f,
+            "{}{} = {}({});",
+            if self.external { "@external " } else { "" },
+            self.name,
+            self.prototype,
+            args.join(", ")
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Port {
+    pub name: String,
+    pub width: u64,
+}
+
+impl Port {
+    pub fn new(name: &str, width: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            width,
+        }
+    }
+}
+
+impl Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.width)
+    }
+}
+
+/// assignments active while the group is enabled, which signal completion
+/// by driving the group's `done` hole
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub name: String,
+    pub assignments: Vec<Assignment>,
+}
+
+/// the schedule of a component's groups
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Control {
+    Empty,
+    Enable(String),
+    Seq(Vec<Control>),
+    Par(Vec<Control>),
+}
+
+impl Control {
+    /// runs the statements in order, without a block around a single one
+    pub fn seq(mut stmts: Vec<Control>) -> Control {
+        stmts.retain(|x| *x != Control::Empty);
+        match stmts.len() {
+            0 => Control::Empty,
+            1 => stmts.remove(0),
+            _ => Control::Seq(stmts),
+        }
+    }
+
+    /// runs the statements together, without a block around a single one
+    pub fn par(mut stmts: Vec<Control>) -> Control {
+        stmts.retain(|x| *x != Control::Empty);
+        match stmts.len() {
+            0 => Control::Empty,
+            1 => stmts.remove(0),
+            _ => Control::Par(stmts),
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        let pad = " ".repeat(indent);
+        let block = |f: &mut fmt::Formatter<'_>, keyword: &str, stmts: &[Control]| {
+            writeln!(f, "{}{} {{", pad, keyword)?;
+            for stmt in stmts {
+                stmt.write(f, indent + 4)?;
+            }
+            writeln!(f, "{}}}", pad)
+        };
+        match self {
+            Control::Empty => Ok(()),
+            Control::Enable(group) => writeln!(f, "{}{};", pad, group),
+            Control::Seq(stmts) => block(f, "seq", stmts),
+            Control::Par(stmts) => block(f, "par", stmts),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    pub name: String,
+    pub inputs: Vec<Port>,
+    pub outputs: Vec<Port>,
+    pub cells: Vec<Cell>,
+    pub groups: Vec<Group>,
+    /// the assignments active whenever the component is
+    pub wires: Vec<Assignment>,
+    pub control: Control,
+}
+
+impl Component {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            inputs: vec![],
+            outputs: vec![],
+            cells: vec![],
+            groups: vec![],
+            wires: vec![],
+            control: Control::Empty,
+        }
+    }
+
+    /// turns the component's ports into cells of the same name, registers
+    /// for the inputs and wires for the outputs, so that the component can be
+    /// driven from its own groups
+    pub fn ports_as_cells(&mut self) {
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|x| x.name.clone())
+            .collect::<HashSet<_>>();
+        let to_cell = |atom: &mut Atom, written: bool| {
+            if let Atom::This(port) = atom {
+                let cell_port = if written && outputs.contains(port) {
+                    "in"
+                } else {
+                    "out"
+                };
+                *atom = Atom::cell(port, cell_port);
+            }
+        };
+        let groups = self
+            .groups
+            .iter_mut()
+            .flat_map(|x| x.assignments.iter_mut());
+        for assignment in self.wires.iter_mut().chain(groups) {
+            to_cell(&mut assignment.dst, true);
+            to_cell(&mut assignment.src, false);
+            assignment.guard.for_each_atom(&mut |x| to_cell(x, false));
+        }
+
+        for port in std::mem::take(&mut self.inputs) {
+            self.cells
+                .push(Cell::new(&port.name, "std_reg", vec![port.width]));
+        }
+        for port in std::mem::take(&mut self.outputs) {
+            self.cells
+                .push(Cell::new(&port.name, "std_wire", vec![port.width]));
+        }
+    }
+
+    /// `value`, which is `from` bits wide, as a value `to` bits wide, adding
+    /// a `std_slice` or `std_pad` cell when the widths differ
+    pub fn resize(&mut self, names: &mut Namer, value: Atom, from: u64, to: u64) -> Atom {
+        let (prefix, prototype) = match from.cmp(&to) {
+            Ordering::Equal => return value,
+            Ordering::Greater => ("slice", "std_slice"),
+            Ordering::Less => ("pad", "std_pad"),
+        };
+        if let Atom::Constant { value, .. } = value {
+            // slicing keeps the low bits
+            return Atom::constant(to, value & (u64::MAX >> (64 - to)));
+        }
+        let cell = names.fresh(prefix);
+        self.cells.push(Cell::new(&cell, prototype, vec![from, to]));
+        self.wires
+            .push(Assignment::new(Atom::cell(&cell, "in"), value));
+        Atom::cell(&cell, "out")
+    }
+}
+
+impl Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ports = |ports: &[Port]| {
+            ports
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        writeln!(
+            f,
+            "component {}({}) -> ({}) {{",
+            self.name,
+            ports(&self.inputs),
+            ports(&self.outputs)
+        )?;
+        writeln!(f, "    cells {{")?;
+        for cell in self.cells.iter() {
+            writeln!(f, "        {}", cell)?;
+        }
+        writeln!(f, "    }}")?;
+        writeln!(f, "    wires {{")?;
+        for group in self.groups.iter() {
+            writeln!(f, "        group {} {{", group.name)?;
+            for assignment in group.assignments.iter() {
+                writeln!(f, "            {}", assignment)?;
+            }
+            writeln!(f, "        }}")?;
+        }
+        for assignment in self.wires.iter() {
+            writeln!(f, "        {}", assignment)?;
+        }
+        writeln!(f, "    }}")?;
+        if self.control == Control::Empty {
+            writeln!(f, "    control {{}}")?;
+        } else {
+            writeln!(f, "    control {{")?;
+            self.control.write(f, 8)?;
+            writeln!(f, "    }}")?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// the components along with the libraries they use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub imports: Vec<String>,
+    pub components: Vec<Component>,
+}
+
+impl Program {
+    /// a program importing the libraries defining the cells' primitives
+    pub fn new(components: Vec<Component>) -> Self {
+        let uses = |prefix: &str| {
+            components
+                .iter()
+                .flat_map(|x| x.cells.iter())
+                .any(|x| x.prototype.starts_with(prefix))
+        };
+        let mut imports = vec!["primitives/core.futil".to_string()];
+        if uses("seq_mem") {
+            imports.push("primitives/memories.futil".to_string());
+        }
+        if uses("std_unsyn") {
+            imports.push("primitives/unsynthesizable.futil".to_string());
+        }
+        Self {
+            imports,
+            components,
+        }
+    }
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for import in self.imports.iter() {
+            writeln!(f, "import \"{}\";", import)?;
+        }
+        for component in self.components.iter() {
+            writeln!(f)?;
+            write!(f, "{}", component)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Assignment, Atom, Cell, Component, Control, Group, Guard, Namer, Port, Program};
+    use crate::structures::ComparisonOperator;
+
+    #[test]
+    fn names_are_never_handed_out_twice() {
+        let mut names = Namer::default();
+        names.reserve("phase");
+        names.reserve("bank1");
+        assert_eq!(names.claim("phase"), "phase0");
+        assert_eq!(names.claim("bank"), "bank");
+        assert_eq!(names.fresh("bank"), "bank0");
+        assert_eq!(names.fresh("bank"), "bank2");
+        assert_eq!(names.claim("phase0"), "phase00");
+    }
+
+    #[test]
+    fn guards_print_with_calyx_precedence() {
+        let lt = Guard::Compare(
+            Atom::this("addr"),
+            ComparisonOperator::LessThan,
+            Atom::constant(4, 8),
+        );
+        let en = Guard::Port(Atom::cell("en", "out"));
+        assert_eq!(Guard::True.and(en.clone()), en);
+        assert_eq!(
+            lt.clone().and(en.clone()).negate().to_string(),
+            "!((addr < 4'd8) & en.out)"
+        );
+        assert_eq!(en.clone().negate().to_string(), "!en.out");
+        // nested disjunctions are flattened, conjunctions within them kept
+        let either = lt.clone().or(en.clone().and(lt.clone())).or(en.clone());
+        assert_eq!(
+            either.to_string(),
+            "((addr < 4'd8) | (en.out & (addr < 4'd8)) | en.out)"
+        );
+        let assignment = Assignment::guarded(Atom::cell("m", "addr0"), en, Atom::this("addr"));
+        assert_eq!(assignment.to_string(), "m.addr0 = en.out ? addr;");
+    }
+
+    #[test]
+    fn components_print_as_calyx() {
+        let mut names = Namer::default();
+        let mut component = Component::new("mem");
+        component.inputs.push(Port::new("addr", 5));
+        component.outputs.push(Port::new("data", 32));
+        component
+            .cells
+            .push(Cell::new("bank0", "std_mem_d1", vec![32, 16, 4]).external());
+        let index = component.resize(&mut names, Atom::this("addr"), 5, 4);
+        // constants are sliced without a cell
+        assert_eq!(
+            component.resize(&mut names, Atom::constant(5, 17), 5, 4),
+            Atom::constant(4, 1)
+        );
+        component
+            .wires
+            .push(Assignment::new(Atom::cell("bank0", "addr0"), index));
+        component.groups.push(Group {
+            name: "read".to_string(),
+            assignments: vec![
+                Assignment::new(Atom::this("data"), Atom::cell("bank0", "read_data")),
+                Assignment::new(Atom::hole("read", "done"), Atom::constant(1, 1)),
+            ],
+        });
+        component.control = Control::seq(vec![
+            Control::Empty,
+            Control::par(vec![Control::Enable("read".to_string())]),
+        ]);
+        let expected = "\
+component mem(addr: 5) -> (data: 32) {
+    cells {
+        @external bank0 = std_mem_d1(32, 16, 4);
+        slice0 = std_slice(5, 4);
+    }
+    wires {
+        group read {
+            data = bank0.read_data;
+            read[done] = 1'd1;
+        }
+        slice0.in = addr;
+        bank0.addr0 = slice0.out;
+    }
+    control {
+        read;
+    }
+}
+";
+        assert_eq!(component.to_string(), expected);
+
+        // driven from its own groups, the ports become registers and wires
+        component.ports_as_cells();
+        assert!(component.inputs.is_empty() && component.outputs.is_empty());
+        assert_eq!(component.wires[0].src, Atom::cell("addr", "out"));
+        assert_eq!(
+            component.groups[0].assignments[0].dst,
+            Atom::cell("data", "in")
+        );
+        let cells = component.cells[2..].to_vec();
+        assert_eq!(
+            cells,
+            vec![
+                Cell::new("addr", "std_reg", vec![5]),
+                Cell::new("data", "std_wire", vec![32])
+            ]
+        );
+    }
+
+    #[test]
+    fn programs_import_the_primitives_they_use() {
+        let mut component = Component::new("main");
+        assert_eq!(
+            Program::new(vec![component.clone()]).to_string(),
+            "import \"primitives/core.futil\";\n\ncomponent main() -> () {\n    cells {\n    }\n    wires {\n    }\n    control {}\n}\n"
+        );
+        component
+            .cells
+            .push(Cell::new("m", "seq_mem_d1", vec![32, 4, 2]));
+        let program = Program::new(vec![component]);
+        assert_eq!(
+            program.imports,
+            vec!["primitives/core.futil", "primitives/memories.futil"]
+        );
+    }
+}
@@ -0,0 +1,381 @@
+use std::fmt::Write;
+
+use super::{
+    index_width, ComparisonOperator, Component, Condition, MemoryBank, SequenceRoutingProg,
+    TerminalRoutingProgram, TopLevelRoutingProgram,
+};
+
+/// `value` truncated or extended to exactly `width` bits, as the arithmetic
+/// primitives grow their results
+fn resize(value: String, width: u64) -> String {
+    format!("bits(pad({}, {}), {}, 0)", value, width, width - 1)
+}
+
+/// a chain of multiplexers picking the first value whose condition holds
+fn mux_chain(cases: Vec<(String, String)>, default: String) -> String {
+    cases
+        .into_iter()
+        .rev()
+        .fold(default, |rest, (condition, value)| {
+            format!("mux({}, {}, {})", condition, value, rest)
+        })
+}
+
+impl ComparisonOperator {
+    fn firrtl_primitive(&self) -> &'static str {
+        match self {
+            ComparisonOperator::LessThan => "lt",
+            ComparisonOperator::Equal => "eq",
+            ComparisonOperator::GreaterThan => "gt",
+            ComparisonOperator::NotEqual => "neq",
+            ComparisonOperator::LessThanOrEqual => "leq",
+            ComparisonOperator::GreaterThanOrEqual => "geq",
+        }
+    }
+}
+
+impl Condition {
+    /// the condition as a one bit expression over the address `source`
+    fn emit_firrtl(&self, source: &str) -> String {
+        match self {
+            Condition::ComparisonPortVal(val, op) => {
+                format!("{}({}, UInt({}))", op.firrtl_primitive(), source, val)
+            }
+            Condition::ComparisonValPort(val, op) => {
+                format!("{}(UInt({}), {})", op.firrtl_primitive(), val, source)
+            }
+            Condition::And(first, second) => format!(
+                "and({}, {})",
+                first.emit_firrtl(source),
+                second.emit_firrtl(source)
+            ),
+            Condition::Or(first, second) => format!(
+                "or({}, {})",
+                first.emit_firrtl(source),
+                second.emit_firrtl(source)
+            ),
+            Condition::Not(c) => format!("not({})", c.emit_firrtl(source)),
+        }
+    }
+}
+
+impl TerminalRoutingProgram {
+    /// the step applied to the address `source`, `width` bits wide
+    fn emit_firrtl(&self, source: &str, width: u64) -> String {
+        let binary = |op: &str, value: u64| format!("{}({}, UInt({}))", op, source, value);
+        let step = match self {
+            TerminalRoutingProgram::RShift(n) => format!("shr({}, {})", source, n),
+            TerminalRoutingProgram::LShift(n) => format!("shl({}, {})", source, n),
+            TerminalRoutingProgram::Add(n) => binary("add", *n),
+            TerminalRoutingProgram::SubPortVal(n) => binary("sub", *n),
+            TerminalRoutingProgram::SubValPort(n) => format!("sub(UInt({}), {})", n, source),
+            TerminalRoutingProgram::Constant(n) => format!("UInt({})", n),
+            TerminalRoutingProgram::And(n) => binary("and", *n),
+            TerminalRoutingProgram::Xor(n) => binary("xor", *n),
+            TerminalRoutingProgram::Mod(n) => binary("rem", *n),
+            TerminalRoutingProgram::Div(n) => binary("div", *n),
+            TerminalRoutingProgram::Mul(n) => binary("mul", *n),
+            // addresses missing from the table land past the end of the bank
+            TerminalRoutingProgram::Lookup(table) => mux_chain(
+                table
+                    .iter()
+                    .map(|(address, index)| {
+                        (
+                            format!("eq({}, UInt({}))", source, address),
+                            format!("UInt({})", index),
+                        )
+                    })
+                    .collect(),
+                format!("UInt({})", u64::MAX >> (64 - width)),
+            ),
+            TerminalRoutingProgram::Noop => return source.to_string(),
+        };
+        resize(step, width)
+    }
+}
+
+impl SequenceRoutingProg {
+    fn emit_firrtl(&self, source: &str, width: u64) -> String {
+        match self {
+            SequenceRoutingProg::Sequence(steps) => {
+                steps.iter().fold(source.to_string(), |source, step| {
+                    step.emit_firrtl(&source, width)
+                })
+            }
+            SequenceRoutingProg::Prog(p) => p.emit_firrtl(source, width),
+        }
+    }
+}
+
+impl TopLevelRoutingProgram {
+    /// the translation as a single expression, with switches written as
+    /// chains of multiplexers
+    fn emit_firrtl(&self, source: &str, width: u64) -> String {
+        match self {
+            TopLevelRoutingProgram::Switch(cases, default) => mux_chain(
+                cases
+                    .iter()
+                    .map(|(condition, prog)| {
+                        (
+                            condition.emit_firrtl(source),
+                            prog.emit_firrtl(source, width),
+                        )
+                    })
+                    .collect(),
+                default.emit_firrtl(source, width),
+            ),
+            TopLevelRoutingProgram::Prog(p) => p.emit_firrtl(source, width),
+        }
+    }
+}
+
+impl MemoryBank {
+    /// the node translating the port's address into its index in the bank,
+    /// selecting the translation of the current phase
+    fn emit_firrtl_translation(&self, bank_idx: usize, width: u64, phase_width: u64) -> String {
+        let port = self.port_name(bank_idx);
+        let source = format!("{}_addr", port);
+        let translations = self
+            .routing
+            .iter()
+            .map(|x| x.emit_firrtl(&source, width))
+            .collect::<Vec<_>>();
+        let (last, earlier) = translations.split_last().unwrap();
+        let index = mux_chain(
+            earlier
+                .iter()
+                .enumerate()
+                .map(|(phase, translation)| {
+                    (
+                        format!("eq(phase, UInt<{}>({}))", phase_width, phase),
+                        translation.clone(),
+                    )
+                })
+                .collect(),
+            last.clone(),
+        );
+        format!("    node {}_index = {}\n", port, index)
+    }
+}
+
+impl Component {
+    /// emits a FIRRTL circuit with a public module for each memory, named
+    /// after the first. A circuit needs a module, so there is none without
+    /// any memories
+    pub fn emit_firrtl_circuit(components: &[Component]) -> Option<String> {
+        let first = components.first()?;
+        let mut w = String::new();
+        writeln!(w, "FIRRTL version 3.3.0").unwrap();
+        writeln!(w, "circuit {} :", first.component_name()).unwrap();
+        for component in components {
+            writeln!(w).unwrap();
+            write!(w, "{}", component.emit_firrtl()).unwrap();
+        }
+        Some(w)
+    }
+
+    /// emits a FIRRTL module with a memory per bank, written and read
+    /// through the same ports as the SystemVerilog backend
+    pub fn emit_firrtl(&self) -> String {
+        let width = self.width;
+        let addr_width = self.address_width();
+        let phase_width = self.phase_width();
+
+        let mut ports = vec!["input clock : Clock".to_string()];
+        if self.phases() > 1 {
+            ports.push(format!("input phase : UInt<{}>", phase_width));
+        }
+        for (idx, bank) in self.banks.iter().enumerate() {
+            let port = bank.port_name(idx);
+            if self.is_shared(idx) {
+                ports.push(format!("input {}_en : UInt<1>", port));
+            }
+            ports.push(format!("input {}_addr : UInt<{}>", port, addr_width));
+            ports.push(format!("input {}_write_en : UInt<1>", port));
+            ports.push(format!("input {}_write_data : UInt<{}>", port, width));
+            ports.push(format!("output {}_read_data : UInt<{}>", port, width));
+        }
+
+        let mut w = String::new();
+        writeln!(w, "  public module {} :", self.component_name()).unwrap();
+        for port in ports {
+            writeln!(w, "    {}", port).unwrap();
+        }
+
+        writeln!(w).unwrap();
+        for (idx, bank) in self.banks.iter().enumerate() {
+            write!(
+                w,
+                "{}",
+                bank.emit_firrtl_translation(idx, addr_width, phase_width)
+            )
+            .unwrap();
+        }
+
+        for (storage, bank) in self.banks.iter().enumerate() {
+            if bank.storage.is_some() {
+                continue;
+            }
+            let users = (0..self.banks.len())
+                .filter(|x| self.storage_of(*x) == storage)
+                .map(|x| (x, self.banks[x].port_name(x)))
+                .collect::<Vec<_>>();
+            // the last port is used whenever no other port is enabled, so its
+            // writes are gated by its own enable
+            let select = |signal: &str| {
+                let (_, last) = users.last().unwrap();
+                let last = if users.len() > 1 && signal == "write_en" {
+                    format!("and({}_en, {}_write_en)", last, last)
+                } else {
+                    format!("{}_{}", last, signal)
+                };
+                mux_chain(
+                    users[..users.len() - 1]
+                        .iter()
+                        .map(|(_, port)| (format!("{}_en", port), format!("{}_{}", port, signal)))
+                        .collect(),
+                    last,
+                )
+            };
+
+            let name = format!("storage_{}", storage);
+            let depth = bank.size();
+            writeln!(w).unwrap();
+            writeln!(w, "    mem {} :", name).unwrap();
+            writeln!(w, "      data-type => UInt<{}>", width).unwrap();
+            writeln!(w, "      depth => {}", depth).unwrap();
+            writeln!(w, "      read-latency => 0").unwrap();
+            writeln!(w, "      write-latency => 1").unwrap();
+            writeln!(w, "      read-under-write => undefined").unwrap();
+            writeln!(w, "      reader => r").unwrap();
+            writeln!(w, "      writer => w").unwrap();
+            writeln!(
+                w,
+                "    node {}_addr = {}",
+                name,
+                resize(select("index"), index_width(depth))
+            )
+            .unwrap();
+            for port in ["r", "w"] {
+                writeln!(w, "    connect {}.{}.addr, {}_addr", name, port, name).unwrap();
+                writeln!(w, "    connect {}.{}.clk, clock", name, port).unwrap();
+            }
+            writeln!(w, "    connect {}.r.en, UInt<1>(1)", name).unwrap();
+            writeln!(w, "    connect {}.w.en, {}", name, select("write_en")).unwrap();
+            writeln!(w, "    connect {}.w.data, {}", name, select("write_data")).unwrap();
+            writeln!(w, "    connect {}.w.mask, UInt<1>(1)", name).unwrap();
+
+            for (bank_idx, port) in users.iter() {
+                let latency = self.banks[*bank_idx].latency;
+                let read = format!("{}.r.data", name);
+                if latency == 0 {
+                    writeln!(w, "    connect {}_read_data, {}", port, read).unwrap();
+                    continue;
+                }
+                // registered reads, as inferred for block RAM when the
+                // latency is one cycle
+                for stage in 0..latency {
+                    writeln!(
+                        w,
+                        "    reg {}_read_{} : UInt<{}>, clock",
+                        port, stage, width
+                    )
+                    .unwrap();
+                }
+                writeln!(w, "    connect {}_read_0, {}", port, read).unwrap();
+                for stage in 1..latency {
+                    writeln!(
+                        w,
+                        "    connect {}_read_{}, {}_read_{}",
+                        port,
+                        stage,
+                        port,
+                        stage - 1
+                    )
+                    .unwrap();
+                }
+                writeln!(
+                    w,
+                    "    connect {}_read_data, {}_read_{}",
+                    port,
+                    port,
+                    latency - 1
+                )
+                .unwrap();
+            }
+        }
+        w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dsl::ast::AstParser;
+    use crate::structures::Component;
+
+    fn memories(source: &str) -> Vec<Component> {
+        AstParser::parse_description(source, &[]).unwrap().memories
+    }
+
+    #[test]
+    fn circuits_need_a_memory() {
+        assert_eq!(Component::emit_firrtl_circuit(&[]), None);
+    }
+
+    #[test]
+    fn circuits_hold_a_module_per_memory() {
+        let circuit = Component::emit_firrtl_circuit(&memories(
+            "memory first<32, 16> { bank { layout: cyclic(1, 0) translation: INPUT } }
+             memory second<8, 4> { bank { layout: {1, 2} translation: INPUT - 1 } }",
+        ))
+        .unwrap();
+        assert!(circuit.starts_with("FIRRTL version 3.3.0\ncircuit first :\n"));
+        for line in [
+            "  public module first :",
+            "  public module second :",
+            "input bank_0_addr : UInt<2>",
+            "output bank_0_read_data : UInt<8>",
+            "depth => 2",
+        ] {
+            assert!(circuit.contains(line), "{}\n{}", line, circuit);
+        }
+    }
+
+    #[test]
+    fn shared_storage_is_selected_by_enables() {
+        let firrtl = memories(
+            "memory mem<32, 16> {
+                bank { layout: {0, 1} translation: INPUT latency: 1 }
+                bank { layout: {0, 1} translation: INPUT storage: 0 }
+            }",
+        )[0]
+        .emit_firrtl();
+        assert!(!firrtl.contains("mem storage_1"), "{}", firrtl);
+        for line in [
+            "input bank_0_en : UInt<1>",
+            "node storage_0_addr = bits(pad(mux(bank_0_en, bank_0_index, bank_1_index), 1), 0, 0)",
+            "connect storage_0.w.en, mux(bank_0_en, bank_0_write_en, and(bank_1_en, bank_1_write_en))",
+            "reg bank_0_read_0 : UInt<32>, clock",
+            "connect bank_0_read_data, bank_0_read_0",
+            "connect bank_1_read_data, storage_0.r.data",
+        ] {
+            assert!(firrtl.contains(line), "{}\n{}", line, firrtl);
+        }
+    }
+
+    #[test]
+    fn phases_select_the_index() {
+        let firrtl = memories(
+            "memory mem<32, 16> {
+                bank { layout: {0, 1} translation phase 0: INPUT translation phase 1: 1 - INPUT }
+            }",
+        )[0]
+        .emit_firrtl();
+        assert!(firrtl.contains("input phase : UInt<1>"), "{}", firrtl);
+        assert!(
+            firrtl.contains("node bank_0_index = mux(eq(phase, UInt<1>(0)), "),
+            "{}",
+            firrtl
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use std::fmt::Write;
+
+use super::infix::Literals;
+use super::Component;
+
+/// C++ constants take the type of the address they are combined with
+struct CppLiterals;
+
+impl Literals for CppLiterals {
+    fn constant(&self, value: u64) -> String {
+        value.to_string()
+    }
+
+    fn all_ones(&self) -> String {
+        "addr_t(-1)".to_string()
+    }
+}
+
+impl Component {
+    /// the `array_partition` arguments when the banks are exactly a cyclic
+    /// or block partitioning of the logical memory, each read at its natural
+    /// index
+    fn standard_partitioning(&self) -> Option<String> {
+        let banks = self.banks.len();
+        let size = self.size as usize;
+        if banks < 2 || self.phases() > 1 || (0..banks).any(|x| self.is_shared(x)) {
+            return None;
+        }
+        let matches = |holds: &dyn Fn(usize, usize) -> bool,
+                       index: &dyn Fn(usize, usize) -> usize| {
+            let mut offsets = vec![];
+            for bank in self.banks.iter() {
+                let addresses = bank.layout().addresses();
+                let offset = (0..banks).find(|offset| {
+                    addresses
+                        == (0..size)
+                            .filter(|address| holds(*offset, *address))
+                            .collect::<Vec<_>>()
+                })?;
+                let natural = addresses.iter().all(|address| {
+                    bank.routing(0).checked_eval(*address as u64)
+                        == Some(index(offset, *address) as u64)
+                });
+                if !natural || offsets.contains(&offset) {
+                    return None;
+                }
+                offsets.push(offset);
+            }
+            Some(())
+        };
+
+        let block = size.div_ceil(banks);
+        if matches(
+            &|offset, address| address % banks == offset,
+            &|_, address| address / banks,
+        )
+        .is_some()
+        {
+            Some(format!("cyclic factor={}", banks))
+        } else if matches(
+            &|offset, address| address / block == offset,
+            &|offset, address| address - offset * block,
+        )
+        .is_some()
+        {
+            Some(format!("block factor={}", banks))
+        } else {
+            None
+        }
+    }
+
+    /// emits a C++ header for HLS tools with a struct for each memory
+    pub fn emit_hls_header(components: &[Component]) -> String {
+        let mut w = String::new();
+        writeln!(w, "#pragma once").unwrap();
+        writeln!(w).unwrap();
+        writeln!(w, "#include <ap_int.h>").unwrap();
+        for component in components {
+            writeln!(w).unwrap();
+            write!(w, "{}", component.emit_hls()).unwrap();
+        }
+        w
+    }
+
+    /// emits a struct declaring the banks as arrays along with `read` and
+    /// `write` functions implementing each port's translation. Memories
+    /// banked like a standard partitioning are instead a single array
+    /// partitioned by the tool
+    pub fn emit_hls(&self) -> String {
+        let mut w = String::new();
+        writeln!(w, "struct {} {{", self.component_name()).unwrap();
+        writeln!(w, "    typedef ap_uint<{}> data_t;", self.width).unwrap();
+        writeln!(w, "    typedef ap_uint<{}> addr_t;", self.address_width()).unwrap();
+        writeln!(w).unwrap();
+
+        if let Some(partitioning) = self.standard_partitioning() {
+            let pragmas = format!(
+                "#pragma HLS inline\n#pragma HLS array_partition variable=data {} dim=1",
+                partitioning
+            );
+            writeln!(w, "    data_t data[{}];", self.size).unwrap();
+            writeln!(w).unwrap();
+            writeln!(
+                w,
+                "    inline data_t read(unsigned port, addr_t addr) const {{"
+            )
+            .unwrap();
+            writeln!(w, "{}", pragmas).unwrap();
+            writeln!(w, "        (void)port;").unwrap();
+            writeln!(
+                w,
+                "        return addr < {} ? data[addr] : data_t(0);",
+                self.size
+            )
+            .unwrap();
+            writeln!(w, "    }}").unwrap();
+            writeln!(w).unwrap();
+            writeln!(
+                w,
+                "    inline void write(unsigned port, addr_t addr, data_t value) {{"
+            )
+            .unwrap();
+            writeln!(w, "{}", pragmas).unwrap();
+            writeln!(w, "        (void)port;").unwrap();
+            writeln!(w, "        if (addr < {}) data[addr] = value;", self.size).unwrap();
+            writeln!(w, "    }}").unwrap();
+            writeln!(w, "}};").unwrap();
+            return w;
+        }
+
+        for (idx, bank) in self.banks.iter().enumerate() {
+            if bank.storage.is_none() {
+                writeln!(w, "    data_t bank_{}[{}];", idx, bank.size()).unwrap();
+            }
+        }
+        if self.phases() > 1 {
+            writeln!(w, "    // selects the translation used by every port").unwrap();
+            writeln!(w, "    unsigned phase = 0;").unwrap();
+        }
+
+        // the storage and index each port accesses, through its translation.
+        // Indices past the end of the storage, such as lookup misses, read
+        // zero and skip the write
+        let elements = self
+            .banks
+            .iter()
+            .enumerate()
+            .map(|(idx, bank)| {
+                let translations = bank
+                    .routing
+                    .iter()
+                    .map(|x| x.emit_infix("addr", &CppLiterals))
+                    .collect::<Vec<_>>();
+                let (last, earlier) = translations.split_last().unwrap();
+                let index = earlier.iter().enumerate().rev().fold(
+                    last.clone(),
+                    |rest, (phase, translation)| {
+                        format!(
+                            "phase == {} ? addr_t({}) : addr_t({})",
+                            phase, translation, rest
+                        )
+                    },
+                );
+                let storage = self.storage_of(idx);
+                (
+                    bank.port_name(idx),
+                    format!("bank_{}", storage),
+                    self.banks[storage].size(),
+                    index,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        writeln!(w).unwrap();
+        writeln!(
+            w,
+            "    inline data_t read(unsigned port, addr_t addr) const {{"
+        )
+        .unwrap();
+        writeln!(w, "#pragma HLS inline").unwrap();
+        writeln!(w, "        switch (port) {{").unwrap();
+        for (idx, (port, storage, size, index)) in elements.iter().enumerate() {
+            writeln!(w, "        case {}: {{ // {}", idx, port).unwrap();
+            writeln!(w, "            addr_t index = {};", index).unwrap();
+            writeln!(
+                w,
+                "            return index < {} ? {}[index] : data_t(0);",
+                size, storage
+            )
+            .unwrap();
+            writeln!(w, "        }}").unwrap();
+        }
+        writeln!(w, "        default:").unwrap();
+        writeln!(w, "            return 0;").unwrap();
+        writeln!(w, "        }}").unwrap();
+        writeln!(w, "    }}").unwrap();
+        writeln!(w).unwrap();
+        writeln!(
+            w,
+            "    inline void write(unsigned port, addr_t addr, data_t value) {{"
+        )
+        .unwrap();
+        writeln!(w, "#pragma HLS inline").unwrap();
+        writeln!(w, "        switch (port) {{").unwrap();
+        for (idx, (port, storage, size, index)) in elements.iter().enumerate() {
+            writeln!(w, "        case {}: {{ // {}", idx, port).unwrap();
+            writeln!(w, "            addr_t index = {};", index).unwrap();
+            writeln!(
+                w,
+                "            if (index < {}) {}[index] = value;",
+                size, storage
+            )
+            .unwrap();
+            writeln!(w, "            break;").unwrap();
+            writeln!(w, "        }}").unwrap();
+        }
+        writeln!(w, "        default:").unwrap();
+        writeln!(w, "            break;").unwrap();
+        writeln!(w, "        }}").unwrap();
+        writeln!(w, "    }}").unwrap();
+        writeln!(w, "}};").unwrap();
+        w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dsl::ast::AstParser;
+    use crate::structures::Component;
+
+    fn memory(source: &str) -> Component {
+        AstParser::parse_description(source, &[])
+            .unwrap()
+            .memories
+            .remove(0)
+    }
+
+    #[test]
+    fn indices_past_the_storage_are_skipped() {
+        let hls = memory(
+            "memory mem<32, 16> {
+                bank { layout: {3, 7} translation: lookup {3 -> 0, 7 -> 1} }
+                bank { layout: {3, 7} translation: lookup {3 -> 0, 7 -> 1} storage: 0 }
+            }",
+        )
+        .emit_hls();
+        assert!(!hls.contains("data_t bank_1"), "{}", hls);
+        for line in [
+            "struct mem {",
+            "typedef ap_uint<4> addr_t;",
+            "data_t bank_0[2];",
+            // a lookup miss translates to all ones, past the end of the bank
+            "addr_t index = ((addr == 3) ? 0 : ((addr == 7) ? 1 : addr_t(-1)));",
+            "return index < 2 ? bank_0[index] : data_t(0);",
+            "if (index < 2) bank_0[index] = value;",
+        ] {
+            assert!(hls.contains(line), "{}\n{}", line, hls);
+        }
+    }
+
+    #[test]
+    fn phases_select_the_index() {
+        let hls = memory(
+            "memory mem<32, 16> {
+                bank { layout: {0, 1} translation phase 0: INPUT translation phase 1: 1 - INPUT }
+            }",
+        )
+        .emit_hls();
+        assert!(hls.contains("unsigned phase = 0;"), "{}", hls);
+        assert!(
+            hls.contains("addr_t index = phase == 0 ? addr_t(addr) : addr_t((1 - addr));"),
+            "{}",
+            hls
+        );
+    }
+
+    #[test]
+    fn standard_partitionings_use_a_single_array() {
+        let hls = memory(
+            "memory mem<32, 16> {
+                bank { layout: cyclic(2, 0) translation: INPUT / 2 }
+                bank { layout: cyclic(2, 1) translation: INPUT / 2 }
+            }",
+        )
+        .emit_hls();
+        for line in [
+            "data_t data[16];",
+            "#pragma HLS array_partition variable=data cyclic factor=2 dim=1",
+            "return addr < 16 ? data[addr] : data_t(0);",
+            "if (addr < 16) data[addr] = value;",
+        ] {
+            assert!(hls.contains(line), "{}\n{}", line, hls);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use std::fmt::{self, Display, Write};
+use std::str::FromStr;
+
+use serde_json::{json, Map, Value};
+
+use super::Component;
+
+/// The encodings of memory contents in files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// a JSON list of numbers, or the Calyx data format for several memories
+    Json,
+    /// whitespace separated hexadecimal words, as read by `$readmemh`
+    Hex,
+    /// little endian words of whole bytes
+    Binary,
+}
+
+/// Reasons memory contents can fail to fit a description
+#[derive(Debug, Clone)]
+pub enum ImageError {
+    /// the file cannot be decoded in its format
+    Malformed(String),
+    /// a memory holds a different number of values than the description
+    Size {
+        memory: String,
+        expected: usize,
+        found: usize,
+    },
+    /// a value does not fit in the memory's width
+    Overflow { value: u64, width: u64 },
+    /// banks holding the same address disagree on its value
+    Disagreement { address: usize },
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Malformed(message) => write!(f, "{}", message),
+            ImageError::Size {
+                memory,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} has {} values but the description gives it {}",
+                memory, found, expected
+            ),
+            ImageError::Overflow { value, width } => {
+                write!(f, "{} does not fit in {} bits", value, width)
+            }
+            ImageError::Disagreement { address } => {
+                write!(f, "the banks hold different values for address {}", address)
+            }
+        }
+    }
+}
+
+/// the number of bytes a binary word of the given width takes
+fn word_bytes(width: u64) -> usize {
+    width.div_ceil(8).max(1) as usize
+}
+
+/// a memory in the data file format of the Calyx toolchain
+pub fn calyx_memory(values: &[u64], width: u64) -> Value {
+    json!({
+        "data": values,
+        "format": {
+            "numeric_type": "bitnum",
+            "is_signed": false,
+            "width": width,
+        },
+    })
+}
+
+/// checks every value fits in `width` bits
+fn check_width(values: &[u64], width: u64) -> Result<(), ImageError> {
+    match values.iter().find(|x| width < 64 && **x >> width != 0) {
+        Some(value) => Err(ImageError::Overflow {
+            value: *value,
+            width,
+        }),
+        None => Ok(()),
+    }
+}
+
+impl ImageFormat {
+    /// the format files with the extension are in, when it is a known one
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "json" | "data" => Some(ImageFormat::Json),
+            "hex" => Some(ImageFormat::Hex),
+            "bin" => Some(ImageFormat::Binary),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Json => "json",
+            ImageFormat::Hex => "hex",
+            ImageFormat::Binary => "bin",
+        }
+    }
+
+    /// decodes the values of a single memory of `width` bit words. JSON
+    /// may also be a memory in the Calyx data format
+    pub fn decode(&self, contents: &[u8], width: u64) -> Result<Vec<u64>, ImageError> {
+        let values = match self {
+            ImageFormat::Json => {
+                let value = serde_json::from_slice::<Value>(contents)
+                    .map_err(|e| ImageError::Malformed(e.to_string()))?;
+                decode_json(value.get("data").unwrap_or(&value))?
+            }
+            ImageFormat::Hex => {
+                let text = std::str::from_utf8(contents)
+                    .map_err(|e| ImageError::Malformed(e.to_string()))?;
+                decode_hex(text)?
+            }
+            ImageFormat::Binary => {
+                let bytes = word_bytes(width);
+                if width > 64 || contents.len() % bytes != 0 {
+                    return Err(ImageError::Malformed(format!(
+                        "{} bytes are not a whole number of {} byte words",
+                        contents.len(),
+                        bytes
+                    )));
+                }
+                contents
+                    .chunks(bytes)
+                    .map(|word| {
+                        word.iter()
+                            .rev()
+                            .fold(0, |value, byte| (value << 8) | *byte as u64)
+                    })
+                    .collect()
+            }
+        };
+        check_width(&values, width)?;
+        Ok(values)
+    }
+
+    /// encodes the values of a single memory of `width` bit words
+    pub fn encode(&self, values: &[u64], width: u64) -> Vec<u8> {
+        match self {
+            ImageFormat::Json => {
+                let mut out = serde_json::to_string(values).unwrap();
+                out.push('\n');
+                out.into_bytes()
+            }
+            ImageFormat::Hex => {
+                let digits = width.div_ceil(4).max(1) as usize;
+                let mut out = String::new();
+                for value in values {
+                    writeln!(out, "{:0digits$x}", value, digits = digits).unwrap();
+                }
+                out.into_bytes()
+            }
+            ImageFormat::Binary => values
+                .iter()
+                .flat_map(|value| value.to_le_bytes().into_iter().take(word_bytes(width)))
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" | "calyx" => Ok(ImageFormat::Json),
+            "hex" => Ok(ImageFormat::Hex),
+            "bin" | "binary" => Ok(ImageFormat::Binary),
+            _ => Err(format!("unknown format `{}`, expected json, hex or bin", s)),
+        }
+    }
+}
+
+fn decode_json(value: &Value) -> Result<Vec<u64>, ImageError> {
+    serde_json::from_value::<Vec<u64>>(value.clone())
+        .map_err(|e| ImageError::Malformed(e.to_string()))
+}
+
+/// reads `$readmemh` style words, skipping `//` comments and underscores.
+/// Address markers are not supported, the words fill the memory in order
+fn decode_hex(text: &str) -> Result<Vec<u64>, ImageError> {
+    text.lines()
+        .map(|line| line.split("//").next().unwrap())
+        .flat_map(|line| line.split_whitespace())
+        .map(|word| {
+            if word.starts_with('@') {
+                return Err(ImageError::Malformed(format!(
+                    "address marker `{}` is not supported",
+                    word
+                )));
+            }
+            u64::from_str_radix(&word.replace('_', ""), 16)
+                .map_err(|_| ImageError::Malformed(format!("`{}` is not a hex word", word)))
+        })
+        .collect()
+}
+
+impl Component {
+    /// the contents of each bank's storage when the logical memory holds
+    /// `image`, following the order its layout stores the addresses in.
+    /// Banks using another bank's storage have no contents of their own
+    pub fn bank_images(&self, image: &[u64]) -> Result<Vec<Option<Vec<u64>>>, ImageError> {
+        if image.len() != self.size as usize {
+            return Err(ImageError::Size {
+                memory: "the logical memory".to_string(),
+                expected: self.size as usize,
+                found: image.len(),
+            });
+        }
+        check_width(image, self.width)?;
+        Ok(self
+            .banks
+            .iter()
+            .map(|bank| {
+                bank.storage.is_none().then(|| {
+                    let mut contents = vec![0; bank.size()];
+                    let mut offset = 0;
+                    for layout in bank.layout().mems.iter() {
+                        for (idx, address) in layout._gen_array().into_iter().enumerate() {
+                            if let Some(value) = image.get(address) {
+                                contents[offset + idx] = *value;
+                            }
+                        }
+                        offset += layout.size();
+                    }
+                    contents
+                })
+            })
+            .collect())
+    }
+
+    /// reconstructs the logical memory from the contents of each storage
+    /// bank, given for the banks in order. Addresses no bank holds are zero
+    pub fn merge_images(&self, images: &[Vec<u64>]) -> Result<Vec<u64>, ImageError> {
+        let storages = (0..self.banks.len())
+            .filter(|x| self.banks[*x].storage.is_none())
+            .collect::<Vec<_>>();
+        if images.len() != storages.len() {
+            return Err(ImageError::Size {
+                memory: "the list of banks".to_string(),
+                expected: storages.len(),
+                found: images.len(),
+            });
+        }
+
+        let mut image: Vec<Option<u64>> = vec![None; self.size as usize];
+        for (idx, contents) in storages.into_iter().zip(images.iter()) {
+            let bank = &self.banks[idx];
+            if contents.len() != bank.size() {
+                return Err(ImageError::Size {
+                    memory: format!("bank_{}", idx),
+                    expected: bank.size(),
+                    found: contents.len(),
+                });
+            }
+            check_width(contents, self.width)?;
+            let mut offset = 0;
+            for layout in bank.layout().mems.iter() {
+                for (position, address) in layout._gen_array().into_iter().enumerate() {
+                    let value = contents[offset + position];
+                    match image.get_mut(address) {
+                        Some(Some(other)) if *other != value => {
+                            return Err(ImageError::Disagreement { address })
+                        }
+                        Some(slot) => *slot = Some(value),
+                        None => {}
+                    }
+                }
+                offset += layout.size();
+            }
+        }
+        Ok(image.into_iter().map(|x| x.unwrap_or(0)).collect())
+    }
+
+    /// the contents of the storage banks in the Calyx data format, under the
+    /// names of the emitted cells
+    pub fn calyx_data(&self, images: &[Option<Vec<u64>>]) -> Map<String, Value> {
+        images
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, contents)| {
+                let contents = contents.as_ref()?;
+                Some((format!("bank_{}", idx), calyx_memory(contents, self.width)))
+            })
+            .collect()
+    }
+
+    /// the contents of the storage banks from a file in the Calyx data
+    /// format, or a plain object of lists as dumped by simulators. Memories
+    /// with a single storage bank may also give just its contents
+    pub fn read_calyx_data(&self, contents: &[u8]) -> Result<Vec<Vec<u64>>, ImageError> {
+        let value = serde_json::from_slice::<Value>(contents)
+            .map_err(|e| ImageError::Malformed(e.to_string()))?;
+        let storages = self.banks.iter().filter(|x| x.storage.is_none()).count();
+        if storages == 1 && (value.is_array() || value.get("data").is_some()) {
+            return Ok(vec![decode_json(value.get("data").unwrap_or(&value))?]);
+        }
+        (0..self.banks.len())
+            .filter(|x| self.banks[*x].storage.is_none())
+            .map(|idx| {
+                let name = format!("bank_{}", idx);
+                let memory = value
+                    .get(&name)
+                    .ok_or_else(|| ImageError::Malformed(format!("no memory `{}`", name)))?;
+                decode_json(memory.get("data").unwrap_or(memory))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImageError, ImageFormat};
+    use crate::dsl::ast::AstParser;
+    use crate::structures::Component;
+
+    fn memory(source: &str) -> Component {
+        AstParser::parse_description(source, &[])
+            .unwrap()
+            .memories
+            .remove(0)
+    }
+
+    #[test]
+    fn images_split_and_merge_back() {
+        let comp = memory(
+            "memory<8, 16> {
+                bank { layout: [[0:4] [8:12]] translation: switch { INPUT < 8 -> INPUT, -> INPUT - 4 } }
+                bank { layout: [4:8] translation: INPUT - 4 }
+                bank { layout: [4:8] translation: INPUT - 4 storage: 1 }
+                bank { layout: [12:16:2] translation: (INPUT - 12) / 2 }
+                bank { layout: {15, 13} translation: lookup {15 -> 0, 13 -> 1} }
+            }",
+        );
+        let image = (0..16).map(|x| x * 3).collect::<Vec<_>>();
+        let banks = comp.bank_images(&image).unwrap();
+        assert_eq!(
+            banks,
+            vec![
+                Some(vec![0, 3, 6, 9, 24, 27, 30, 33]),
+                Some(vec![12, 15, 18, 21]),
+                None,
+                Some(vec![36, 42]),
+                Some(vec![45, 39]),
+            ]
+        );
+        let storages = banks.into_iter().flatten().collect::<Vec<_>>();
+        assert_eq!(comp.merge_images(&storages).unwrap(), image);
+    }
+
+    #[test]
+    fn merging_checks_the_banks() {
+        let comp = memory(
+            "memory<8, 4> {
+                bank { layout: [0:4] translation: INPUT }
+                bank { layout: [2:4] translation: INPUT - 2 }
+            }",
+        );
+        assert!(matches!(
+            comp.merge_images(&[vec![1, 2, 3, 4], vec![3]]),
+            Err(ImageError::Size { expected: 2, .. })
+        ));
+        assert!(matches!(
+            comp.merge_images(&[vec![1, 2, 3, 4], vec![3, 5]]),
+            Err(ImageError::Disagreement { address: 3 })
+        ));
+        assert!(matches!(
+            comp.bank_images(&[1, 2, 3, 256]),
+            Err(ImageError::Overflow { value: 256, .. })
+        ));
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        let values = vec![0, 1, 0xab, 0x1ff];
+        for format in [ImageFormat::Json, ImageFormat::Hex, ImageFormat::Binary] {
+            let encoded = format.encode(&values, 9);
+            assert_eq!(format.decode(&encoded, 9).unwrap(), values, "{:?}", format);
+        }
+    }
+}
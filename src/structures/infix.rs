@@ -0,0 +1,112 @@
+use super::{Condition, SequenceRoutingProg, TerminalRoutingProgram, TopLevelRoutingProgram};
+
+/// The way a backend with C-like infix expressions writes constants
+pub(super) trait Literals {
+    fn constant(&self, value: u64) -> String;
+    /// the largest address, which lands past the end of every bank
+    fn all_ones(&self) -> String;
+}
+
+impl Condition {
+    /// the condition as an expression over the address `source`
+    pub(super) fn emit_infix(&self, source: &str, literals: &dyn Literals) -> String {
+        match self {
+            Condition::ComparisonPortVal(val, op) => format!(
+                "({} {} {})",
+                source,
+                op.pretty_print(),
+                literals.constant(*val)
+            ),
+            Condition::ComparisonValPort(val, op) => format!(
+                "({} {} {})",
+                literals.constant(*val),
+                op.pretty_print(),
+                source
+            ),
+            Condition::And(first, second) => format!(
+                "({} && {})",
+                first.emit_infix(source, literals),
+                second.emit_infix(source, literals)
+            ),
+            Condition::Or(first, second) => format!(
+                "({} || {})",
+                first.emit_infix(source, literals),
+                second.emit_infix(source, literals)
+            ),
+            Condition::Not(c) => format!("!{}", c.emit_infix(source, literals)),
+        }
+    }
+}
+
+impl TerminalRoutingProgram {
+    /// the step applied to the address `source`
+    pub(super) fn emit_infix(&self, source: &str, literals: &dyn Literals) -> String {
+        let binary =
+            |op: &str, value: u64| format!("({} {} {})", source, op, literals.constant(value));
+        match self {
+            TerminalRoutingProgram::RShift(n) => binary(">>", *n as u64),
+            TerminalRoutingProgram::LShift(n) => binary("<<", *n as u64),
+            TerminalRoutingProgram::Add(n) => binary("+", *n),
+            TerminalRoutingProgram::SubPortVal(n) => binary("-", *n),
+            TerminalRoutingProgram::SubValPort(n) => {
+                format!("({} - {})", literals.constant(*n), source)
+            }
+            TerminalRoutingProgram::Constant(n) => literals.constant(*n),
+            TerminalRoutingProgram::And(n) => binary("&", *n),
+            TerminalRoutingProgram::Xor(n) => binary("^", *n),
+            TerminalRoutingProgram::Mod(n) => binary("%", *n),
+            TerminalRoutingProgram::Div(n) => binary("/", *n),
+            TerminalRoutingProgram::Mul(n) => binary("*", *n),
+            // addresses missing from the table land past the end of the bank
+            TerminalRoutingProgram::Lookup(table) => {
+                table
+                    .iter()
+                    .rev()
+                    .fold(literals.all_ones(), |rest, (address, index)| {
+                        format!(
+                            "(({} == {}) ? {} : {})",
+                            source,
+                            literals.constant(*address),
+                            literals.constant(*index),
+                            rest
+                        )
+                    })
+            }
+            TerminalRoutingProgram::Noop => source.to_string(),
+        }
+    }
+}
+
+impl SequenceRoutingProg {
+    pub(super) fn emit_infix(&self, source: &str, literals: &dyn Literals) -> String {
+        match self {
+            SequenceRoutingProg::Sequence(steps) => {
+                steps.iter().fold(source.to_string(), |source, step| {
+                    step.emit_infix(&source, literals)
+                })
+            }
+            SequenceRoutingProg::Prog(p) => p.emit_infix(source, literals),
+        }
+    }
+}
+
+impl TopLevelRoutingProgram {
+    /// the translation as a single expression, with switches written as
+    /// chains of conditionals
+    pub(super) fn emit_infix(&self, source: &str, literals: &dyn Literals) -> String {
+        match self {
+            TopLevelRoutingProgram::Switch(cases, default) => cases.iter().rev().fold(
+                default.emit_infix(source, literals),
+                |rest, (condition, prog)| {
+                    format!(
+                        "{} ? {} : {}",
+                        condition.emit_infix(source, literals),
+                        prog.emit_infix(source, literals),
+                        rest
+                    )
+                },
+            ),
+            TopLevelRoutingProgram::Prog(p) => p.emit_infix(source, literals),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use serde_json::{json, Value};
+
+use super::calyx::{self, Assignment, Atom, Cell, Control, Group, Namer};
+use super::image::calyx_memory;
+use super::{index_width, Component, Simulator, Trace, ValidationError};
+
+/// A Calyx program checking a memory against traces, along with the data
+/// file it runs on and the memories it is expected to leave behind
+pub struct Testbench {
+    pub program: calyx::Program,
+    pub data: Value,
+    pub expect: Value,
+}
+
+impl Component {
+    /// a Calyx `main` component holding the memory, which issues the reads of
+    /// each line of the traces, one trace per phase, and records the data each
+    /// port reads in an `out_PORT` memory. The expected data comes from
+    /// simulating the traces on the memory starting out with the bank images.
+    /// Lines are run one after another, so the testbench checks the data
+    /// rather than the timing
+    pub fn calyx_testbench(
+        &self,
+        traces: &[Trace],
+        images: &[Option<Vec<u64>>],
+    ) -> Result<Testbench, ValidationError> {
+        let mut expected = vec![vec![]; self.banks.len()];
+        for (phase, trace) in traces.iter().enumerate() {
+            let schedule = Simulator::stalling(self).in_phase(phase).run(trace)?;
+            for read in schedule.reads {
+                let bank = &self.banks[read.bank];
+                let index = bank.routing(phase).eval(read.address as u64) as usize;
+                let storage = images[self.storage_of(read.bank)].as_deref();
+                let value = storage.and_then(|x| x.get(index)).copied();
+                expected[read.bank].push(value.unwrap_or(0));
+            }
+        }
+
+        let mut main = self.calyx_component();
+        let memory = std::mem::replace(&mut main.control, Control::Empty);
+        main.ports_as_cells();
+        main.name = "main".to_string();
+        let mut names = Namer::default();
+        for name in main.cells.iter().map(|x| &x.name) {
+            names.reserve(name);
+        }
+        for name in main.groups.iter().map(|x| &x.name) {
+            names.reserve(name);
+        }
+
+        let outputs = expected
+            .iter()
+            .enumerate()
+            .map(|(idx, reads)| {
+                if reads.is_empty() {
+                    return None;
+                }
+                let name = names.claim(&format!("out_{}", self.banks[idx].port_name(idx)));
+                let args = vec![self.width, reads.len() as u64, index_width(reads.len())];
+                main.cells
+                    .push(Cell::new(&name, "std_mem_d1", args).external());
+                Some(name)
+            })
+            .collect::<Vec<_>>();
+
+        let write = |cell: &str, value: Atom| {
+            vec![
+                Assignment::new(Atom::cell(cell, "in"), value),
+                Assignment::new(Atom::cell(cell, "write_en"), Atom::constant(1, 1)),
+            ]
+        };
+        let mut recorded = vec![0; self.banks.len()];
+        let mut schedule = vec![];
+        for (phase, trace) in traces.iter().enumerate() {
+            let banks = (0..trace.num_ports())
+                .map(|port| self.bank_for_port(trace, port))
+                .collect::<Result<Vec<_>, _>>()?;
+            // a line's reads of the same storage are issued one after another,
+            // as the memory serves one request per storage at a time
+            let mut rounds: Vec<Vec<(usize, usize)>> = vec![];
+            for line in trace.iter() {
+                let start = rounds.len();
+                for (bank, address) in banks.iter().zip(line.iter()) {
+                    let Some(address) = address else {
+                        continue;
+                    };
+                    let round = rounds[start..]
+                        .iter()
+                        .position(|x| {
+                            x.iter()
+                                .all(|(other, _)| self.storage_of(*other) != self.storage_of(*bank))
+                        })
+                        .map_or(rounds.len(), |x| start + x);
+                    if round == rounds.len() {
+                        rounds.push(vec![]);
+                    }
+                    rounds[round].push((*bank, *address));
+                }
+            }
+            for reads in rounds {
+                let (first, _) = reads[0];
+
+                // drives the memory's inputs with the line's requests
+                let drive = names.fresh("drive");
+                let mut assignments = vec![];
+                if self.phases() > 1 {
+                    assignments.extend(write(
+                        "phase",
+                        Atom::constant(self.phase_width(), phase as u64),
+                    ));
+                }
+                for idx in (0..self.banks.len()).filter(|x| self.is_shared(*x)) {
+                    let reading = reads.iter().any(|(bank, _)| *bank == idx);
+                    let enable = format!("{}_en", self.banks[idx].port_name(idx));
+                    assignments.extend(write(&enable, Atom::constant(1, reading as u64)));
+                }
+                for (bank, address) in reads.iter() {
+                    let port = self.banks[*bank].port_name(*bank);
+                    assignments.extend(write(
+                        &format!("{}_addr", port),
+                        Atom::constant(self.address_width(), *address as u64),
+                    ));
+                }
+                let register = format!("{}_addr", self.banks[first].port_name(first));
+                assignments.push(Assignment::new(
+                    Atom::hole(&drive, "done"),
+                    Atom::cell(&register, "done"),
+                ));
+                main.groups.push(Group {
+                    name: drive.clone(),
+                    assignments,
+                });
+
+                // stores the data each port reads in its output memory
+                let record = names.fresh("record");
+                let mut assignments = vec![];
+                for (bank, _) in reads.iter() {
+                    let output = outputs[*bank].as_ref().unwrap();
+                    let size = expected[*bank].len();
+                    let port = self.banks[*bank].port_name(*bank);
+                    assignments.extend([
+                        Assignment::new(
+                            Atom::cell(output, "addr0"),
+                            Atom::constant(index_width(size), recorded[*bank]),
+                        ),
+                        Assignment::new(
+                            Atom::cell(output, "write_data"),
+                            Atom::cell(&format!("read_{}_addr", port), "out"),
+                        ),
+                        Assignment::new(Atom::cell(output, "write_en"), Atom::constant(1, 1)),
+                    ]);
+                    recorded[*bank] += 1;
+                }
+                assignments.push(Assignment::new(
+                    Atom::hole(&record, "done"),
+                    Atom::cell(outputs[first].as_ref().unwrap(), "done"),
+                ));
+                main.groups.push(Group {
+                    name: record.clone(),
+                    assignments,
+                });
+
+                schedule.push(Control::Enable(drive));
+                schedule.push(memory.clone());
+                schedule.push(Control::Enable(record));
+            }
+        }
+        main.control = Control::seq(schedule);
+
+        let mut data = self.calyx_data(images);
+        let mut expect = images
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, x)| Some((format!("bank_{}", idx), json!(x.as_ref()?))))
+            .collect::<serde_json::Map<_, _>>();
+        for (output, reads) in outputs.iter().zip(expected.iter()) {
+            if let Some(output) = output {
+                data.insert(
+                    output.clone(),
+                    calyx_memory(&vec![0; reads.len()], self.width),
+                );
+                expect.insert(output.clone(), json!(reads));
+            }
+        }
+
+        Ok(Testbench {
+            program: calyx::Program::new(vec![main]),
+            data: Value::Object(data),
+            expect: Value::Object(expect),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::dsl::ast::AstParser;
+    use crate::structures::calyx::{Assignment, Atom, Control};
+    use crate::Trace;
+
+    #[test]
+    fn testbenches_read_each_line_and_expect_the_simulated_data() {
+        let comp = AstParser::parse_description(
+            "memory<32, 8> {
+                bank a { layout: [0:8] translation: INPUT }
+                bank b { layout: [0:8] translation: INPUT storage: 0 }
+            }",
+            &[],
+        )
+        .map(|mut x| x.memories.remove(0))
+        .unwrap_or_else(|e| panic!("{}", e));
+        let trace =
+            Trace::parse_trace(r#"{"size": 8, "bitwidth": 32, "trace": [[1, 5], [7, null]]}"#)
+                .unwrap();
+        let images = comp.bank_images(&(10..18).collect::<Vec<_>>()).unwrap();
+        let bench = comp.calyx_testbench(&[trace], &images).unwrap();
+
+        assert_eq!(
+            bench.expect,
+            json!({
+                "bank_0": [10, 11, 12, 13, 14, 15, 16, 17],
+                "out_a": [11, 17],
+                "out_b": [15],
+            })
+        );
+        assert_eq!(bench.data["out_a"]["data"], json!([0, 0]));
+        assert_eq!(bench.data["bank_0"]["data"], json!(images[0]));
+
+        // the two reads of the first line share the storage, so they are
+        // issued one after another
+        let main = &bench.program.components[0];
+        assert_eq!(main.name, "main");
+        let enables = [
+            "drive0", "record0", "drive1", "record1", "drive2", "record2",
+        ];
+        assert_eq!(
+            main.control,
+            Control::Seq(
+                enables
+                    .iter()
+                    .map(|x| Control::Enable(x.to_string()))
+                    .collect()
+            )
+        );
+        let drive = main.groups.iter().find(|x| x.name == "drive1").unwrap();
+        for (register, value) in [
+            ("a_en", Atom::constant(1, 0)),
+            ("b_en", Atom::constant(1, 1)),
+            ("b_addr", Atom::constant(3, 5)),
+        ] {
+            let assignment = Assignment::new(Atom::cell(register, "in"), value);
+            assert!(drive.assignments.contains(&assignment), "{:?}", drive);
+        }
+        let record = main.groups.iter().find(|x| x.name == "record1").unwrap();
+        assert!(record.assignments.contains(&Assignment::new(
+            Atom::cell("out_b", "write_data"),
+            Atom::cell("read_b_addr", "out")
+        )));
+    }
+}
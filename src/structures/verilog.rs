@@ -0,0 +1,338 @@
+use std::fmt::Write;
+
+use super::infix::Literals;
+use super::{Component, MemoryBank, TopLevelRoutingProgram};
+
+/// a sized decimal literal
+fn literal(width: u64, value: u64) -> String {
+    format!("{}'d{}", width, value)
+}
+
+/// Verilog constants are sized to the address width
+struct VerilogLiterals(u64);
+
+impl Literals for VerilogLiterals {
+    fn constant(&self, value: u64) -> String {
+        literal(self.0, value)
+    }
+
+    fn all_ones(&self) -> String {
+        format!("{{{}{{1'b1}}}}", self.0)
+    }
+}
+
+impl TopLevelRoutingProgram {
+    /// statements assigning the translation of `source` to `target`
+    fn emit_verilog(&self, source: &str, target: &str, width: u64, indent: usize) -> String {
+        let pad = " ".repeat(indent);
+        let literals = VerilogLiterals(width);
+        let mut w = String::new();
+        match self {
+            TopLevelRoutingProgram::Switch(cases, default) => {
+                for (idx, (condition, prog)) in cases.iter().enumerate() {
+                    writeln!(
+                        w,
+                        "{}{}if {} begin",
+                        pad,
+                        if idx == 0 { "" } else { "end else " },
+                        condition.emit_infix(source, &literals)
+                    )
+                    .unwrap();
+                    writeln!(
+                        w,
+                        "{}    {} = {};",
+                        pad,
+                        target,
+                        prog.emit_infix(source, &literals)
+                    )
+                    .unwrap();
+                }
+                writeln!(w, "{}end else begin", pad).unwrap();
+                writeln!(
+                    w,
+                    "{}    {} = {};",
+                    pad,
+                    target,
+                    default.emit_infix(source, &literals)
+                )
+                .unwrap();
+                writeln!(w, "{}end", pad).unwrap();
+            }
+            TopLevelRoutingProgram::Prog(p) => writeln!(
+                w,
+                "{}{} = {};",
+                pad,
+                target,
+                p.emit_infix(source, &literals)
+            )
+            .unwrap(),
+        }
+        w
+    }
+}
+
+impl MemoryBank {
+    /// the combinational logic translating the port's address into its
+    /// index in the bank, selecting the translation of the current phase
+    fn emit_verilog_translation(&self, bank_idx: usize, width: u64, phase_width: u64) -> String {
+        let port = self.port_name(bank_idx);
+        let source = format!("{}_addr", port);
+        let target = format!("{}_index", port);
+        let mut w = String::new();
+        writeln!(w, "    logic [{}:0] {};", width - 1, target).unwrap();
+        writeln!(w, "    always_comb begin").unwrap();
+        if let [routing] = self.routing.as_slice() {
+            write!(w, "{}", routing.emit_verilog(&source, &target, width, 8)).unwrap();
+        } else {
+            writeln!(w, "        case (phase)").unwrap();
+            for (phase, routing) in self.routing.iter().enumerate() {
+                writeln!(
+                    w,
+                    "            {}: begin",
+                    literal(phase_width, phase as u64)
+                )
+                .unwrap();
+                write!(w, "{}", routing.emit_verilog(&source, &target, width, 16)).unwrap();
+                writeln!(w, "            end").unwrap();
+            }
+            writeln!(
+                w,
+                "            default: {} = {{{}{{1'bx}}}};",
+                target, width
+            )
+            .unwrap();
+            writeln!(w, "        endcase").unwrap();
+        }
+        writeln!(w, "    end").unwrap();
+        w
+    }
+}
+
+impl Component {
+    /// emits a SystemVerilog module with an array per bank. Every port can
+    /// read and write its bank at the translated address, ports sharing a
+    /// bank use it while their enable is high, and reads take the port's
+    /// latency in cycles
+    pub fn emit_verilog(&self) -> String {
+        let width = self.width;
+        let addr_width = self.address_width();
+        let phase_width = self.phase_width();
+
+        let mut ports = vec!["input logic clk".to_string()];
+        if self.phases() > 1 {
+            ports.push(format!("input logic [{}:0] phase", phase_width - 1));
+        }
+        for (idx, bank) in self.banks.iter().enumerate() {
+            let port = bank.port_name(idx);
+            if self.is_shared(idx) {
+                ports.push(format!("input logic {}_en", port));
+            }
+            ports.push(format!("input logic [{}:0] {}_addr", addr_width - 1, port));
+            ports.push(format!("input logic {}_write_en", port));
+            ports.push(format!("input logic [{}:0] {}_write_data", width - 1, port));
+            ports.push(format!("output logic [{}:0] {}_read_data", width - 1, port));
+        }
+
+        let mut w = String::new();
+        writeln!(w, "module {} (", self.component_name()).unwrap();
+        writeln!(w, "    {}", ports.join(",\n    ")).unwrap();
+        writeln!(w, ");").unwrap();
+
+        for (idx, bank) in self.banks.iter().enumerate() {
+            writeln!(w).unwrap();
+            write!(
+                w,
+                "{}",
+                bank.emit_verilog_translation(idx, addr_width, phase_width)
+            )
+            .unwrap();
+        }
+
+        for (storage, bank) in self.banks.iter().enumerate() {
+            if bank.storage.is_some() {
+                continue;
+            }
+            let users = (0..self.banks.len())
+                .filter(|x| self.storage_of(*x) == storage)
+                .map(|x| (x, self.banks[x].port_name(x)))
+                .collect::<Vec<_>>();
+            let enable = |port: &str| {
+                if users.len() > 1 {
+                    format!("{}_en && ", port)
+                } else {
+                    String::new()
+                }
+            };
+
+            writeln!(w).unwrap();
+            writeln!(
+                w,
+                "    logic [{}:0] storage_{} [0:{}];",
+                width - 1,
+                storage,
+                bank.size() - 1
+            )
+            .unwrap();
+            // the last port is used whenever no other port is enabled
+            let (_, last) = users.last().unwrap();
+            let address = users
+                .iter()
+                .rev()
+                .skip(1)
+                .fold(format!("{}_index", last), |rest, (_, port)| {
+                    format!("{}_en ? {}_index : {}", port, port, rest)
+                });
+            writeln!(
+                w,
+                "    logic [{}:0] storage_{}_addr;",
+                addr_width - 1,
+                storage
+            )
+            .unwrap();
+            writeln!(w, "    assign storage_{}_addr = {};", storage, address).unwrap();
+
+            writeln!(w, "    always_ff @(posedge clk) begin").unwrap();
+            for (idx, (_, port)) in users.iter().enumerate() {
+                writeln!(
+                    w,
+                    "        {}if ({}{}_write_en) storage_{}[storage_{}_addr] <= {}_write_data;",
+                    if idx == 0 { "" } else { "else " },
+                    enable(port),
+                    port,
+                    storage,
+                    storage,
+                    port
+                )
+                .unwrap();
+            }
+            writeln!(w, "    end").unwrap();
+
+            for (bank_idx, port) in users.iter() {
+                let latency = self.banks[*bank_idx].latency;
+                let read = format!("storage_{}[storage_{}_addr]", storage, storage);
+                if latency == 0 {
+                    writeln!(w, "    assign {}_read_data = {};", port, read).unwrap();
+                    continue;
+                }
+                // registered reads, as inferred for block RAM when the
+                // latency is one cycle
+                for stage in 0..latency {
+                    writeln!(w, "    logic [{}:0] {}_read_{};", width - 1, port, stage).unwrap();
+                }
+                writeln!(w, "    always_ff @(posedge clk) begin").unwrap();
+                writeln!(w, "        {}_read_0 <= {};", port, read).unwrap();
+                for stage in 1..latency {
+                    writeln!(
+                        w,
+                        "        {}_read_{} <= {}_read_{};",
+                        port,
+                        stage,
+                        port,
+                        stage - 1
+                    )
+                    .unwrap();
+                }
+                writeln!(w, "    end").unwrap();
+                writeln!(
+                    w,
+                    "    assign {}_read_data = {}_read_{};",
+                    port,
+                    port,
+                    latency - 1
+                )
+                .unwrap();
+            }
+        }
+        writeln!(w, "endmodule").unwrap();
+        w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dsl::ast::AstParser;
+    use crate::structures::Component;
+
+    fn memory(source: &str) -> Component {
+        AstParser::parse_description(source, &[])
+            .unwrap()
+            .memories
+            .remove(0)
+    }
+
+    #[test]
+    fn banks_get_their_own_storage() {
+        let verilog = memory(
+            "memory mem<32, 16> {
+                bank { layout: cyclic(2, 0) translation: INPUT / 2 }
+                bank { layout: cyclic(2, 1) translation: (INPUT - 1) / 2 }
+            }",
+        )
+        .emit_verilog();
+        assert!(verilog.starts_with("module mem (\n    input logic clk,\n"));
+        assert!(!verilog.contains("phase"));
+        assert!(!verilog.contains("input logic bank_0_en"));
+        for line in [
+            "input logic [3:0] bank_1_addr",
+            "bank_1_index = ((bank_1_addr - 4'd1) / 4'd2);",
+            "logic [31:0] storage_0 [0:7];",
+            "logic [31:0] storage_1 [0:7];",
+            "if (bank_1_write_en) storage_1[storage_1_addr] <= bank_1_write_data;",
+            "assign bank_0_read_data = storage_0[storage_0_addr];",
+        ] {
+            assert!(verilog.contains(line), "{}\n{}", line, verilog);
+        }
+        assert!(verilog.ends_with("endmodule\n"));
+    }
+
+    #[test]
+    fn shared_storage_is_selected_by_enables() {
+        let verilog = memory(
+            "memory mem<32, 16> {
+                bank { layout: [0:16] translation: INPUT latency: 2 }
+                bank { layout: [0:16] translation: INPUT storage: 0 }
+            }",
+        )
+        .emit_verilog();
+        assert!(!verilog.contains("storage_1"));
+        for line in [
+            "input logic bank_0_en,",
+            "input logic bank_1_en,",
+            "assign storage_0_addr = bank_0_en ? bank_0_index : bank_1_index;",
+            "else if (bank_1_en && bank_1_write_en) storage_0[storage_0_addr] <= bank_1_write_data;",
+            // the read is registered once per cycle of latency
+            "bank_0_read_0 <= storage_0[storage_0_addr];",
+            "bank_0_read_1 <= bank_0_read_0;",
+            "assign bank_0_read_data = bank_0_read_1;",
+            "assign bank_1_read_data = storage_0[storage_0_addr];",
+        ] {
+            assert!(verilog.contains(line), "{}\n{}", line, verilog);
+        }
+    }
+
+    #[test]
+    fn phases_select_the_translation() {
+        let verilog = memory(
+            "memory mem<32, 16> {
+                bank {
+                    layout: [0:16]
+                    translation phase 0: INPUT
+                    translation phase 1: switch { INPUT < 8 -> INPUT + 8, -> INPUT - 8 }
+                }
+            }",
+        )
+        .emit_verilog();
+        for line in [
+            "input logic [0:0] phase,",
+            "case (phase)",
+            "1'd0: begin",
+            "bank_0_index = bank_0_addr;",
+            "if (bank_0_addr < 4'd8) begin",
+            "bank_0_index = (bank_0_addr + 4'd8);",
+            "end else begin",
+            "default: bank_0_index = {4{1'bx}};",
+        ] {
+            assert!(verilog.contains(line), "{}\n{}", line, verilog);
+        }
+    }
+}
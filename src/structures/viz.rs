@@ -0,0 +1,563 @@
+use std::fmt::Write;
+
+use super::{
+    Component, MemoryBank, SequenceRoutingProg, TerminalRoutingProgram, TopLevelRoutingProgram,
+    Trace, ValidationError,
+};
+
+/// the fill of each bank, repeating for memories with more banks
+const PALETTE: [&str; 10] = [
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7",
+    "#9c755f", "#bab0ac",
+];
+const MARGIN: usize = 16;
+const COLUMNS: usize = 16;
+const CELL_WIDTH: usize = 40;
+const CELL_HEIGHT: usize = 24;
+const LINE_HEIGHT: usize = 18;
+/// the columns of the trace tables, wide enough for `address → index`
+const TRACE_WIDTH: usize = 72;
+const CHAR_WIDTH: usize = 8;
+
+fn color(bank_idx: usize) -> &'static str {
+    PALETTE[bank_idx % PALETTE.len()]
+}
+
+/// `text` with the characters XML reserves escaped
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// `text` as a quoted DOT string
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// An SVG drawing growing downwards
+struct Canvas {
+    body: String,
+    width: usize,
+    height: usize,
+}
+
+impl Canvas {
+    fn text(&mut self, x: usize, y: usize, text: &str, attributes: &str) {
+        self.width = self.width.max(x + text.chars().count() * CHAR_WIDTH);
+        writeln!(
+            self.body,
+            "  <text x=\"{}\" y=\"{}\"{}>{}</text>",
+            x,
+            y,
+            attributes,
+            escape_xml(text)
+        )
+        .unwrap();
+    }
+
+    fn rect(&mut self, x: usize, y: usize, width: usize, height: usize, attributes: &str) {
+        self.width = self.width.max(x + width);
+        writeln!(
+            self.body,
+            "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"{}/>",
+            x, y, width, height, attributes
+        )
+        .unwrap();
+    }
+
+    /// a cell of a grid, split into a stripe per fill and labelled in the
+    /// middle, with a tooltip
+    fn cell(
+        &mut self,
+        (x, y): (usize, usize),
+        width: usize,
+        fills: &[&str],
+        label: &str,
+        tooltip: &str,
+        outline: &str,
+    ) {
+        writeln!(self.body, "  <g><title>{}</title>", escape_xml(tooltip)).unwrap();
+        match fills {
+            [] => self.rect(x, y, width, CELL_HEIGHT, " fill=\"#ffffff\""),
+            _ => {
+                let stripe = width / fills.len();
+                for (idx, fill) in fills.iter().enumerate() {
+                    let last = idx + 1 == fills.len();
+                    let stripe_width = if last { width - stripe * idx } else { stripe };
+                    self.rect(
+                        x + stripe * idx,
+                        y,
+                        stripe_width,
+                        CELL_HEIGHT,
+                        &format!(" fill=\"{}\"", fill),
+                    );
+                }
+            }
+        }
+        self.rect(
+            x,
+            y,
+            width,
+            CELL_HEIGHT,
+            &format!(" fill=\"none\" {}", outline),
+        );
+        self.text(
+            x + width / 2,
+            y + CELL_HEIGHT / 2 + 4,
+            label,
+            " text-anchor=\"middle\"",
+        );
+        writeln!(self.body, "  </g>").unwrap();
+    }
+}
+
+impl Component {
+    /// an SVG drawing of each memory's logical addresses colored by the banks
+    /// holding them. With traces, which are only drawn for a single memory,
+    /// a table per phase shows the bank and index each port's reads hit
+    pub fn emit_svg_document(
+        components: &[Component],
+        traces: &[Trace],
+    ) -> Result<String, ValidationError> {
+        let mut canvas = Canvas {
+            body: String::new(),
+            width: 0,
+            height: MARGIN,
+        };
+        for component in components {
+            component.draw_layout(&mut canvas);
+            for (phase, trace) in traces.iter().enumerate() {
+                component.draw_trace(&mut canvas, trace, phase, traces.len() > 1)?;
+            }
+        }
+
+        let mut w = String::new();
+        writeln!(
+            w,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             font-family=\"monospace\" font-size=\"12\">",
+            canvas.width + MARGIN,
+            canvas.height
+        )
+        .unwrap();
+        writeln!(
+            w,
+            "  <rect width=\"100%\" height=\"100%\" fill=\"#ffffff\"/>"
+        )
+        .unwrap();
+        write!(w, "{}", canvas.body).unwrap();
+        writeln!(w, "</svg>").unwrap();
+        Ok(w)
+    }
+
+    /// the grid of addresses, followed by a legend of the banks
+    fn draw_layout(&self, canvas: &mut Canvas) {
+        let top = canvas.height;
+        canvas.text(
+            MARGIN,
+            top + 14,
+            &format!(
+                "{}: {} x {} bits in {} banks",
+                self.component_name(),
+                self.size,
+                self.width,
+                self.banks.len()
+            ),
+            " font-weight=\"bold\"",
+        );
+
+        let grid = top + 24;
+        for address in 0..self.size as usize {
+            let holders = (0..self.banks.len())
+                .filter(|x| self.banks[*x].layout()._contains(&address))
+                .collect::<Vec<_>>();
+            let fills = holders.iter().map(|x| color(*x)).collect::<Vec<_>>();
+            let names = holders
+                .iter()
+                .map(|x| format!("bank {}", self.banks[*x].port_name(*x)))
+                .collect::<Vec<_>>();
+            let tooltip = match names.is_empty() {
+                true => format!("address {}: held by no bank", address),
+                false => format!("address {}: {}", address, names.join(", ")),
+            };
+            canvas.cell(
+                (
+                    MARGIN + (address % COLUMNS) * CELL_WIDTH,
+                    grid + (address / COLUMNS) * CELL_HEIGHT,
+                ),
+                CELL_WIDTH,
+                &fills,
+                &address.to_string(),
+                &tooltip,
+                "stroke=\"#333333\"",
+            );
+        }
+        let rows = (self.size as usize).div_ceil(COLUMNS);
+
+        let legend = grid + rows * CELL_HEIGHT + 8;
+        for (idx, bank) in self.banks.iter().enumerate() {
+            let y = legend + idx * LINE_HEIGHT;
+            canvas.rect(
+                MARGIN,
+                y,
+                12,
+                12,
+                &format!(" fill=\"{}\" stroke=\"#333333\"", color(idx)),
+            );
+            let mut description = format!(
+                "bank {}: {}",
+                bank.port_name(idx),
+                bank.layout().pretty_print()
+            );
+            if bank.latency > 0 {
+                write!(description, ", latency {}", bank.latency).unwrap();
+            }
+            if let Some(storage) = bank.storage {
+                write!(
+                    description,
+                    ", storage of bank {}",
+                    self.banks[storage].port_name(storage)
+                )
+                .unwrap();
+            }
+            canvas.text(MARGIN + 20, y + 10, &description, "");
+        }
+        canvas.height = legend + self.banks.len() * LINE_HEIGHT + MARGIN;
+    }
+
+    /// a row per line of the trace with a cell per port, colored by the bank
+    /// the port reads and labelled with the index it reads there. Reads the
+    /// bank cannot serve are crossed out, and reads contending for the same
+    /// storage in a line are outlined in red
+    fn draw_trace(
+        &self,
+        canvas: &mut Canvas,
+        trace: &Trace,
+        phase: usize,
+        phased: bool,
+    ) -> Result<(), ValidationError> {
+        let banks = (0..trace.num_ports())
+            .map(|port| self.bank_for_port(trace, port))
+            .collect::<Result<Vec<_>, _>>()?;
+        let top = canvas.height;
+        let title = match phased {
+            true => format!("trace of phase {}", phase),
+            false => "trace".to_string(),
+        };
+        canvas.text(MARGIN, top + 14, &title, " font-weight=\"bold\"");
+
+        let labels = trace
+            .iter_cycles()
+            .map(|(cycle, _)| format!("cycle {}", cycle))
+            .collect::<Vec<_>>();
+        let label_width = labels.iter().map(|x| x.len()).max().unwrap_or(0) * CHAR_WIDTH + 8;
+        let header = top + 24;
+        for (column, bank) in banks.iter().enumerate() {
+            canvas.text(
+                MARGIN + label_width + column * TRACE_WIDTH + TRACE_WIDTH / 2,
+                header + 14,
+                &self.banks[*bank].port_name(*bank),
+                " text-anchor=\"middle\"",
+            );
+        }
+
+        for (row, ((_, line), label)) in trace.iter_cycles().zip(labels.iter()).enumerate() {
+            let y = header + (row + 1) * CELL_HEIGHT;
+            canvas.text(MARGIN, y + CELL_HEIGHT / 2 + 4, label, "");
+            for (column, (bank, address)) in banks.iter().zip(line.iter()).enumerate() {
+                let Some(address) = address else {
+                    continue;
+                };
+                let port = self.banks[*bank].port_name(*bank);
+                let contended = banks.iter().zip(line.iter()).enumerate().any(|(other, x)| {
+                    other != column
+                        && x.1.is_some()
+                        && self.storage_of(*x.0) == self.storage_of(*bank)
+                });
+                let outline = match contended {
+                    true => "stroke=\"#d62728\" stroke-width=\"2\"",
+                    false => "stroke=\"#333333\"",
+                };
+                let x = MARGIN + label_width + column * TRACE_WIDTH;
+                let translated = self.banks[*bank].translated_index(*address, phase);
+                match translated {
+                    Some(index) => canvas.cell(
+                        (x, y),
+                        TRACE_WIDTH,
+                        &[color(*bank)],
+                        &format!("{} → {}", address, index),
+                        &format!("port {} reads address {} at index {}", port, address, index),
+                        outline,
+                    ),
+                    None => canvas.cell(
+                        (x, y),
+                        TRACE_WIDTH,
+                        &[],
+                        &format!("{} ✗", address),
+                        &format!("port {} cannot read address {}", port, address),
+                        outline,
+                    ),
+                }
+            }
+        }
+        canvas.height = header + (labels.len() + 1) * CELL_HEIGHT + MARGIN;
+        Ok(())
+    }
+
+    /// a Graphviz graph with a cluster per memory, showing how each port's
+    /// address flows through the steps of its translation into the storage
+    pub fn emit_dot_graph(components: &[Component]) -> String {
+        let mut graph = Graph::default();
+        writeln!(graph.out, "digraph memories {{").unwrap();
+        writeln!(graph.out, "    rankdir=LR;").unwrap();
+        writeln!(graph.out, "    node [fontname=\"monospace\", shape=box];").unwrap();
+        writeln!(graph.out, "    edge [fontname=\"monospace\"];").unwrap();
+        for component in components {
+            component.emit_dot(&mut graph);
+        }
+        writeln!(graph.out, "}}").unwrap();
+        graph.out
+    }
+
+    fn emit_dot(&self, graph: &mut Graph) {
+        graph.open(&self.component_name());
+        let storages = (0..self.banks.len())
+            .map(|idx| match self.banks[idx].storage {
+                Some(_) => None,
+                None => Some(graph.node(
+                    &format!(
+                        "bank_{}\\n{} x {} bits",
+                        idx,
+                        self.banks[idx].size(),
+                        self.width
+                    ),
+                    &format!(
+                        "shape=cylinder, style=filled, fillcolor={}",
+                        quote(color(idx))
+                    ),
+                )),
+            })
+            .collect::<Vec<_>>();
+        for (idx, bank) in self.banks.iter().enumerate() {
+            let port = bank.port_name(idx);
+            let title = match bank.latency {
+                0 => format!("bank {}", port),
+                latency => format!("bank {} (latency {})", port, latency),
+            };
+            graph.open(&title);
+            let source = graph.node(&format!("{}_addr", port), "shape=ellipse");
+            let mut index = bank.emit_dot(graph, &source, 0);
+            if bank.routing.len() > 1 {
+                let phases = graph.node("phase", "shape=invtrapezium");
+                graph.edge(&index, &phases, "label=\"phase 0\"");
+                for phase in 1..bank.routing.len() {
+                    let translated = bank.emit_dot(graph, &source, phase);
+                    graph.edge(&translated, &phases, &format!("label=\"phase {}\"", phase));
+                }
+                index = phases;
+            }
+            graph.close();
+            let storage = storages[self.storage_of(idx)].as_ref().unwrap();
+            graph.edge(&index, storage, "label=\"index\"");
+        }
+        graph.close();
+    }
+}
+
+impl MemoryBank {
+    /// the index the bank reads the address at in the phase, if it can
+    fn translated_index(&self, address: usize, phase: usize) -> Option<u64> {
+        if !self.can_read(address, phase) {
+            return None;
+        }
+        self.routing(phase).checked_eval(address as u64)
+    }
+
+    /// the nodes translating `source` in the phase, returning the node
+    /// holding the index
+    fn emit_dot(&self, graph: &mut Graph, source: &str, phase: usize) -> String {
+        match self.routing(phase) {
+            TopLevelRoutingProgram::Prog(prog) => prog.emit_dot(graph, source),
+            TopLevelRoutingProgram::Switch(cases, default) => {
+                let select = graph.node("switch", "shape=invtrapezium");
+                for (case, (condition, prog)) in cases.iter().enumerate() {
+                    let test = graph.node(&condition.pretty_print(), "shape=diamond");
+                    graph.edge(source, &test, "");
+                    graph.edge(&test, &select, "style=dashed");
+                    let translated = prog.emit_dot(graph, source);
+                    graph.edge(&translated, &select, &format!("label=\"case {}\"", case));
+                }
+                let translated = default.emit_dot(graph, source);
+                graph.edge(&translated, &select, "label=\"else\"");
+                select
+            }
+        }
+    }
+}
+
+impl SequenceRoutingProg {
+    /// a node per step, chained from `source`
+    fn emit_dot(&self, graph: &mut Graph, source: &str) -> String {
+        let steps = match self {
+            SequenceRoutingProg::Sequence(steps) => steps.as_slice(),
+            SequenceRoutingProg::Prog(step) => std::slice::from_ref(step),
+        };
+        steps
+            .iter()
+            .filter(|x| **x != TerminalRoutingProgram::Noop)
+            .fold(source.to_string(), |source, step| {
+                let node = graph.node(&step.pretty_print(), "");
+                graph.edge(&source, &node, "");
+                node
+            })
+    }
+}
+
+/// A Graphviz graph written as it is built, with nodes and clusters given
+/// fresh identifiers
+#[derive(Default)]
+struct Graph {
+    out: String,
+    ids: usize,
+    depth: usize,
+}
+
+impl Graph {
+    fn indent(&self) -> String {
+        "    ".repeat(self.depth + 1)
+    }
+
+    fn fresh(&mut self) -> usize {
+        self.ids += 1;
+        self.ids - 1
+    }
+
+    fn node(&mut self, label: &str, attributes: &str) -> String {
+        let id = format!("n{}", self.fresh());
+        let separator = if attributes.is_empty() { "" } else { ", " };
+        // labels keep the line breaks they were given
+        let label = format!("\"{}\"", label.replace('"', "\\\""));
+        writeln!(
+            self.out,
+            "{}{} [label={}{}{}];",
+            self.indent(),
+            id,
+            label,
+            separator,
+            attributes
+        )
+        .unwrap();
+        id
+    }
+
+    fn edge(&mut self, from: &str, to: &str, attributes: &str) {
+        let attributes = match attributes {
+            "" => String::new(),
+            x => format!(" [{}]", x),
+        };
+        writeln!(
+            self.out,
+            "{}{} -> {}{};",
+            self.indent(),
+            from,
+            to,
+            attributes
+        )
+        .unwrap();
+    }
+
+    fn open(&mut self, label: &str) {
+        let id = self.fresh();
+        writeln!(self.out, "{}subgraph cluster_{} {{", self.indent(), id).unwrap();
+        self.depth += 1;
+        writeln!(self.out, "{}label={};", self.indent(), quote(label)).unwrap();
+    }
+
+    fn close(&mut self) {
+        self.depth -= 1;
+        writeln!(self.out, "{}}}", self.indent()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dsl::ast::AstParser;
+    use crate::structures::Component;
+    use crate::Trace;
+
+    fn memory(source: &str) -> Component {
+        AstParser::parse_description(source, &[])
+            .unwrap()
+            .memories
+            .remove(0)
+    }
+
+    fn phased() -> Component {
+        memory(
+            "memory mem<32, 4> {
+                bank { layout: {0, 1} translation: INPUT latency: 1 }
+                bank { layout: {0, 1} translation: INPUT storage: 0 }
+                bank {
+                    layout: {2}
+                    translation phase 0: INPUT - 2
+                    translation phase 1: switch { INPUT < 3 -> INPUT - 2, -> 0 }
+                }
+            }",
+        )
+    }
+
+    #[test]
+    fn svg_documents_draw_the_layouts_and_traces() {
+        let trace = Trace::parse_trace(
+            r#"{"size": 4, "bitwidth": 32, "trace": [[0, null, 2], [1, 1, 3]]}"#,
+        )
+        .unwrap();
+        let svg = Component::emit_svg_document(&[phased()], &[trace.clone(), trace]).unwrap();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        for line in [
+            "mem: 4 x 32 bits in 3 banks",
+            "<title>address 0: bank bank_0, bank bank_1</title>",
+            "<title>address 3: held by no bank</title>",
+            "bank bank_1: {0, 1}, storage of bank bank_0",
+            "trace of phase 0",
+            "trace of phase 1",
+            "<title>port bank_0 reads address 0 at index 0</title>",
+            "<title>port bank_2 cannot read address 3</title>",
+            "3 ✗",
+        ] {
+            assert!(svg.contains(line), "{}\n{}", line, svg);
+        }
+        // the ports sharing a bank are outlined when they use it together
+        assert!(svg.contains("stroke=\"#d62728\" stroke-width=\"2\""));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+
+    #[test]
+    fn svg_documents_need_the_traced_ports() {
+        let trace =
+            Trace::parse_trace(r#"{"size": 4, "bitwidth": 32, "trace": [[0, 1, 2, 3]]}"#).unwrap();
+        assert!(Component::emit_svg_document(&[phased()], &[trace]).is_err());
+    }
+
+    #[test]
+    fn dot_graphs_draw_the_storage_and_routing() {
+        let dot = Component::emit_dot_graph(&[phased()]);
+        assert!(dot.starts_with("digraph memories {"));
+        for line in [
+            "label=\"mem\";",
+            "[label=\"bank_0\\n2 x 32 bits\", shape=cylinder",
+            "label=\"bank bank_0 (latency 1)\";",
+            "[label=\"phase\", shape=invtrapezium]",
+            "[label=\"switch\", shape=invtrapezium]",
+            "[label=\"INPUT < 3\", shape=diamond]",
+            "[label=\"case 0\"]",
+            "[label=\"else\"]",
+            "[label=\"phase 1\"]",
+        ] {
+            assert!(dot.contains(line), "{}\n{}", line, dot);
+        }
+        // banks using another bank's storage draw no array of their own
+        assert!(!dot.contains("label=\"bank_1\\n"));
+    }
+}
@@ -0,0 +1,321 @@
+use super::calyx::{self, Assignment, Atom, Cell, Control, Group, Guard, Namer, Port};
+use super::{ComparisonOperator, Component};
+
+/// a guard holding when `source` is one of the addresses, written as
+/// comparisons against runs of consecutive addresses, or `None` when there
+/// are no addresses
+fn address_guard(addresses: &[usize], source: &Atom, width: u64) -> Option<Guard> {
+    let mut runs: Vec<(usize, usize)> = vec![];
+    for address in addresses {
+        match runs.last_mut() {
+            Some((_, last)) if *last + 1 == *address => *last = *address,
+            _ => runs.push((*address, *address)),
+        }
+    }
+    let compare =
+        |op, value: usize| Guard::Compare(source.clone(), op, Atom::constant(width, value as u64));
+    runs.into_iter()
+        .map(|(first, last)| {
+            if first == last {
+                compare(ComparisonOperator::Equal, first)
+            } else if first == 0 {
+                compare(ComparisonOperator::LessThanOrEqual, last)
+            } else {
+                compare(ComparisonOperator::GreaterThanOrEqual, first)
+                    .and(compare(ComparisonOperator::LessThanOrEqual, last))
+            }
+        })
+        .reduce(Guard::or)
+}
+
+impl Component {
+    /// the bank each logical port routes each address to in the phase: its
+    /// own bank when that can read the address, otherwise the first bank
+    /// that can
+    fn targets(&self, port: usize, phase: usize) -> Vec<Option<usize>> {
+        let candidates = std::iter::once(port)
+            .chain((0..self.banks.len()).filter(|x| *x != port))
+            .collect::<Vec<_>>();
+        (0..self.size as usize)
+            .map(|address| {
+                candidates
+                    .iter()
+                    .copied()
+                    .find(|x| self.banks[*x].can_read(address, phase))
+            })
+            .collect()
+    }
+
+    /// the Calyx component presenting the memory as a `std_mem_d1` style
+    /// port per logical port. Each address is routed to a bank holding it,
+    /// and ports contend for the storage behind that bank: ports whose own
+    /// bank uses the storage come first, then the rest in order. A port
+    /// stalls when it loses, or when no bank can read its address
+    pub fn calyx_wrapper(&self) -> calyx::Component {
+        let mut names = Namer::default();
+        let name = self.component_name();
+        let mut component = calyx::Component::new(&format!("{}_logical", name));
+        let addr_width = self.address_width();
+        let ports = self.banks.len();
+
+        if self.phases() > 1 {
+            component
+                .inputs
+                .push(Port::new("phase", self.phase_width()));
+        }
+        for port in 0..ports {
+            let port = self.banks[port].port_name(port);
+            component
+                .inputs
+                .push(Port::new(&format!("{}_addr0", port), addr_width));
+            component
+                .outputs
+                .push(Port::new(&format!("{}_read_data", port), self.width));
+            component
+                .outputs
+                .push(Port::new(&format!("{}_stall", port), 1));
+        }
+        for port in component.inputs.iter().chain(component.outputs.iter()) {
+            names.reserve(&port.name);
+        }
+        let memory = names.fresh("mem");
+        component.cells.push(Cell::new(&memory, &name, vec![]));
+        if self.phases() > 1 {
+            component.wires.push(Assignment::new(
+                Atom::cell(&memory, "phase"),
+                Atom::this("phase"),
+            ));
+        }
+
+        let phases = self.phases();
+        let targets = (0..ports)
+            .map(|x| (0..phases).map(|phase| self.targets(x, phase)).collect())
+            .collect::<Vec<Vec<_>>>();
+        let sources = (0..ports)
+            .map(|x| Atom::this(&format!("{}_addr0", self.banks[x].port_name(x))))
+            .collect::<Vec<_>>();
+        // the guard under which a port routes to a bank satisfying `filter`
+        let routes = |port: usize, filter: &dyn Fn(usize) -> bool| {
+            targets[port]
+                .iter()
+                .enumerate()
+                .filter_map(|(phase, targets)| {
+                    let addresses = targets
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, x)| x.is_some_and(filter))
+                        .map(|(address, _)| address)
+                        .collect::<Vec<_>>();
+                    let guard = address_guard(&addresses, &sources[port], addr_width)?;
+                    if phases == 1 {
+                        return Some(guard);
+                    }
+                    Some(
+                        Guard::Compare(
+                            Atom::this("phase"),
+                            ComparisonOperator::Equal,
+                            Atom::constant(self.phase_width(), phase as u64),
+                        )
+                        .and(guard),
+                    )
+                })
+                .reduce(Guard::or)
+        };
+
+        let mut granted: Vec<Option<Guard>> = vec![None; ports];
+        for (storage, bank) in self.banks.iter().enumerate() {
+            if bank.storage.is_some() {
+                continue;
+            }
+            let on_storage = |x: usize| self.storage_of(x) == storage;
+            let mut priority = (0..ports).filter(|x| on_storage(*x)).collect::<Vec<_>>();
+            priority.extend((0..ports).filter(|x| !on_storage(*x)));
+
+            // the ports with a higher priority are not using the storage
+            let mut clear = Guard::True;
+            for port in priority {
+                let Some(uses) = routes(port, &on_storage) else {
+                    continue;
+                };
+                for target in (0..ports).filter(|x| on_storage(*x)) {
+                    let Some(route) = routes(port, &|x| x == target) else {
+                        continue;
+                    };
+                    let guard = route.and(clear.clone());
+                    let bank_port = self.banks[target].port_name(target);
+                    component.wires.push(Assignment::guarded(
+                        Atom::cell(&memory, &format!("{}_addr", bank_port)),
+                        guard.clone(),
+                        sources[port].clone(),
+                    ));
+                    if self.is_shared(target) {
+                        component.wires.push(Assignment::guarded(
+                            Atom::cell(&memory, &format!("{}_en", bank_port)),
+                            guard,
+                            Atom::constant(1, 1),
+                        ));
+                    }
+                }
+                let wins = uses.clone().and(clear.clone());
+                granted[port] = Some(match granted[port].take() {
+                    Some(other) => other.or(wins),
+                    None => wins,
+                });
+                clear = clear.and(uses.negate());
+            }
+        }
+
+        for (port, granted) in granted.into_iter().enumerate() {
+            let name = self.banks[port].port_name(port);
+            for target in 0..ports {
+                if let Some(route) = routes(port, &|x| x == target) {
+                    let bank_port = self.banks[target].port_name(target);
+                    component.wires.push(Assignment::guarded(
+                        Atom::this(&format!("{}_read_data", name)),
+                        route,
+                        Atom::cell(&memory, &format!("read_{}_addr", bank_port)),
+                    ));
+                }
+            }
+            let stall = Atom::this(&format!("{}_stall", name));
+            component.wires.push(match granted {
+                Some(granted) => Assignment::guarded(stall, granted.negate(), Atom::constant(1, 1)),
+                None => Assignment::new(stall, Atom::constant(1, 1)),
+            });
+        }
+
+        // memories with sequential banks are run through their go/done
+        // interface
+        if self.banks.iter().any(|x| x.latency > 0) {
+            let group = names.fresh("run");
+            component.groups.push(Group {
+                name: group.clone(),
+                assignments: vec![
+                    Assignment::new(Atom::cell(&memory, "go"), Atom::constant(1, 1)),
+                    Assignment::new(Atom::hole(&group, "done"), Atom::cell(&memory, "done")),
+                ],
+            });
+            component.control = Control::Enable(group);
+        }
+        component
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::address_guard;
+    use crate::dsl::ast::AstParser;
+    use crate::structures::calyx::{Atom, Control, Guard};
+    use crate::structures::{ComparisonOperator, Component};
+
+    fn memory(source: &str) -> Component {
+        AstParser::parse_description(source, &[])
+            .map(|mut x| x.memories.remove(0))
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// whether the guard holds when the wrapper's ports carry the values
+    fn holds(guard: &Guard, inputs: &[(&str, u64)]) -> bool {
+        let value = |atom: &Atom| match atom {
+            Atom::This(port) => inputs.iter().find(|x| x.0 == port).unwrap().1,
+            Atom::Constant { value, .. } => *value,
+            other => panic!("unexpected {}", other),
+        };
+        match guard {
+            Guard::True => true,
+            Guard::Port(atom) => value(atom) != 0,
+            Guard::Compare(left, op, right) => op.eval(&value(left), &value(right)),
+            Guard::And(left, right) => holds(left, inputs) && holds(right, inputs),
+            Guard::Or(left, right) => holds(left, inputs) || holds(right, inputs),
+            Guard::Not(guard) => !holds(guard, inputs),
+        }
+    }
+
+    /// the values driving the destination while the ports carry the inputs
+    fn drivers(comp: &Component, inputs: &[(&str, u64)], dst: Atom) -> Vec<Atom> {
+        comp.calyx_wrapper()
+            .wires
+            .iter()
+            .filter(|x| x.dst == dst && holds(&x.guard, inputs))
+            .map(|x| x.src.clone())
+            .collect()
+    }
+
+    #[test]
+    fn addresses_are_guarded_by_runs() {
+        let addr = Atom::this("a");
+        let guard = address_guard(&[0, 1, 2, 5, 7, 8], &addr, 4).unwrap();
+        assert_eq!(
+            guard.to_string(),
+            "((a <= 4'd2) | (a == 4'd5) | ((a >= 4'd7) & (a <= 4'd8)))"
+        );
+        for address in 0..16 {
+            let expected = [0, 1, 2, 5, 7, 8].contains(&address);
+            assert_eq!(holds(&guard, &[("a", address)]), expected);
+        }
+        assert_eq!(address_guard(&[], &addr, 4), None);
+        assert_eq!(
+            address_guard(&[3], &addr, 4),
+            Some(Guard::Compare(
+                addr,
+                ComparisonOperator::Equal,
+                Atom::constant(4, 3)
+            ))
+        );
+    }
+
+    #[test]
+    fn ports_read_through_any_bank_and_stall_on_contention() {
+        let comp = memory(
+            "memory<32, 8> {
+                bank lo { layout: [0:4] translation: INPUT }
+                bank hi { layout: [4:7] translation: INPUT - 4 }
+            }",
+        );
+        let wrapper = comp.calyx_wrapper();
+        assert_eq!(wrapper.name, "mem_8_2_logical");
+        assert_eq!(wrapper.control, Control::Empty);
+
+        let stalls = |lo: u64, hi: u64| {
+            let inputs = [("lo_addr0", lo), ("hi_addr0", hi)];
+            let stalled = |port: &str| {
+                !drivers(&comp, &inputs, Atom::this(&format!("{}_stall", port))).is_empty()
+            };
+            (stalled("lo"), stalled("hi"))
+        };
+        // each port reads its own bank
+        assert_eq!(stalls(1, 5), (false, false));
+        // or the other one, when that holds its address
+        assert_eq!(stalls(5, 2), (false, false));
+        let inputs = [("lo_addr0", 5), ("hi_addr0", 2)];
+        assert_eq!(
+            drivers(&comp, &inputs, Atom::cell("mem0", "hi_addr")),
+            vec![Atom::this("lo_addr0")]
+        );
+        assert_eq!(
+            drivers(&comp, &inputs, Atom::this("lo_read_data")),
+            vec![Atom::cell("mem0", "read_hi_addr")]
+        );
+        // the port owning the bank wins it
+        assert_eq!(stalls(5, 6), (true, false));
+        assert_eq!(stalls(0, 3), (false, true));
+        // no bank holds address 7
+        assert_eq!(stalls(7, 0), (true, false));
+        assert_eq!(stalls(0, 7), (false, true));
+    }
+
+    #[test]
+    fn sequential_memories_run_through_go_and_done() {
+        let comp = memory(
+            "memory<32, 8> {
+                bank { layout: [0:8] translation: INPUT latency: 1 }
+            }",
+        );
+        let wrapper = comp.calyx_wrapper();
+        assert_eq!(wrapper.control, Control::Enable("run0".to_string()));
+        assert_eq!(
+            wrapper.groups[0].assignments[1].src,
+            Atom::cell("mem0", "done")
+        );
+    }
+}